@@ -0,0 +1,66 @@
+//! Talk to Consul over HTTP/2 with prior knowledge, multiplexing every
+//! request over a single connection.
+//!
+//! The buffer hands requests to the service one at a time but does not
+//! wait for a response before sending the next, so concurrent requests
+//! share the connection rather than queueing behind each other. The
+//! hyper client must be shared between requests for this to work, a new
+//! client per request would open a new connection every time.
+//!
+//! Consul only speaks HTTP/2 over TLS, so without TLS this is meant for
+//! a proxy in front of Consul that accepts cleartext HTTP/2.
+
+use bytes::Bytes;
+use futures::{future, Future, Stream};
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Request, Response};
+use tower_consul::Consul;
+use tower_util::service_fn;
+
+static CONSUL_ADDRESS: &str = "127.0.0.1:8500";
+
+fn main() {
+    hyper::rt::run(future::lazy(get_services))
+}
+
+fn get_services() -> impl Future<Item = (), Error = ()> {
+    let client = Client::builder().http2_only(true).build_http();
+    let hyper = service_fn(move |req| send(&client, req));
+
+    let mut consul = match Consul::new(hyper, 100, "http".into(), CONSUL_ADDRESS.into()) {
+        Ok(c) => c,
+        Err(_) => panic!("Unable to spawn!"),
+    };
+
+    let requests = (0..10)
+        .map(|i| {
+            consul
+                .get_keys(&format!("my-prefix/{}", i))
+                .then(Ok::<_, ()>)
+        })
+        .collect::<Vec<_>>();
+
+    future::join_all(requests).map(|responses| {
+        for response in responses {
+            println!("keys: {:?}", response);
+        }
+    })
+}
+
+fn send(
+    client: &Client<HttpConnector, Body>,
+    req: Request<Bytes>,
+) -> impl Future<Item = Response<Bytes>, Error = hyper::Error> {
+    client
+        .request(req.map(Body::from))
+        .and_then(|res| {
+            let status = res.status();
+            res.into_body().concat2().join(Ok(status))
+        })
+        .and_then(|(body, status)| {
+            Ok(Response::builder()
+                .status(status)
+                .body(body.into())
+                .unwrap())
+        })
+}
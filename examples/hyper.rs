@@ -4,10 +4,10 @@ use hyper::{Body, Client, Request, Response};
 use tower_consul::Consul;
 use tower_util::service_fn;
 
-static CONSUL_ADDRESS: &'static str = "127.0.0.1:8500";
+static CONSUL_ADDRESS: &str = "127.0.0.1:8500";
 
 fn main() {
-    hyper::rt::run(future::lazy(|| get_services()))
+    hyper::rt::run(future::lazy(get_services))
 }
 
 fn get_services() -> impl Future<Item = (), Error = ()> {
@@ -33,7 +33,7 @@ fn hyper(req: Request<Bytes>) -> impl Future<Item = Response<Bytes>, Error = hyp
     client
         .request(req.map(Body::from))
         .and_then(|res| {
-            let status = res.status().clone();
+            let status = res.status();
             res.into_body().concat2().join(Ok(status))
         })
         .and_then(|(body, status)| {
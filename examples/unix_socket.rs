@@ -0,0 +1,66 @@
+use bytes::Bytes;
+use futures::{future, Future, Stream};
+use hyper::client::connect::{Connect, Connected, Destination};
+use hyper::{Body, Client, Request, Response};
+use std::io;
+use std::path::PathBuf;
+use tokio::net::UnixStream;
+use tower_consul::Consul;
+use tower_util::service_fn;
+
+/// The socket set with `addresses { http = "unix:///var/run/consul/http.sock" }`
+static CONSUL_SOCKET: &str = "/var/run/consul/http.sock";
+
+/// A connector that connects every request to the socket, whatever the
+/// host of its URI.
+#[derive(Clone)]
+struct UnixConnector(PathBuf);
+
+impl Connect for UnixConnector {
+    type Transport = UnixStream;
+    type Error = io::Error;
+    type Future = Box<dyn Future<Item = (UnixStream, Connected), Error = io::Error> + Send>;
+
+    fn connect(&self, _: Destination) -> Self::Future {
+        let fut = UnixStream::connect(&self.0).map(|stream| (stream, Connected::new()));
+        Box::new(fut)
+    }
+}
+
+fn main() {
+    hyper::rt::run(future::lazy(get_services))
+}
+
+fn get_services() -> impl Future<Item = (), Error = ()> {
+    let client = Client::builder().build::<_, Body>(UnixConnector(CONSUL_SOCKET.into()));
+
+    let hyper = service_fn(move |req: Request<Bytes>| {
+        client
+            .request(req.map(Body::from))
+            .and_then(|res| {
+                let status = res.status();
+                res.into_body().concat2().join(Ok(status))
+            })
+            .and_then(|(body, status)| {
+                Ok(Response::builder()
+                    .status(status)
+                    .body(Bytes::from(body))
+                    .unwrap())
+            })
+    });
+
+    // The connector ignores the host, `localhost` is only a placeholder
+    // for the authority of the request URIs.
+    let mut consul = match Consul::new(hyper, 100, "http".into(), "localhost".into()) {
+        Ok(c) => c,
+        Err(_) => panic!("Unable to spawn!"),
+    };
+
+    consul
+        .get("my-key")
+        .and_then(|value| {
+            println!("value: {:?}", value);
+            Ok(())
+        })
+        .map_err(|e| panic!("{:?}", e))
+}
@@ -0,0 +1,94 @@
+//! Dropping the requests that are still queued in the buffer
+
+use crate::BoxError;
+use bytes::Bytes;
+use futures::future::{self, Either, FutureResult, MapErr};
+use futures::{Future, Poll};
+use http::Request;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tower_service::Service;
+
+/// The epoch a request was queued in, stored in its extensions.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Epoch(pub(crate) usize);
+
+/// The error a request fails with when it was abandoned while queued.
+#[derive(Debug)]
+pub(crate) struct Abandoned;
+
+impl fmt::Display for Abandoned {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "request abandoned before it was sent")
+    }
+}
+
+impl std::error::Error for Abandoned {}
+
+/// The error of an `Abandonable` service, converted to a `BoxError` by
+/// the buffer.
+pub(crate) enum AbandonError<E> {
+    Abandoned,
+    Inner(E),
+}
+
+impl<E> From<AbandonError<E>> for BoxError
+where
+    E: Into<BoxError>,
+{
+    fn from(e: AbandonError<E>) -> Self {
+        match e {
+            AbandonError::Abandoned => Box::new(Abandoned),
+            AbandonError::Inner(e) => e.into(),
+        }
+    }
+}
+
+/// Wraps the inner service within the buffer, requests queued in an
+/// earlier epoch than the current one fail with `Abandoned` without
+/// reaching the inner service.
+pub(crate) struct Abandonable<S> {
+    inner: S,
+    epoch: Arc<AtomicUsize>,
+}
+
+impl<S> Abandonable<S> {
+    pub(crate) fn new(inner: S, epoch: Arc<AtomicUsize>) -> Self {
+        Abandonable { inner, epoch }
+    }
+}
+
+impl<S> Service<Request<Bytes>> for Abandonable<S>
+where
+    S: Service<Request<Bytes>>,
+{
+    type Response = S::Response;
+    type Error = AbandonError<S::Error>;
+    type Future = Either<
+        FutureResult<S::Response, Self::Error>,
+        MapErr<S::Future, fn(S::Error) -> Self::Error>,
+    >;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(AbandonError::Inner)
+    }
+
+    fn call(&mut self, request: Request<Bytes>) -> Self::Future {
+        let current = self.epoch.load(Ordering::SeqCst);
+        let abandoned = request
+            .extensions()
+            .get::<Epoch>()
+            .is_some_and(|queued| queued.0 != current);
+
+        if abandoned {
+            return Either::A(future::err(AbandonError::Abandoned));
+        }
+
+        Either::B(
+            self.inner
+                .call(request)
+                .map_err(AbandonError::Inner as fn(_) -> _),
+        )
+    }
+}
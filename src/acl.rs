@@ -0,0 +1,238 @@
+//! ACL tokens
+
+use crate::session::parse_rfc3339;
+use crate::{null_to_default, BoxError, Consul, Error};
+use bytes::Bytes;
+use futures::future;
+use futures::future::Either;
+use futures::future::Loop;
+use futures::{Future, IntoFuture};
+use http::Method;
+use serde::Deserialize;
+use serde::Serialize;
+use std::cmp;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio_executor::{DefaultExecutor, Executor};
+use tokio_timer::{clock, Delay};
+use tower_http_util::service::HttpService;
+
+/// How long to wait before retrying a failed read or renewal of a token
+/// or session
+pub(crate) const RENEW_RETRY: Duration = Duration::from_secs(5);
+
+/// A step of a renewal task, `S` is the state kept between steps
+pub(crate) type RenewStep<S> = Box<dyn Future<Item = Loop<(), S>, Error = ()> + Send>;
+
+impl<T> Consul<T>
+where
+    T: HttpService<Bytes, ResponseBody = Bytes> + Send + 'static,
+    T::Future: Send + 'static,
+    T::Error: Into<BoxError> + Send + Sync,
+{
+    /// Read the ACL token the client is configured with
+    ///
+    /// This can be used at startup to check the policies of the token
+    /// before relying on it.
+    pub fn token_self(&mut self) -> impl Future<Item = AclToken, Error = Error> {
+        let request = match self.build("/v1/acl/token/self", Method::GET, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        Either::B(self.call(request))
+    }
+
+    /// Read the ACL default policy of the local agent, `allow` or `deny`
+    ///
+    /// Depending on the version of Consul the policy is reported in a
+    /// different part of `/v1/agent/self`, every known location is checked.
+    /// Fails with `Error::NotFound` if the agent does not report it.
+    pub fn acl_default_policy(&mut self) -> impl Future<Item = String, Error = Error> {
+        let request = match self.build("/v1/agent/self", Method::GET, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        let fut = self.call(request).and_then(|agent: AgentAclConfig| {
+            let debug = agent.debug_config.unwrap_or_default();
+            let config = agent.config.unwrap_or_default();
+
+            debug
+                .resolver_settings
+                .and_then(|settings| settings.default_policy)
+                .or(debug.default_policy)
+                .or(config.default_policy)
+                .ok_or(Error::NotFound)
+        });
+
+        Either::B(fut)
+    }
+
+    /// Keep the configured token valid by replacing it with the token
+    /// returned by `renew` before it expires
+    ///
+    /// Consul can not extend the expiration of an existing token, a token
+    /// with an `ExpirationTime` has to be replaced by a new one, for example
+    /// one issued by the Vault Consul secrets engine. This spawns a task on
+    /// the default executor that reads the token with `token_self`, waits
+    /// until two thirds of its remaining lifetime have passed and then
+    /// calls `renew` with it. The returned token is used by this client and
+    /// all of its clones from then on. Failed reads and renewals are
+    /// retried every 5 seconds.
+    ///
+    /// The task stops once the client and all of its clones have been
+    /// dropped, checked whenever it wakes up, or once the token has no
+    /// expiration time. A clone on which `set_token` is called stops
+    /// following the renewed token.
+    pub fn with_auto_renew_token<F, R>(mut self, renew: F) -> Result<Self, Error>
+    where
+        F: FnMut(AclToken) -> R + Send + 'static,
+        R: IntoFuture<Item = String, Error = BoxError>,
+        R::Future: Send + 'static,
+    {
+        let renewed = Arc::new(Mutex::new(self.token.clone()));
+        let shared = Arc::downgrade(&renewed);
+
+        // The task only holds a weak reference to the renewed token so that
+        // it does not keep itself running.
+        let mut client = self.clone();
+        client.renewed_token = None;
+        self.renewed_token = Some(renewed);
+
+        let wall_clock = self.clock.clone();
+        let task = future::loop_fn((client, renew), move |(mut client, renew)| {
+            let shared = shared.clone();
+            let wall_clock = wall_clock.clone();
+
+            if shared.upgrade().is_none() {
+                return Box::new(future::ok(Loop::Break(()))) as RenewStep<_>;
+            }
+
+            let step = client.token_self().then(move |res| {
+                let token = match res {
+                    Ok(token) => token,
+                    Err(_) => return Self::renew_later(RENEW_RETRY, client, renew),
+                };
+
+                let remaining = match token.expires_at() {
+                    Some(expires) => expires.duration_since(wall_clock.now()).unwrap_or_default(),
+                    None => return Box::new(future::ok(Loop::Break(()))) as RenewStep<_>,
+                };
+                let wait = cmp::max(remaining * 2 / 3, Duration::from_secs(1));
+
+                let step = Delay::new(clock::now() + wait).then(move |_| {
+                    let renewed = match shared.upgrade() {
+                        Some(renewed) => renewed,
+                        None => return Box::new(future::ok(Loop::Break(()))) as RenewStep<_>,
+                    };
+
+                    let mut renew = renew;
+                    let step = renew(token).into_future().then(move |res| match res {
+                        Ok(secret) => {
+                            *renewed.lock().unwrap() = Some(secret.clone());
+                            client.set_token(Some(secret));
+                            Box::new(future::ok(Loop::Continue((client, renew))))
+                        }
+                        Err(_) => Self::renew_later(RENEW_RETRY, client, renew),
+                    });
+
+                    Box::new(step)
+                });
+
+                Box::new(step)
+            });
+
+            Box::new(step)
+        });
+
+        DefaultExecutor::current()
+            .spawn(Box::new(task))
+            .map_err(|_| Error::SpawnError)?;
+
+        Ok(self)
+    }
+
+    pub(crate) fn renew_later<S>(wait: Duration, client: Self, renew: S) -> RenewStep<(Self, S)>
+    where
+        S: Send + 'static,
+    {
+        let step = Delay::new(clock::now() + wait).then(|_| Ok(Loop::Continue((client, renew))));
+        Box::new(step)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// An ACL token returned from Consul
+///
+/// For more information on this go [here][value]
+/// [value]: https://www.consul.io/api/acl/tokens.html#read-self-token
+pub struct AclToken {
+    #[serde(rename = "AccessorID")]
+    pub accessor_id: String,
+    #[serde(rename = "SecretID")]
+    pub secret_id: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default, deserialize_with = "null_to_default")]
+    pub policies: Vec<AclTokenPolicy>,
+    #[serde(default)]
+    pub local: bool,
+    #[serde(default)]
+    pub create_time: String,
+    /// The RFC 3339 time the token expires at, `None` for tokens that do
+    /// not expire
+    #[serde(default)]
+    pub expiration_time: Option<String>,
+    #[serde(default)]
+    pub create_index: i64,
+    #[serde(default)]
+    pub modify_index: i64,
+}
+
+/// The parts of `/v1/agent/self` that may report the ACL default policy
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AgentAclConfig {
+    #[serde(default)]
+    config: Option<AclPolicyConfig>,
+    #[serde(default)]
+    debug_config: Option<AclPolicyConfig>,
+}
+
+#[derive(Default, Deserialize)]
+struct AclPolicyConfig {
+    /// Consul before 1.10
+    #[serde(rename = "ACLDefaultPolicy", default)]
+    default_policy: Option<String>,
+    /// Consul 1.10 and later
+    #[serde(rename = "ACLResolverSettings", default)]
+    resolver_settings: Option<AclResolverSettings>,
+}
+
+#[derive(Deserialize)]
+struct AclResolverSettings {
+    #[serde(rename = "ACLDefaultPolicy", default)]
+    default_policy: Option<String>,
+}
+
+impl AclToken {
+    /// The time the token expires at, if it expires
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        self.expiration_time
+            .as_ref()
+            .and_then(|time| parse_rfc3339(time))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// A policy linked to an `AclToken`
+pub struct AclTokenPolicy {
+    #[serde(rename = "ID")]
+    pub id: String,
+    pub name: String,
+}
@@ -0,0 +1,754 @@
+//! The local agent, service registration and its logs
+
+use crate::{null_to_default, BoxConsulFuture, BoxError, Consul, ConsulDuration, Error, Weights};
+use bytes::Bytes;
+use futures::future;
+use futures::future::Either;
+use futures::stream;
+use futures::stream::Stream;
+use futures::sync::oneshot;
+use futures::Future;
+use http::Method;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use tokio_executor::{DefaultExecutor, Executor};
+use tokio_timer::{clock, Interval};
+use tower_http_util::service::HttpService;
+
+/// The id of the check every agent has that tracks its membership
+const SERF_HEALTH: &str = "serfHealth";
+
+/// The shortest TTL `register_with_ttl` accepts, shorter ones would
+/// need a heartbeat faster than is reasonable to send to the agent.
+const MIN_HEARTBEAT_TTL: Duration = Duration::from_secs(1);
+
+impl<T> Consul<T>
+where
+    T: HttpService<Bytes, ResponseBody = Bytes> + Send + 'static,
+    T::Future: Send + 'static,
+    T::Error: Into<BoxError> + Send + Sync,
+{
+    /// Register with the current agent with the service config
+    pub fn register(&mut self, service: impl Into<Bytes>) -> BoxConsulFuture<()> {
+        Box::new(self.register_with_warnings(service).map(|_| ()))
+    }
+
+    /// Register with the current agent with the service config, returning
+    /// any warnings Consul attached to the response
+    ///
+    /// Warnings are read from the standard `Warning` response headers.
+    /// Current Consul versions only log deprecated registration fields
+    /// rather than returning them, so the list is usually empty, this
+    /// exists so callers notice deprecations once Consul reports them.
+    pub fn register_with_warnings(
+        &mut self,
+        service: impl Into<Bytes>,
+    ) -> BoxConsulFuture<Vec<String>> {
+        let url = "/v1/agent/service/register";
+        let request = match self.build(url, Method::PUT, service.into()) {
+            Ok(req) => req,
+            Err(e) => return Box::new(future::lazy(move || Box::new(future::err(e)))),
+        };
+
+        let fut = self
+            .send(request)
+            .then(|res| match res {
+                Ok(res) => Self::handle_status(res),
+                Err(e) => Err(e),
+            })
+            .map(|res| Self::warnings(&res));
+
+        Box::new(fut)
+    }
+
+    /// Register a typed service definition with the current agent
+    pub fn register_service(
+        &mut self,
+        registration: &AgentServiceRegistration,
+    ) -> BoxConsulFuture<()> {
+        let body = if self.default_tags.is_empty() {
+            serde_json::to_vec(registration)
+        } else {
+            let mut registration = registration.clone();
+            let mut seen = HashSet::new();
+            let tags = registration.tags.iter().chain(&self.default_tags);
+            registration.tags = tags.filter(|tag| seen.insert(*tag)).cloned().collect();
+
+            serde_json::to_vec(&registration)
+        };
+
+        match body {
+            Ok(body) => self.register(body),
+            Err(e) => Box::new(future::err(Error::from(e))),
+        }
+    }
+
+    /// Register a service with a TTL check and keep it passing
+    ///
+    /// Any check already set on `registration`, in `check` or `checks`, is
+    /// replaced by a single TTL check with the id `service:<id>`. Once registered a task is spawned on
+    /// the default executor that marks the check as passing immediately
+    /// and then every `ttl / 2`, failed heartbeats are retried on the next
+    /// tick. The check is registered with `ttl` exactly, a `ttl` shorter
+    /// than a second fails with `Error::InvalidArgument` without
+    /// registering anything.
+    ///
+    /// The task runs until the returned `Heartbeat` is dropped, it then
+    /// stops and deregisters the service. The deregistration happens in
+    /// the background so the executor must keep running briefly after the
+    /// `Heartbeat` is dropped for it to complete.
+    pub fn register_with_ttl(
+        &mut self,
+        mut registration: AgentServiceRegistration,
+        ttl: Duration,
+    ) -> impl Future<Item = Heartbeat, Error = Error> {
+        let id = registration
+            .id
+            .clone()
+            .unwrap_or_else(|| registration.name.clone());
+        let check_id = format!("service:{}", id);
+
+        if ttl < MIN_HEARTBEAT_TTL {
+            let msg = format!("ttl of {:?} is shorter than one second", ttl);
+            return Either::A(future::err(Error::InvalidArgument(msg)));
+        }
+
+        // The heartbeat is derived from the registered value so the two
+        // can never drift apart.
+        let registered = ConsulDuration::from(ttl);
+        registration.check = Some(AgentServiceCheck {
+            check_id: Some(check_id.clone()),
+            ttl: Some(registered),
+            ..Default::default()
+        });
+        registration.checks.clear();
+
+        let mut heartbeat = self.clone();
+        let mut deregister = self.clone();
+
+        let fut = self.register_service(&registration).and_then(move |_| {
+            let (stop, stopped) = oneshot::channel();
+
+            let beats = Interval::new(clock::now(), registered.0 / 2)
+                .map_err(|_| ())
+                .for_each(move |_| heartbeat.check_pass(&check_id).then(|_| Ok(())));
+
+            // The receiver resolves, with an error, once the `Heartbeat`
+            // holding the sender is dropped.
+            let task = beats
+                .select2(stopped)
+                .then(move |_| deregister.deregister(&id).then(|_| Ok(())));
+
+            DefaultExecutor::current()
+                .spawn(Box::new(task))
+                .map_err(|_| Error::SpawnError)?;
+
+            Ok(Heartbeat { _stop: stop })
+        });
+
+        Either::B(fut)
+    }
+
+    /// Mark a TTL check as passing
+    pub fn check_pass(&mut self, check_id: &str) -> BoxConsulFuture<()> {
+        let url = format!("/v1/agent/check/pass/{}", check_id);
+        self.put_empty(&url)
+    }
+
+    /// Deregister a service from the current agent
+    pub fn deregister(&mut self, service_id: &str) -> BoxConsulFuture<()> {
+        let url = format!("/v1/agent/service/deregister/{}", service_id);
+        self.put_empty(&url)
+    }
+
+    /// Deregister a service from the current agent without waiting for
+    /// the result
+    ///
+    /// The deregistration is spawned on the default executor and its result
+    /// is ignored, this only fails if the task can not be spawned. It is
+    /// meant for shutdown paths, the executor and the process must keep
+    /// running briefly after this returns for the request to complete.
+    pub fn deregister_detached(&mut self, service_id: &str) -> Result<(), Error> {
+        let task = self.deregister(service_id).then(|_| Ok(()));
+
+        DefaultExecutor::current()
+            .spawn(Box::new(task))
+            .map_err(|_| Error::SpawnError)
+    }
+
+    /// Force a node that failed into the left state, removing it from the
+    /// catalog
+    pub fn force_leave(&mut self, node: &str) -> BoxConsulFuture<()> {
+        let url = format!("/v1/agent/force-leave/{}", node);
+        self.put_empty(&url)
+    }
+
+    /// Force a node to leave only if its `serfHealth` check is critical,
+    /// resolving to whether it was forced to leave
+    ///
+    /// The `serfHealth` check is critical once the cluster considers the
+    /// node failed, a node that is merely slow or in maintenance is left
+    /// alone, as is an unknown node. The check is read before the node is
+    /// forced to leave, a node that recovers in between is still evicted
+    /// and has to rejoin.
+    pub fn force_leave_if_failed(&mut self, node: &str) -> impl Future<Item = bool, Error = Error> {
+        let node = node.to_string();
+        let mut client = self.clone();
+
+        self.health_node(&node).and_then(move |checks| {
+            let failed = checks
+                .iter()
+                .any(|check| check.check_id == SERF_HEALTH && check.status == "critical");
+
+            if !failed {
+                return Either::A(future::ok(false));
+            }
+
+            Either::B(client.force_leave(&node).map(|_| true))
+        })
+    }
+
+    /// Register several services with the current agent concurrently
+    ///
+    /// Every registration is attempted, if any of them fail the returned
+    /// `Error::Registration` lists the id (or name when no id was set) of
+    /// each service that failed along with its error.
+    pub fn register_many(
+        &mut self,
+        registrations: &[AgentServiceRegistration],
+    ) -> impl Future<Item = (), Error = Error> {
+        let futs = registrations
+            .iter()
+            .map(|registration| {
+                let id = registration
+                    .id
+                    .clone()
+                    .unwrap_or_else(|| registration.name.clone());
+
+                self.register_service(registration)
+                    .then(move |res| Ok::<_, Error>(res.err().map(|e| (id, e))))
+            })
+            .collect::<Vec<_>>();
+
+        future::join_all(futs).and_then(|results| {
+            let failed = results.into_iter().flatten().collect::<Vec<_>>();
+
+            if failed.is_empty() {
+                Ok(())
+            } else {
+                Err(Error::Registration(failed))
+            }
+        })
+    }
+
+    fn put_empty(&mut self, url: &str) -> BoxConsulFuture<()> {
+        let request = match self.build(url, Method::PUT, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Box::new(future::err(e)),
+        };
+
+        let fut = self
+            .send(request)
+            .then(|res| match res {
+                Ok(res) => Self::handle_status(res),
+                Err(e) => Err(e),
+            })
+            .map(|_| ());
+
+        Box::new(fut)
+    }
+
+    /// Read the configuration and member information of the local agent
+    pub fn agent_self(&mut self) -> impl Future<Item = AgentSelf, Error = Error> {
+        let request = match self.build("/v1/agent/self", Method::GET, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        Either::B(self.call(request))
+    }
+
+    /// List the members of the LAN gossip pool seen by the local agent, or
+    /// of the WAN pool of the servers when `wan` is set
+    ///
+    /// Only server agents are part of the WAN pool, asking a client agent
+    /// for it lists nothing.
+    pub fn agent_members(&mut self, wan: bool) -> impl Future<Item = Vec<Member>, Error = Error> {
+        let url = if wan {
+            "/v1/agent/members?wan=1"
+        } else {
+            "/v1/agent/members"
+        };
+        let request = match self.build(url, Method::GET, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        Either::B(self.call(request))
+    }
+
+    /// Get the name of the node of the local agent
+    ///
+    /// The name is read with `agent_self` on the first call and cached,
+    /// every later call on the client or its clones returns the cached
+    /// name without a request.
+    pub fn node_name(&mut self) -> impl Future<Item = String, Error = Error> {
+        let cached = self.agent_cache.lock().unwrap().node_name.clone();
+        if let Some(name) = cached {
+            return Either::A(future::ok(name));
+        }
+
+        let cache = self.agent_cache.clone();
+        let fut = self.agent_self().map(move |agent| {
+            let name = agent.config.node_name;
+            cache.lock().unwrap().node_name = Some(name.clone());
+            name
+        });
+
+        Either::B(fut)
+    }
+
+    /// Get every check registered with the local agent, keyed by check id
+    pub fn agent_checks(
+        &mut self,
+    ) -> impl Future<Item = HashMap<String, AgentCheck>, Error = Error> {
+        let request = match self.build("/v1/agent/checks", Method::GET, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        Either::B(self.call(request))
+    }
+
+    /// Get the checks the local agent has for the service with the id
+    /// `service_id`, sorted by check id
+    ///
+    /// Node level checks, like `serfHealth`, are not included.
+    pub fn agent_service_checks(
+        &mut self,
+        service_id: &str,
+    ) -> impl Future<Item = Vec<AgentCheck>, Error = Error> {
+        let service_id = service_id.to_string();
+
+        self.agent_checks().map(move |checks| {
+            let mut checks = checks
+                .into_values()
+                .filter(|check| check.service_id == service_id)
+                .collect::<Vec<_>>();
+            checks.sort_by(|a, b| a.check_id.cmp(&b.check_id));
+            checks
+        })
+    }
+}
+
+/// Derive a stable service id from the name of a service and the
+/// address and port it is reachable at.
+///
+/// The id has the format `name-address-port` where every character that
+/// is not ascii alphanumeric, `-` or `_` is replaced by a `-`. For example
+/// `service_id("web", "10.0.0.1", 8080)` returns `web-10-0-0-1-8080`.
+/// Registering with this id means that a restarted instance replaces its
+/// previous registration rather than adding a new one.
+pub fn service_id(name: &str, address: &str, port: u16) -> String {
+    format!("{}-{}-{}", name, address, port)
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+/// Keeps a service registered with `Consul::register_with_ttl` healthy,
+/// dropping it stops the heartbeat and deregisters the service.
+#[must_use = "dropping the heartbeat deregisters the service"]
+pub struct Heartbeat {
+    _stop: oneshot::Sender<()>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// A service definition to register with the agent
+///
+/// For more information on this go [here][value]
+/// [value]: https://www.consul.io/api/agent/service.html#register-service
+pub struct AgentServiceRegistration {
+    #[serde(rename = "ID", skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub meta: HashMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub check: Option<AgentServiceCheck>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub checks: Vec<AgentServiceCheck>,
+    /// The weights of the instance for weighted load balancing, Consul
+    /// defaults both to `1`
+    ///
+    /// `HealthService::weight` reads them back, picking the passing or
+    /// warning weight from the current health of the instance.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weights: Option<Weights>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// A check registered alongside a service
+///
+/// For more information on this go [here][value]
+/// [value]: https://www.consul.io/api/agent/check.html#register-check
+pub struct AgentServiceCheck {
+    #[serde(rename = "CheckID", skip_serializing_if = "Option::is_none")]
+    pub check_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(rename = "HTTP", skip_serializing_if = "Option::is_none")]
+    pub http: Option<String>,
+    #[serde(rename = "TCP", skip_serializing_if = "Option::is_none")]
+    pub tcp: Option<String>,
+    #[serde(rename = "GRPC", skip_serializing_if = "Option::is_none")]
+    pub grpc: Option<String>,
+    #[serde(rename = "TTL", skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<ConsulDuration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval: Option<ConsulDuration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<ConsulDuration>,
+    /// Deregister the service once the check has been critical for this
+    /// long, Consul reaps at most every 30 seconds so it may take longer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deregister_critical_service_after: Option<ConsulDuration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    /// The most bytes of check output Consul keeps, longer output is
+    /// truncated, Consul defaults to 4KB
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_max_size: Option<usize>,
+}
+
+impl AgentServiceCheck {
+    /// A check that sends a `GET` to `url` every `interval`, passing on a
+    /// `2xx` response
+    pub fn http(url: &str, interval: Duration) -> Self {
+        AgentServiceCheck {
+            http: Some(url.to_string()),
+            interval: Some(interval.into()),
+            ..AgentServiceCheck::default()
+        }
+    }
+
+    /// A check that opens a TCP connection to `address`, a `host:port`,
+    /// every `interval`
+    pub fn tcp(address: &str, interval: Duration) -> Self {
+        AgentServiceCheck {
+            tcp: Some(address.to_string()),
+            interval: Some(interval.into()),
+            ..AgentServiceCheck::default()
+        }
+    }
+
+    /// A check that calls the standard gRPC health service at `address`,
+    /// a `host:port` optionally followed by `/service`, every `interval`
+    pub fn grpc(address: &str, interval: Duration) -> Self {
+        AgentServiceCheck {
+            grpc: Some(address.to_string()),
+            interval: Some(interval.into()),
+            ..AgentServiceCheck::default()
+        }
+    }
+
+    /// A check that turns critical unless it is marked as passing within
+    /// every `ttl`, see `Consul::check_pass`
+    pub fn ttl(ttl: Duration) -> Self {
+        AgentServiceCheck {
+            ttl: Some(ttl.into()),
+            ..AgentServiceCheck::default()
+        }
+    }
+
+    /// Keep up to `size` bytes of the output of the check rather than
+    /// Consul's default of 4KB
+    pub fn with_output_max_size(mut self, size: usize) -> Self {
+        self.output_max_size = Some(size);
+        self
+    }
+
+    /// Deregister the service once the check has been critical for
+    /// `after`, so instances that went away without deregistering are
+    /// cleaned up
+    pub fn with_deregister_critical_after(mut self, after: Duration) -> Self {
+        self.deregister_critical_service_after = Some(after.into());
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// The local agent as returned from Consul
+///
+/// For more information on this go [here][value]
+/// [value]: https://www.consul.io/api/agent.html#read-configuration
+pub struct AgentSelf {
+    pub config: AgentConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// The configuration of the local agent
+pub struct AgentConfig {
+    pub datacenter: String,
+    pub node_name: String,
+    #[serde(rename = "NodeID", default)]
+    pub node_id: String,
+    #[serde(default)]
+    pub server: bool,
+    #[serde(default)]
+    pub revision: String,
+    #[serde(default)]
+    pub version: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// A member of a gossip pool
+///
+/// For more information on this go [here][value]
+/// [value]: https://www.consul.io/api/agent.html#list-members
+pub struct Member {
+    pub name: String,
+    pub addr: String,
+    pub port: u16,
+    #[serde(default, deserialize_with = "null_to_default")]
+    pub tags: HashMap<String, String>,
+    pub status: MemberStatus,
+    #[serde(default)]
+    pub protocol_cur: u8,
+}
+
+/// The gossip status of a `Member`
+///
+/// Consul reports the status as a number, numbers this version of the
+/// client does not know about are kept as `Unknown`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemberStatus {
+    /// The member is alive and reachable
+    Alive,
+    /// The member is leaving the pool gracefully
+    Leaving,
+    /// The member left the pool gracefully
+    Left,
+    /// The member stopped responding and is considered failed
+    Failed,
+    /// A status this version of the client does not know about
+    Unknown(u8),
+}
+
+impl From<u8> for MemberStatus {
+    fn from(status: u8) -> Self {
+        match status {
+            1 => MemberStatus::Alive,
+            2 => MemberStatus::Leaving,
+            3 => MemberStatus::Left,
+            4 => MemberStatus::Failed,
+            status => MemberStatus::Unknown(status),
+        }
+    }
+}
+
+impl From<MemberStatus> for u8 {
+    fn from(status: MemberStatus) -> Self {
+        match status {
+            MemberStatus::Alive => 1,
+            MemberStatus::Leaving => 2,
+            MemberStatus::Left => 3,
+            MemberStatus::Failed => 4,
+            MemberStatus::Unknown(status) => status,
+        }
+    }
+}
+
+impl Serialize for MemberStatus {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_u8(u8::from(*self))
+    }
+}
+
+impl<'de> Deserialize<'de> for MemberStatus {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        u8::deserialize(deserializer).map(MemberStatus::from)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// A check registered with the local agent
+///
+/// For more information on this go [here][value]
+/// [value]: https://www.consul.io/api/agent/check.html#list-checks
+pub struct AgentCheck {
+    pub node: String,
+    #[serde(rename = "CheckID")]
+    pub check_id: String,
+    pub name: String,
+    pub status: String,
+    #[serde(default)]
+    pub notes: String,
+    #[serde(default)]
+    pub output: String,
+    #[serde(rename = "ServiceID", default)]
+    pub service_id: String,
+    #[serde(default)]
+    pub service_name: String,
+    #[serde(default, deserialize_with = "null_to_default")]
+    pub service_tags: Vec<String>,
+    #[serde(default)]
+    pub r#type: String,
+}
+
+/// The level of an agent log line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[allow(missing_docs)]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    /// The level as used by the `loglevel` parameter of the agent
+    /// monitor endpoint
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LogLevel::Trace => "trace",
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Warn => "warn",
+            LogLevel::Error => "err",
+        }
+    }
+
+    fn parse(level: &str) -> Option<LogLevel> {
+        match level.to_ascii_lowercase().as_str() {
+            "trace" => Some(LogLevel::Trace),
+            "debug" => Some(LogLevel::Debug),
+            "info" => Some(LogLevel::Info),
+            "warn" | "warning" => Some(LogLevel::Warn),
+            "err" | "error" => Some(LogLevel::Error),
+            _ => None,
+        }
+    }
+}
+
+/// A single line logged by the agent
+///
+/// Lines logged with `-log-json` are parsed, any other line is kept as
+/// the `message` with no level or timestamp.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    /// The level of the line, if it could be parsed
+    pub level: Option<LogLevel>,
+    /// The timestamp of the line as logged, if it could be parsed
+    pub timestamp: Option<String>,
+    /// The message, or the whole line if it could not be parsed
+    pub message: String,
+}
+
+impl LogEntry {
+    /// Parse a single log line
+    pub fn parse(line: &str) -> LogEntry {
+        #[derive(Deserialize)]
+        struct JsonLine {
+            #[serde(rename = "@level")]
+            level: Option<String>,
+            #[serde(rename = "@timestamp")]
+            timestamp: Option<String>,
+            #[serde(rename = "@message")]
+            message: String,
+        }
+
+        match serde_json::from_str::<JsonLine>(line) {
+            Ok(json) => LogEntry {
+                level: json.level.as_ref().and_then(|level| LogLevel::parse(level)),
+                timestamp: json.timestamp,
+                message: json.message,
+            },
+            Err(_) => LogEntry {
+                level: None,
+                timestamp: None,
+                message: line.to_string(),
+            },
+        }
+    }
+}
+
+/// Split a stream of body chunks into parsed agent log lines
+///
+/// Lines may span chunks, a line is only parsed once its end has been
+/// received or the stream ends. Empty lines are skipped.
+///
+/// The client buffers whole response bodies so it can not follow the
+/// never ending `/v1/agent/monitor` endpoint itself, this is meant for
+/// callers that read it with a streaming transport.
+pub fn log_entries<S>(chunks: S) -> impl Stream<Item = LogEntry, Error = S::Error>
+where
+    S: Stream<Item = Bytes>,
+{
+    let mut partial = Vec::new();
+
+    chunks
+        .map(Some)
+        .chain(stream::once(Ok(None)))
+        .map(move |chunk| {
+            let complete = match chunk {
+                Some(chunk) => {
+                    partial.extend_from_slice(&chunk[..]);
+                    match partial.iter().rposition(|b| *b == b'\n') {
+                        Some(end) => {
+                            let rest = partial.split_off(end + 1);
+                            std::mem::replace(&mut partial, rest)
+                        }
+                        None => Vec::new(),
+                    }
+                }
+                None => std::mem::take(&mut partial),
+            };
+
+            let entries = String::from_utf8_lossy(&complete)
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(LogEntry::parse)
+                .collect::<Vec<_>>();
+
+            stream::iter_ok(entries)
+        })
+        .flatten()
+}
@@ -0,0 +1,442 @@
+//! The catalog of nodes, services and datacenters
+
+use crate::{null_to_default, BoxError, Consul, ConsulFuture, Error, Query};
+use bytes::Bytes;
+use futures::future;
+use futures::future::Either;
+use futures::stream;
+use futures::stream::Stream;
+use futures::Future;
+use http::Method;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
+use std::collections::HashMap;
+use tower_http_util::service::HttpService;
+
+/// How many services `all_service_instances` reads at once
+const INVENTORY_CONCURRENCY: usize = 8;
+
+impl<T> Consul<T>
+where
+    T: HttpService<Bytes, ResponseBody = Bytes> + Send + 'static,
+    T::Future: Send + 'static,
+    T::Error: Into<BoxError> + Send + Sync,
+{
+    /// Get a list of nodes that have registered via the provided service
+    ///
+    /// Consul responds with an empty list both for a service that has no
+    /// instances and for a service that was never registered, use
+    /// `service_nodes_required` to treat both as an error.
+    pub fn service_nodes(
+        &mut self,
+        service: &str,
+    ) -> impl Future<Item = Vec<ConsulService>, Error = Error> {
+        let url = format!("/v1/catalog/service/{}", service);
+        let request = match self.build(&url, Method::GET, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        Either::B(self.call(request))
+    }
+
+    /// Get the instances that can connect to `service` through the service
+    /// mesh, the Connect-capable instances of a service and the sidecar
+    /// proxies registered for it
+    ///
+    /// Mesh clients dial these rather than the instances returned by
+    /// `service_nodes`. A proxy has a `service_kind` of
+    /// `ServiceKind::ConnectProxy` and is reached at its `service_address`
+    /// and `service_port`, its `proxy` names the service it fronts.
+    pub fn connect_service_nodes(
+        &mut self,
+        service: &str,
+    ) -> impl Future<Item = Vec<ConsulService>, Error = Error> {
+        let url = format!("/v1/catalog/connect/{}", service);
+        let request = match self.build(&url, Method::GET, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        Either::B(self.call(request))
+    }
+
+    /// Get a list of nodes that have registered via the provided service,
+    /// failing with `Error::NotFound` if there are none
+    ///
+    /// Consul can not distinguish a service without instances from an
+    /// unknown service, both fail here.
+    pub fn service_nodes_required(
+        &mut self,
+        service: &str,
+    ) -> impl Future<Item = Vec<ConsulService>, Error = Error> {
+        self.service_nodes(service).and_then(|nodes| {
+            if nodes.is_empty() {
+                Err(Error::NotFound)
+            } else {
+                Ok(nodes)
+            }
+        })
+    }
+
+    /// Get a list of nodes that have registered via the provided service,
+    /// sorted by node name and then by address
+    ///
+    /// `service_nodes` returns the instances in the order Consul lists
+    /// them, which is not guaranteed to be stable between requests.
+    pub fn service_nodes_sorted(
+        &mut self,
+        service: &str,
+    ) -> impl Future<Item = Vec<ConsulService>, Error = Error> {
+        self.service_nodes(service).map(|mut nodes| {
+            nodes.sort_by(|a, b| (&a.node, &a.address).cmp(&(&b.node, &b.address)));
+            nodes
+        })
+    }
+
+    /// Get the name of every service in the catalog along with the tags
+    /// of its instances
+    pub fn catalog_services(
+        &mut self,
+    ) -> impl Future<Item = HashMap<String, Vec<String>>, Error = Error> {
+        let request = match self.build("/v1/catalog/services", Method::GET, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        Either::B(self.call(request))
+    }
+
+    /// Get the instances of every service in the catalog, keyed by
+    /// service name
+    ///
+    /// The services are listed with `catalog_services` and then read with
+    /// `service_nodes`, at most 8 at a time. A service that fails to be
+    /// read is left out of `instances` and listed in `errors` instead, only
+    /// a failure to list the services fails the whole call.
+    pub fn all_service_instances(&mut self) -> impl Future<Item = ServiceInventory, Error = Error> {
+        let client = self.clone();
+
+        self.catalog_services().and_then(move |services| {
+            let reads = services.into_keys().map(move |name| {
+                client
+                    .clone()
+                    .service_nodes(&name)
+                    .then(move |res| Ok::<_, Error>((name, res)))
+            });
+
+            stream::iter_ok(reads)
+                .buffer_unordered(INVENTORY_CONCURRENCY)
+                .fold(ServiceInventory::default(), |mut inventory, (name, res)| {
+                    match res {
+                        Ok(nodes) => {
+                            inventory.instances.insert(name, nodes);
+                        }
+                        Err(e) => inventory.errors.push((name, e)),
+                    }
+                    Ok::<_, Error>(inventory)
+                })
+        })
+    }
+
+    /// Get the address and port of every instance of the provided service
+    ///
+    /// Only the fields needed to connect are deserialized, which is
+    /// considerably cheaper than `service_nodes` for large services. The
+    /// address is the service address, or the address of its node when the
+    /// service did not register one.
+    pub fn service_node_addresses(
+        &mut self,
+        service: &str,
+    ) -> impl Future<Item = Vec<(String, u16)>, Error = Error> {
+        let url = format!("/v1/catalog/service/{}", service);
+        let request = match self.build(&url, Method::GET, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        let fut: ConsulFuture<T, Vec<ServiceNodeAddress>> = self.call(request);
+        Either::B(fut.map(|nodes| {
+            nodes
+                .into_iter()
+                .map(|node| {
+                    let address = if node.service_address.is_empty() {
+                        node.address
+                    } else {
+                        node.service_address
+                    };
+                    (address, node.service_port)
+                })
+                .collect()
+        }))
+    }
+
+    /// Get a list of nodes that have registered via the provided service
+    /// filtered by `query`
+    pub fn service_nodes_query(
+        &mut self,
+        service: &str,
+        query: ServiceQuery,
+    ) -> impl Future<Item = Vec<ConsulService>, Error = Error> {
+        let mut params = Query::default();
+        params.opt("dc", query.dc.as_ref());
+        for tag in &query.tags {
+            params.param("tag", tag);
+        }
+        for (key, value) in &query.node_meta {
+            params.param("node-meta", &format!("{}:{}", key, value));
+        }
+        params.opt("near", query.near.as_ref());
+        params.opt("filter", query.filter.as_ref());
+
+        let url = params.append_to(&format!("/v1/catalog/service/{}", service));
+        let request = match self.build(&url, Method::GET, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        Either::B(self.call(request))
+    }
+
+    /// Check that the datacenter set with `with_datacenter` exists,
+    /// failing with `Error::InvalidArgument` if it does not
+    ///
+    /// Without a configured datacenter this always succeeds.
+    pub fn validate_datacenter(&mut self) -> impl Future<Item = (), Error = Error> {
+        let dc = match self.datacenter.clone() {
+            Some(dc) => dc,
+            None => return Either::A(future::ok(())),
+        };
+
+        let fut = self.datacenters().and_then(move |dcs| {
+            if dcs.contains(&dc) {
+                Ok(())
+            } else {
+                let known = dcs.join(", ");
+                let msg = format!("unknown datacenter {:?}, known: {}", dc, known);
+                Err(Error::InvalidArgument(msg))
+            }
+        });
+
+        Either::B(fut)
+    }
+
+    /// Get the names of every datacenter known to the agent, sorted by
+    /// estimated round trip time
+    pub fn datacenters(&mut self) -> impl Future<Item = Vec<String>, Error = Error> {
+        let url = "/v1/catalog/datacenters";
+        let request = match self.build(url, Method::GET, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        Either::B(self.call(request))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// The value returned from Consul on Service requests
+///
+/// For more information on this go [here][value]
+/// [value]: https://www.consul.io/api/agent/service.html#sample-response-1
+pub struct ConsulService {
+    /// The kind of the service as reported by Consul, see `service_kind`
+    #[serde(rename = "ServiceKind", default)]
+    pub kind: Option<String>,
+    #[serde(rename = "ID")]
+    pub id: String,
+    #[serde(rename = "ServiceID")]
+    pub service_id: String,
+    #[serde(rename = "ServiceName")]
+    pub service_name: String,
+    #[serde(rename = "ServiceTags")]
+    pub tags: Vec<String>,
+    #[serde(rename = "ServiceMeta")]
+    pub meta: HashMap<String, String>,
+    pub node: String,
+    pub address: String,
+    pub datacenter: String,
+    #[serde(rename = "ServiceWeights", default)]
+    pub weights: Weights,
+    /// The address the service registered, empty if it uses the address
+    /// of its node
+    #[serde(default, deserialize_with = "null_to_default")]
+    pub service_address: String,
+    #[serde(default)]
+    pub service_port: u16,
+    /// The service a `connect-proxy` fronts, Consul also returns an empty
+    /// proxy for other kinds
+    #[serde(
+        rename = "ServiceProxy",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub proxy: Option<ServiceProxy>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// The service a Connect proxy fronts, as returned in the catalog
+///
+/// For more information on this go [here][value]
+/// [value]: https://www.consul.io/docs/connect/registration/service-registration
+pub struct ServiceProxy {
+    #[serde(default)]
+    pub destination_service_name: String,
+    #[serde(rename = "DestinationServiceID", default)]
+    pub destination_service_id: String,
+    #[serde(default)]
+    pub local_service_address: String,
+    #[serde(default)]
+    pub local_service_port: u16,
+}
+
+impl ConsulService {
+    /// The weight of the instance for load balancing
+    ///
+    /// Catalog responses do not include the health of the instance so
+    /// this is always the passing weight, use `HealthService::weight` to
+    /// take health into account.
+    pub fn weight(&self) -> u32 {
+        self.weights.passing
+    }
+
+    /// The kind of the service, parsed from `kind`
+    pub fn service_kind(&self) -> ServiceKind {
+        ServiceKind::from(self.kind.as_deref().unwrap_or_default())
+    }
+}
+
+/// The instances of every service in the catalog, see
+/// `Consul::all_service_instances`
+#[derive(Debug, Default)]
+pub struct ServiceInventory {
+    /// The instances of every service that was read, keyed by service name
+    pub instances: HashMap<String, Vec<ConsulService>>,
+    /// The services that could not be read along with their error
+    pub errors: Vec<(String, Error)>,
+}
+
+/// The kind of a service
+///
+/// Kinds added in later Consul versions are kept as `Unknown` rather than
+/// failing to deserialize.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ServiceKind {
+    /// A regular service, reported by Consul as an empty kind
+    Typical,
+    /// A Connect sidecar proxy
+    ConnectProxy,
+    /// A mesh gateway
+    MeshGateway,
+    /// A terminating gateway
+    TerminatingGateway,
+    /// An ingress gateway
+    IngressGateway,
+    /// A kind this version of the client does not know about
+    Unknown(String),
+}
+
+impl ServiceKind {
+    /// The kind as reported by Consul
+    pub fn as_str(&self) -> &str {
+        match self {
+            ServiceKind::Typical => "",
+            ServiceKind::ConnectProxy => "connect-proxy",
+            ServiceKind::MeshGateway => "mesh-gateway",
+            ServiceKind::TerminatingGateway => "terminating-gateway",
+            ServiceKind::IngressGateway => "ingress-gateway",
+            ServiceKind::Unknown(kind) => kind,
+        }
+    }
+}
+
+impl From<&str> for ServiceKind {
+    fn from(kind: &str) -> Self {
+        match kind {
+            "" | "typical" => ServiceKind::Typical,
+            "connect-proxy" => ServiceKind::ConnectProxy,
+            "mesh-gateway" => ServiceKind::MeshGateway,
+            "terminating-gateway" => ServiceKind::TerminatingGateway,
+            "ingress-gateway" => ServiceKind::IngressGateway,
+            kind => ServiceKind::Unknown(kind.to_string()),
+        }
+    }
+}
+
+impl Serialize for ServiceKind {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for ServiceKind {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let kind: Option<String> = Option::deserialize(deserializer)?;
+        Ok(ServiceKind::from(kind.as_deref().unwrap_or_default()))
+    }
+}
+
+/// The connect info of a catalog service, every other field of the
+/// response is skipped
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ServiceNodeAddress {
+    #[serde(default)]
+    address: String,
+    #[serde(default, deserialize_with = "null_to_default")]
+    service_address: String,
+    #[serde(default)]
+    service_port: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// The weights of a service instance used for weighted load balancing,
+/// Consul defaults both to `1`
+///
+/// For more information on this go [here][value]
+/// [value]: https://www.consul.io/docs/agent/services.html
+pub struct Weights {
+    pub passing: u32,
+    pub warning: u32,
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Weights {
+            passing: 1,
+            warning: 1,
+        }
+    }
+}
+
+/// Options used to filter the nodes returned by
+/// `Consul::service_nodes_query`
+#[derive(Debug, Clone, Default)]
+pub struct ServiceQuery {
+    /// The datacenter to query, defaults to the datacenter of the agent
+    pub dc: Option<String>,
+    /// Only return instances that have all of these tags
+    pub tags: Vec<String>,
+    /// Only return instances on nodes with all of these meta key value
+    /// pairs
+    pub node_meta: Vec<(String, String)>,
+    /// Sort the instances by round trip time from this node, `_agent`
+    /// sorts by distance from the agent
+    pub near: Option<String>,
+    /// A filter expression applied by Consul to the instances
+    pub filter: Option<String>,
+}
@@ -0,0 +1,558 @@
+//! Creating a client and its configuration
+
+use crate::abandon::Abandonable;
+use crate::health::Balancer;
+use crate::kv::KvPrefix;
+use crate::metrics::Counters;
+use crate::transport::Reconnect;
+use crate::worker::MonitoredExecutor;
+use crate::{
+    BoxError, Clock, Consul, ConsulMetrics, Error, Inner, LoadBalance, SystemClock, TaggedAddress,
+    WorkerMonitor,
+};
+use bytes::Bytes;
+use http::header::HeaderName;
+use http::Method;
+use std::cmp;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tower_buffer::Buffer;
+use tower_http_util::service::HttpService;
+use tower_layer::Layer;
+
+/// The default header used to send request ids
+const REQUEST_ID: &str = "x-request-id";
+
+impl<T> Consul<T>
+where
+    T: HttpService<Bytes, ResponseBody = Bytes> + Send + 'static,
+    T::Future: Send + 'static,
+    T::Error: Into<BoxError> + Send + Sync,
+{
+    /// Create a new consul client
+    ///
+    /// `scheme` and `authority` are only used to build the URIs of
+    /// requests. A transport that does not route by host, like one that
+    /// talks to the agent over a Unix domain socket, can use a placeholder
+    /// authority such as `localhost`, see `examples/unix_socket.rs`.
+    pub fn new(inner: T, bound: usize, scheme: String, authority: String) -> Result<Self, Error> {
+        let epoch = Arc::new(AtomicUsize::new(0));
+        let inner = Buffer::new(Abandonable::new(inner.into_service(), epoch.clone()), bound);
+
+        Ok(Self::new_with_buffer(inner, epoch, scheme, authority))
+    }
+
+    /// Create a new consul client that sends its requests through `layer`
+    /// applied to `inner`
+    ///
+    /// This inserts middleware like tracing or retries between the client
+    /// and the transport, below the buffer so every request of the client
+    /// and its clones passes through it. The layered service must still be
+    /// an `HttpService<Bytes, ResponseBody = Bytes>` whose future is `Send`
+    /// and whose error converts into a boxed error, a layer that changes
+    /// the request or response body type can not be used.
+    pub fn layered<S, L>(
+        inner: S,
+        bound: usize,
+        scheme: String,
+        authority: String,
+        layer: L,
+    ) -> Result<Self, Error>
+    where
+        L: Layer<S, Service = T>,
+    {
+        Self::new(layer.layer(inner), bound, scheme, authority)
+    }
+
+    /// Create a new consul client along with a `WorkerMonitor` that
+    /// resolves once the buffer worker stops
+    ///
+    /// Once the worker has stopped every request fails with
+    /// `Error::ServiceClosed`, the monitor resolves with the reason, like
+    /// the message of a panic in the inner service. The worker is spawned
+    /// on the default executor, so this must be called within a runtime.
+    ///
+    /// Clients created with `from_factory` are not monitored, they replace
+    /// a closed buffer with a new one on their own.
+    pub fn new_monitored(
+        inner: T,
+        bound: usize,
+        scheme: String,
+        authority: String,
+    ) -> Result<(Self, WorkerMonitor), Error> {
+        let (mut executor, monitor) = MonitoredExecutor::new();
+        let epoch = Arc::new(AtomicUsize::new(0));
+        let service = Abandonable::new(inner.into_service(), epoch.clone());
+        let inner = Buffer::with_executor(service, bound, &mut executor);
+
+        Ok((
+            Self::new_with_buffer(inner, epoch, scheme, authority),
+            monitor,
+        ))
+    }
+
+    /// Create a new consul client that rebuilds its inner service with
+    /// `factory` once it has failed `failure_threshold` times in a row
+    ///
+    /// A failure is a request that fails with `Error::Inner` or a buffer
+    /// that has closed, any successful response resets the count. When the
+    /// threshold is reached a new service is created and a new buffer of
+    /// size `bound` is spawned for it, requests that are already in flight
+    /// still complete against the old service. Every clone of the client
+    /// switches to the new service on its next request.
+    ///
+    /// A `failure_threshold` of `0` is treated as `1`.
+    pub fn from_factory<F>(
+        mut factory: F,
+        bound: usize,
+        scheme: String,
+        authority: String,
+        failure_threshold: usize,
+    ) -> Result<Self, Error>
+    where
+        F: FnMut() -> T + Send + 'static,
+    {
+        let epoch = Arc::new(AtomicUsize::new(0));
+        let inner = Buffer::new(
+            Abandonable::new(factory().into_service(), epoch.clone()),
+            bound,
+        );
+
+        let reconnect = Reconnect {
+            factory: Box::new(factory),
+            bound,
+            failure_threshold: cmp::max(failure_threshold, 1),
+            failures: 0,
+            generation: 0,
+            epoch: epoch.clone(),
+            inner: inner.clone(),
+        };
+
+        let mut consul = Self::new_with_buffer(inner, epoch, scheme, authority);
+        consul.reconnect = Some((0, Arc::new(Mutex::new(reconnect))));
+
+        Ok(consul)
+    }
+
+    fn new_with_buffer(
+        inner: Inner<T>,
+        epoch: Arc<AtomicUsize>,
+        scheme: String,
+        authority: String,
+    ) -> Self {
+        Consul {
+            scheme,
+            authority,
+            max_redirects: 0,
+            compression_threshold: None,
+            raw_kv_values: false,
+            pretty: false,
+            capture_raw_body: false,
+            datacenter: None,
+            default_tags: Vec::new(),
+            kv_prefix: KvPrefix::default(),
+            token: None,
+            renewed_token: None,
+            request_id_header: HeaderName::from_static(REQUEST_ID),
+            request_id: None,
+            body_warning_threshold: None,
+            body_stats: Arc::new(BodyStats::default()),
+            metrics: Arc::new(Counters::default()),
+            agent_cache: Arc::new(Mutex::new(AgentCache::default())),
+            session_renewals: Arc::new(Mutex::new(HashMap::new())),
+            load_balance: LoadBalance::default(),
+            balancer: Arc::new(Mutex::new(Balancer::default())),
+            warnings_are_healthy: false,
+            tagged_address: None,
+            skipped_hostnames: Arc::new(AtomicUsize::new(0)),
+            clock: Arc::new(SystemClock),
+            reconnect: None,
+            epoch,
+            inner,
+        }
+    }
+
+    /// Follow up to `max_hops` redirects returned by Consul or a proxy
+    /// in front of it, by default redirects are not followed.
+    ///
+    /// Only redirects to the same host are followed, this allows a proxy
+    /// to upgrade the scheme to `https` while a redirect to any other host
+    /// fails with `Error::Redirect`.
+    pub fn with_follow_redirects(mut self, max_hops: u8) -> Self {
+        self.set_follow_redirects(max_hops);
+        self
+    }
+
+    /// Set the number of redirects to follow in place, see
+    /// `with_follow_redirects`
+    ///
+    /// Like every `set_*` method this only reconfigures this client,
+    /// clones made before the call keep their configuration.
+    pub fn set_follow_redirects(&mut self, max_hops: u8) -> &mut Self {
+        self.max_redirects = max_hops;
+        self
+    }
+
+    /// Gzip request bodies larger than `threshold` bytes and send them
+    /// with `Content-Encoding: gzip`, by default bodies are never
+    /// compressed.
+    ///
+    /// Not every Consul version or proxy in front of it accepts compressed
+    /// bodies, so this should only be enabled when it is known to be
+    /// supported. Bodies at or below the threshold are sent as is.
+    pub fn with_request_compression(mut self, threshold: usize) -> Self {
+        self.set_request_compression(Some(threshold));
+        self
+    }
+
+    /// Set the request compression threshold in place, `None` disables
+    /// compression, see `with_request_compression`
+    pub fn set_request_compression(&mut self, threshold: Option<usize>) -> &mut Self {
+        self.compression_threshold = threshold;
+        self
+    }
+
+    /// Treat the `value` of KV entries as the value itself rather than
+    /// base64 encoded, by default values are base64 decoded
+    ///
+    /// Consul always base64 encodes values, this is only needed for
+    /// backends that imitate Consul but return values as is, like some
+    /// emulators and test doubles. It affects the methods that decode
+    /// values, like `get_value` and `get_decoded`, `KVValue::decoded_value`
+    /// always decodes.
+    pub fn assume_raw_kv_values(mut self, enabled: bool) -> Self {
+        self.set_assume_raw_kv_values(enabled);
+        self
+    }
+
+    /// Set whether KV values are base64 encoded in place, see
+    /// `assume_raw_kv_values`
+    pub fn set_assume_raw_kv_values(&mut self, enabled: bool) -> &mut Self {
+        self.raw_kv_values = enabled;
+        self
+    }
+
+    /// Ask Consul to pretty print the JSON responses of read requests by
+    /// adding `?pretty`, by default responses are compact
+    ///
+    /// This does not change the results, it only makes captured traffic
+    /// easier to read when debugging through a logging proxy.
+    pub fn with_pretty(mut self, enabled: bool) -> Self {
+        self.set_pretty(enabled);
+        self
+    }
+
+    /// Set whether reads are pretty printed in place, see `with_pretty`
+    pub fn set_pretty(&mut self, enabled: bool) -> &mut Self {
+        self.pretty = enabled;
+        self
+    }
+
+    /// Keep the body of successful responses in `ConsulMeta::raw_body` for
+    /// the methods that return a `ConsulMeta`, by default it is dropped
+    ///
+    /// The body is kept alive for as long as the caller holds on to the
+    /// metadata on top of the parsed value, which roughly doubles the
+    /// memory used by large reads like a recursive KV read.
+    pub fn with_raw_body_capture(mut self, enabled: bool) -> Self {
+        self.set_raw_body_capture(enabled);
+        self
+    }
+
+    /// Set whether response bodies are captured in place, see
+    /// `with_raw_body_capture`
+    pub fn set_raw_body_capture(&mut self, enabled: bool) -> &mut Self {
+        self.capture_raw_body = enabled;
+        self
+    }
+
+    /// Send every request to `dc` rather than the datacenter of the agent
+    ///
+    /// Requests to the local agent, `/v1/agent/*`, are not affected and
+    /// neither are requests that set their own datacenter. Consul does not
+    /// reject unknown datacenters on every endpoint, use
+    /// `validate_datacenter` to check the name at startup.
+    pub fn with_datacenter(mut self, dc: impl Into<String>) -> Self {
+        self.set_datacenter(Some(dc.into()));
+        self
+    }
+
+    /// Set the datacenter requests are sent to in place, `None` uses the
+    /// datacenter of the agent, see `with_datacenter`
+    pub fn set_datacenter(&mut self, dc: Option<String>) -> &mut Self {
+        self.datacenter = dc;
+        self
+    }
+
+    /// Add `tags` to every service registered with `register_service`,
+    /// `register_many` or `register_with_ttl`
+    ///
+    /// The tags are appended to those of the registration and duplicates
+    /// are removed, keeping the first occurrence. Registrations sent as raw
+    /// bytes with `register` are not changed.
+    pub fn with_default_tags(mut self, tags: Vec<String>) -> Self {
+        self.set_default_tags(tags);
+        self
+    }
+
+    /// Set the tags added to every typed registration in place, see
+    /// `with_default_tags`
+    pub fn set_default_tags(&mut self, tags: Vec<String>) -> &mut Self {
+        self.default_tags = tags;
+        self
+    }
+
+    /// Prepend `prefix` to every KV key, so the client operates within a
+    /// namespace of keys like `apps/myservice/`
+    ///
+    /// The prefix is added to the keys of every KV method, including the
+    /// keys of the transactions sent by `set_if_flags`, `apply` and
+    /// `rename`, and removed from the keys of the values and key listings
+    /// read back. The prefix is prepended as is, include a trailing `/` to
+    /// separate it from the keys. Keys in transactions sent with `txn`
+    /// directly are not changed.
+    pub fn with_kv_prefix(mut self, prefix: &str) -> Self {
+        self.set_kv_prefix(Some(prefix.to_string()));
+        self
+    }
+
+    /// Set the KV key prefix in place, `None` uses keys as is, see
+    /// `with_kv_prefix`
+    pub fn set_kv_prefix(&mut self, prefix: Option<String>) -> &mut Self {
+        self.kv_prefix = KvPrefix(prefix);
+        self
+    }
+
+    /// Pick the instance returned by `discover_one` with `strategy`,
+    /// defaults to `LoadBalance::RoundRobin`
+    pub fn with_load_balance(mut self, strategy: LoadBalance) -> Self {
+        self.set_load_balance(strategy);
+        self
+    }
+
+    /// Set the strategy of `discover_one` in place, see `with_load_balance`
+    pub fn set_load_balance(&mut self, strategy: LoadBalance) -> &mut Self {
+        self.load_balance = strategy;
+        self
+    }
+
+    /// Count instances whose checks are warning as healthy in `discover`,
+    /// its variants and `service_health`, defaults to `false`
+    ///
+    /// Consul's `passing` filter excludes instances with a warning check,
+    /// which is the default here too. When enabled discovery drops the
+    /// filter and keeps the instances whose checks are all passing or
+    /// warning, see `HealthService::is_healthy_or_warning`, and a service
+    /// whose worst check is warning is reported as `HealthStatus::Passing`.
+    pub fn warnings_are_healthy(mut self, enabled: bool) -> Self {
+        self.set_warnings_are_healthy(enabled);
+        self
+    }
+
+    /// Set whether warning checks count as healthy in place, see
+    /// `warnings_are_healthy`
+    pub fn set_warnings_are_healthy(&mut self, enabled: bool) -> &mut Self {
+        self.warnings_are_healthy = enabled;
+        self
+    }
+
+    /// Prefer the `tagged` address of instances in `discover` and its
+    /// variants, see `HealthService::address`
+    ///
+    /// Instances without such an address use their service or node
+    /// address as before.
+    pub fn with_tagged_address(mut self, tagged: TaggedAddress) -> Self {
+        self.set_tagged_address(Some(tagged));
+        self
+    }
+
+    /// Set the tagged address to prefer in place, `None` uses the service
+    /// or node address, see `with_tagged_address`
+    pub fn set_tagged_address(&mut self, tagged: Option<TaggedAddress>) -> &mut Self {
+        self.tagged_address = tagged;
+        self
+    }
+
+    /// Send `token` as the `X-Consul-Token` of every request
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.set_token(Some(token.into()));
+        self
+    }
+
+    /// Set the ACL token in place, `None` sends requests without a token,
+    /// see `with_token`
+    ///
+    /// This stops the client from following a token renewed by
+    /// `with_auto_renew_token`.
+    pub fn set_token(&mut self, token: Option<String>) -> &mut Self {
+        self.token = token;
+        self.renewed_token = None;
+        self
+    }
+
+    /// Send request ids set with `with_request_id` in the `name` header,
+    /// by default `X-Request-ID` is used
+    pub fn with_request_id_header(mut self, name: HeaderName) -> Self {
+        self.set_request_id_header(name);
+        self
+    }
+
+    /// Set the header request ids are sent in in place, see
+    /// `with_request_id_header`
+    pub fn set_request_id_header(&mut self, name: HeaderName) -> &mut Self {
+        self.request_id_header = name;
+        self
+    }
+
+    /// Get a clone of the client that sends `id` as the request id of
+    /// every request, to correlate them with the trace of the caller
+    ///
+    /// ```rust,ignore
+    /// client.with_request_id(trace_id).get("my-key")
+    /// ```
+    ///
+    /// The clone shares the buffer of the client so this is cheap to call
+    /// for every request.
+    pub fn with_request_id(&self, id: impl Into<String>) -> Self {
+        let mut client = self.clone();
+        client.request_id = Some(id.into());
+        client
+    }
+
+    /// Count every response with a body larger than `threshold` bytes,
+    /// the count is available through `oversized_bodies`
+    ///
+    /// This surfaces values that keep growing, like a KV value that is
+    /// getting huge, before they become a problem. The responses are still
+    /// returned as is.
+    pub fn with_body_size_warning(mut self, threshold: usize) -> Self {
+        self.set_body_size_warning(Some(threshold));
+        self
+    }
+
+    /// Set the body size warning threshold in place, `None` stops
+    /// counting oversized bodies, see `with_body_size_warning`
+    pub fn set_body_size_warning(&mut self, threshold: Option<usize>) -> &mut Self {
+        self.body_warning_threshold = threshold;
+        self
+    }
+
+    /// The size in bytes of the largest response body received by this
+    /// client or any of its clones
+    pub fn largest_body_size(&self) -> usize {
+        self.body_stats.largest.load(Ordering::Relaxed)
+    }
+
+    /// The number of response bodies larger than the threshold set with
+    /// `with_body_size_warning` received by this client or any of its
+    /// clones
+    pub fn oversized_bodies(&self) -> usize {
+        self.body_stats.oversized.load(Ordering::Relaxed)
+    }
+
+    /// The number of instances `discover` and its variants skipped, by
+    /// this client or any of its clones, because their address is a
+    /// hostname rather than an ip
+    pub fn skipped_hostnames(&self) -> usize {
+        self.skipped_hostnames.load(Ordering::Relaxed)
+    }
+
+    /// Use `clock` rather than the system clock to compare against times
+    /// reported by Consul
+    ///
+    /// This makes the age of sessions in `destroy_expired_sessions` and the
+    /// remaining lifetime of a token in `with_auto_renew_token` testable
+    /// without waiting, see `Clock`.
+    pub fn with_clock(mut self, clock: impl Clock) -> Self {
+        self.set_clock(clock);
+        self
+    }
+
+    /// Set the clock in place, see `with_clock`
+    pub fn set_clock(&mut self, clock: impl Clock) -> &mut Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// The full URL a `GET` of `path` is sent to, for logging
+    ///
+    /// The scheme, authority and the query parameters added by the client,
+    /// like the datacenter set with `with_datacenter`, are included. The
+    /// ACL token is sent as a header and never appears in the URL, a
+    /// `token` parameter in `path` itself is removed. Nothing is sent.
+    pub fn resolve_url(&self, path: &str) -> String {
+        let url = self.path_and_query(path, &Method::GET);
+
+        let url = match url.find('?') {
+            Some(start) => {
+                let params = url[start + 1..]
+                    .split('&')
+                    .filter(|param| *param != "token" && !param.starts_with("token="))
+                    .collect::<Vec<_>>();
+
+                if params.is_empty() {
+                    url[..start].to_string()
+                } else {
+                    format!("{}?{}", &url[..start], params.join("&"))
+                }
+            }
+            None => url,
+        };
+
+        format!("{}://{}{}", self.scheme, self.authority, url)
+    }
+
+    /// Abandon every request that is still queued in the buffer
+    ///
+    /// Requests sent by this client or any of its clones that are waiting
+    /// in the buffer fail with `Error::Abandoned` without being sent, while
+    /// requests already handed to the inner service complete as usual.
+    /// Requests made after this call are not affected. This allows a client
+    /// to switch configuration, like a rotated token, without requests
+    /// built with the old one being sent after the switch.
+    pub fn abandon_inflight(&mut self) {
+        self.epoch.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// A snapshot of the requests sent by this client and its clones,
+    /// labeled by the area of the API they were sent to
+    pub fn metrics(&self) -> ConsulMetrics {
+        self.metrics.snapshot()
+    }
+
+    /// The scheme used to talk to consul
+    pub fn scheme(&self) -> &str {
+        &self.scheme
+    }
+
+    /// The authority of the consul agent this client talks to
+    pub fn authority(&self) -> &str {
+        &self.authority
+    }
+}
+
+/// The sizes of the response bodies received by a client, shared
+/// between the client and its clones.
+#[derive(Debug, Default)]
+pub(crate) struct BodyStats {
+    pub(crate) largest: AtomicUsize,
+    pub(crate) oversized: AtomicUsize,
+}
+
+impl BodyStats {
+    pub(crate) fn record(&self, len: usize, threshold: Option<usize>) {
+        self.largest.fetch_max(len, Ordering::Relaxed);
+
+        if threshold.is_some_and(|threshold| len > threshold) {
+            self.oversized.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Values read from the local agent that do not change while it runs,
+/// shared by a client and its clones.
+#[derive(Debug, Default)]
+pub(crate) struct AgentCache {
+    pub(crate) node_name: Option<String>,
+}
@@ -0,0 +1,26 @@
+//! The wall clock used by the client
+
+use std::time::SystemTime;
+
+/// A source of the current wall clock time
+///
+/// The client compares times reported by Consul, like the create time of a
+/// session or the expiration time of a token, against this clock. Timers,
+/// like the heartbeat of `register_with_ttl`, run on the `tokio_timer`
+/// clock of the runtime instead, which tests can replace with
+/// `tokio_timer::clock::with_default`.
+pub trait Clock: Send + Sync + 'static {
+    /// The current time
+    fn now(&self) -> SystemTime;
+}
+
+/// The system clock, used unless another one is set with
+/// `Consul::with_clock`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
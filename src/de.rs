@@ -0,0 +1,13 @@
+//! Helpers for deserializing Consul responses
+
+use serde::{Deserialize, Deserializer};
+
+/// Deserialize a `null` as the default value of `T`, Consul sends `null`
+/// for empty lists and maps
+pub(crate) fn null_to_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::deserialize(deserializer)?.unwrap_or_default())
+}
@@ -0,0 +1,158 @@
+//! Durations in the formats Consul expects
+
+use crate::Error;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::convert::TryFrom;
+use std::fmt;
+use std::time::Duration;
+
+/// A `Duration` that is sent to and read from Consul as a Go duration
+/// string, like `30s` or `1m30s`
+///
+/// Consul rejects durations without a unit, `ConsulDuration` always
+/// serializes with one.
+///
+/// ```
+/// use std::time::Duration;
+/// use tower_consul::ConsulDuration;
+///
+/// let ttl: ConsulDuration = "1m30s".parse().unwrap();
+/// assert_eq!(Duration::from(ttl), Duration::from_secs(90));
+/// assert_eq!(ttl.to_string(), "1m30s");
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ConsulDuration(pub Duration);
+
+impl From<Duration> for ConsulDuration {
+    fn from(duration: Duration) -> Self {
+        ConsulDuration(duration)
+    }
+}
+
+impl From<ConsulDuration> for Duration {
+    fn from(duration: ConsulDuration) -> Self {
+        duration.0
+    }
+}
+
+impl fmt::Display for ConsulDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.0.as_secs();
+        let nanos = self.0.subsec_nanos();
+
+        if secs == 0 {
+            return match nanos {
+                0 => write!(f, "0s"),
+                n if n % 1_000_000 == 0 => write!(f, "{}ms", n / 1_000_000),
+                n if n % 1_000 == 0 => write!(f, "{}us", n / 1_000),
+                n => write!(f, "{}ns", n),
+            };
+        }
+
+        let (hours, minutes, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+        if hours > 0 {
+            write!(f, "{}h", hours)?;
+        }
+        if hours > 0 || minutes > 0 {
+            write!(f, "{}m", minutes)?;
+        }
+        write!(f, "{}", secs)?;
+        if nanos > 0 {
+            let fraction = format!("{:09}", nanos);
+            write!(f, ".{}", fraction.trim_end_matches('0'))?;
+        }
+        write!(f, "s")
+    }
+}
+
+impl std::str::FromStr for ConsulDuration {
+    type Err = Error;
+
+    /// Parse a Go duration string, a sequence of decimal numbers each
+    /// with a unit of `h`, `m`, `s`, `ms`, `us` or `ns`
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let invalid = || Error::InvalidArgument(format!("invalid duration {:?}", s));
+
+        if s == "0" {
+            return Ok(ConsulDuration::default());
+        }
+        if s.is_empty() {
+            return Err(invalid());
+        }
+
+        let mut nanos: u128 = 0;
+        let mut rest = s;
+        while !rest.is_empty() {
+            let number_len = rest
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .ok_or_else(invalid)?;
+            let (number, tail) = rest.split_at(number_len);
+            let unit_len = tail
+                .find(|c: char| c.is_ascii_digit() || c == '.')
+                .unwrap_or(tail.len());
+            let (unit, tail) = tail.split_at(unit_len);
+
+            let unit: u128 = match unit {
+                "ns" => 1,
+                "us" | "\u{b5}s" | "\u{3bc}s" => 1_000,
+                "ms" => 1_000_000,
+                "s" => 1_000_000_000,
+                "m" => 60_000_000_000,
+                "h" => 3_600_000_000_000,
+                _ => return Err(invalid()),
+            };
+
+            let (whole, fraction) = match number.find('.') {
+                Some(dot) => (&number[..dot], &number[dot + 1..]),
+                None => (number, ""),
+            };
+            if whole.is_empty() && fraction.is_empty() || fraction.contains('.') {
+                return Err(invalid());
+            }
+
+            let whole: u128 = if whole.is_empty() {
+                0
+            } else {
+                whole.parse().map_err(|_| invalid())?
+            };
+            let mut scale = unit;
+            for digit in fraction.bytes() {
+                scale /= 10;
+                nanos += u128::from(digit - b'0') * scale;
+            }
+            nanos += whole.checked_mul(unit).ok_or_else(invalid)?;
+            rest = tail;
+        }
+
+        let secs = u64::try_from(nanos / 1_000_000_000).map_err(|_| invalid())?;
+        Ok(ConsulDuration(Duration::new(
+            secs,
+            (nanos % 1_000_000_000) as u32,
+        )))
+    }
+}
+
+impl Serialize for ConsulDuration {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ConsulDuration {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Format a duration as milliseconds in the format Consul expects
+pub(crate) fn duration_ms(duration: Duration) -> String {
+    let ms = duration.as_secs() * 1000 + u64::from(duration.subsec_millis());
+    format!("{}ms", ms)
+}
@@ -0,0 +1,297 @@
+//! The error returned by the client
+
+use crate::BoxError;
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize, Serializer};
+use std::borrow::Cow;
+use std::cmp;
+use std::fmt;
+use std::string::FromUtf8Error;
+use tower_buffer::error::SpawnError;
+
+#[derive(Debug)]
+/// The Error returned by the client
+pub enum Error {
+    /// The requested resource does not exist
+    NotFound,
+    /// The consul http request returned a `4xx` response that is not
+    /// a `404`
+    ConsulClient(String),
+    /// The consul http request returned a `5xx` response
+    ConsulServer(String),
+    /// The servers are electing a leader and can not answer, this usually
+    /// resolves within a few seconds and the request may be retried
+    NoClusterLeader,
+    /// The inner service returned an error
+    Inner(Box<dyn ::std::error::Error + Send>),
+    /// There was an error creating and reading Response/Requests
+    Http(http::Error),
+    /// The error returned if the json parsing has failed
+    Json(serde_json::Error),
+    /// The body of a response is not the json expected, `excerpt`
+    /// contains the part of the body around the failure
+    JsonBody {
+        /// The underlying serde error
+        error: serde_json::Error,
+        /// The body surrounding the position of the failure
+        excerpt: String,
+    },
+    /// Error parsing the response string as utf8
+    StringUtf8(FromUtf8Error),
+    /// The value of a key is not valid base64
+    Base64 {
+        /// The key the value was read from
+        key: String,
+        /// The underlying decode error
+        error: base64::DecodeError,
+    },
+    /// The user provided decode hook failed
+    Decode(BoxError),
+    /// Error attempting to spawn the Buffer service
+    SpawnError,
+    /// A blocking query response did not contain a valid
+    /// `X-Consul-Index` header
+    MissingIndex,
+    /// One or more service registrations failed, listed by service id
+    Registration(Vec<(String, Error)>),
+    /// Consul redirected to a location that could not be followed
+    Redirect(String),
+    /// An argument passed to the client was invalid, the request was
+    /// never sent
+    InvalidArgument(String),
+    /// The buffer has reached its `bound`, the request was shed and
+    /// may be retried once in-flight requests complete
+    BufferFull,
+    /// The buffer worker has shut down and will not accept any more
+    /// requests, the client must be recreated
+    ServiceClosed,
+    /// A read-modify-write of the key kept conflicting with concurrent
+    /// writes and gave up
+    CasConflict(String),
+    /// A response added up to more than the given number of bytes
+    BodyTooLarge(usize),
+    /// The request was still queued when `Consul::abandon_inflight` was
+    /// called and was never sent
+    Abandoned,
+    /// The condition waited for was not met within the given time
+    Timeout,
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+
+impl From<FromUtf8Error> for Error {
+    fn from(e: FromUtf8Error) -> Self {
+        Error::StringUtf8(e)
+    }
+}
+
+impl From<http::Error> for Error {
+    fn from(e: http::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+impl From<SpawnError> for Error {
+    fn from(_: SpawnError) -> Self {
+        Error::SpawnError
+    }
+}
+
+impl From<Box<dyn std::error::Error + Send + Sync>> for Error {
+    fn from(e: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        Error::Inner(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NotFound => write!(f, "resource not found"),
+            Error::ConsulClient(body) => write!(f, "consul client error: {}", body),
+            Error::ConsulServer(body) => write!(f, "consul server error: {}", body),
+            Error::NoClusterLeader => write!(f, "no cluster leader"),
+            Error::Inner(e) => write!(f, "inner service error: {}", e),
+            Error::Http(e) => write!(f, "http error: {}", e),
+            Error::Json(e) => write!(f, "json error: {}", e),
+            Error::JsonBody { error, excerpt } => {
+                write!(f, "json error: {} near `{}`", error, excerpt)
+            }
+            Error::StringUtf8(e) => write!(f, "utf8 error: {}", e),
+            Error::Base64 { key, error } => write!(f, "base64 error in {}: {}", key, error),
+            Error::Decode(e) => write!(f, "decode error: {}", e),
+            Error::SpawnError => write!(f, "unable to spawn the buffer worker"),
+            Error::MissingIndex => write!(f, "missing X-Consul-Index header"),
+            Error::Registration(failed) => {
+                let ids = failed.iter().map(|(id, _)| id.as_str()).collect::<Vec<_>>();
+                write!(f, "failed to register services: {}", ids.join(", "))
+            }
+            Error::Redirect(location) => write!(f, "unable to follow redirect to {}", location),
+            Error::InvalidArgument(msg) => write!(f, "invalid argument: {}", msg),
+            Error::BufferFull => write!(f, "buffer is full"),
+            Error::ServiceClosed => write!(f, "buffer worker has closed"),
+            Error::CasConflict(key) => write!(f, "too many conflicting writes to {}", key),
+            Error::BodyTooLarge(limit) => write!(f, "response larger than {} bytes", limit),
+            Error::Abandoned => write!(f, "request abandoned before it was sent"),
+            Error::Timeout => write!(f, "timed out"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Inner(e) => Some(&**e),
+            Error::Http(e) => Some(e),
+            Error::Json(e) => Some(e),
+            Error::JsonBody { error, .. } => Some(error),
+            Error::StringUtf8(e) => Some(e),
+            Error::Base64 { error, .. } => Some(error),
+            Error::Decode(e) => Some(&**e),
+            _ => None,
+        }
+    }
+}
+
+impl Error {
+    /// The name of the variant, used as the `kind` of the serialized error
+    fn kind(&self) -> &'static str {
+        match self {
+            Error::NotFound => "not_found",
+            Error::ConsulClient(_) => "consul_client",
+            Error::ConsulServer(_) => "consul_server",
+            Error::NoClusterLeader => "no_cluster_leader",
+            Error::Inner(_) => "inner",
+            Error::Http(_) => "http",
+            Error::Json(_) => "json",
+            Error::JsonBody { .. } => "json_body",
+            Error::StringUtf8(_) => "string_utf8",
+            Error::Base64 { .. } => "base64",
+            Error::Decode(_) => "decode",
+            Error::SpawnError => "spawn_error",
+            Error::MissingIndex => "missing_index",
+            Error::Registration(_) => "registration",
+            Error::Redirect(_) => "redirect",
+            Error::InvalidArgument(_) => "invalid_argument",
+            Error::BufferFull => "buffer_full",
+            Error::ServiceClosed => "service_closed",
+            Error::CasConflict(_) => "cas_conflict",
+            Error::BodyTooLarge(_) => "body_too_large",
+            Error::Abandoned => "abandoned",
+            Error::Timeout => "timeout",
+        }
+    }
+}
+
+/// Errors serialize to a map with the `kind` of the error, its `message`
+/// and the fields of the variant, for structured logs
+///
+/// Response bodies and messages are truncated to 256 bytes so a large or
+/// sensitive body is not logged whole.
+///
+/// ```
+/// use tower_consul::Error;
+///
+/// let json = serde_json::to_value(&Error::CasConflict("config".into())).unwrap();
+/// assert_eq!(json["kind"], "cas_conflict");
+/// assert_eq!(json["key"], "config");
+/// ```
+impl Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("kind", self.kind())?;
+        map.serialize_entry("message", &truncate(&self.to_string()))?;
+
+        match self {
+            Error::ConsulClient(body) | Error::ConsulServer(body) => {
+                map.serialize_entry("body", &truncate(body))?;
+            }
+            Error::JsonBody { excerpt, .. } => {
+                map.serialize_entry("excerpt", &truncate(excerpt))?;
+            }
+            Error::Base64 { key, .. } | Error::CasConflict(key) => {
+                map.serialize_entry("key", key)?;
+            }
+            Error::Registration(failed) => {
+                let failed = failed
+                    .iter()
+                    .map(|(id, error)| RegistrationFailure { id, error })
+                    .collect::<Vec<_>>();
+                map.serialize_entry("failed", &failed)?;
+            }
+            Error::Redirect(location) => map.serialize_entry("location", location)?,
+            Error::BodyTooLarge(limit) => map.serialize_entry("limit", limit)?,
+            _ => {}
+        }
+
+        map.end()
+    }
+}
+
+#[derive(Serialize)]
+struct RegistrationFailure<'a> {
+    id: &'a str,
+    error: &'a Error,
+}
+
+/// The longest body or message included in a serialized error.
+const MAX_SERIALIZED: usize = 256;
+
+fn truncate(s: &str) -> Cow<'_, str> {
+    if s.len() <= MAX_SERIALIZED {
+        return Cow::Borrowed(s);
+    }
+
+    let mut end = MAX_SERIALIZED;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    Cow::Owned(format!("{}...", &s[..end]))
+}
+
+/// The body of the `5xx` Consul responds with while there is no leader.
+const NO_CLUSTER_LEADER: &str = "No cluster leader";
+
+/// The error for a `5xx` response with `body`.
+pub(crate) fn server_error(body: &[u8]) -> Error {
+    let body = String::from_utf8_lossy(body).into_owned();
+
+    if body.contains(NO_CLUSTER_LEADER) {
+        Error::NoClusterLeader
+    } else {
+        Error::ConsulServer(body)
+    }
+}
+
+/// The number of bytes on either side of a json error to include in
+/// its excerpt.
+const EXCERPT_CONTEXT: usize = 32;
+
+pub(crate) fn from_json<R>(body: &[u8]) -> Result<R, Error>
+where
+    for<'de> R: Deserialize<'de>,
+{
+    serde_json::from_slice(body).map_err(|error| {
+        // serde reports 1 based lines and columns rather than offsets.
+        let offset = body
+            .split(|b| *b == b'\n')
+            .take(error.line().saturating_sub(1))
+            .map(|line| line.len() + 1)
+            .sum::<usize>()
+            + error.column().saturating_sub(1);
+
+        let offset = cmp::min(offset, body.len());
+        let start = offset.saturating_sub(EXCERPT_CONTEXT);
+        let end = cmp::min(offset + EXCERPT_CONTEXT, body.len());
+        let excerpt = String::from_utf8_lossy(&body[start..end]).into_owned();
+
+        Error::JsonBody { error, excerpt }
+    })
+}
@@ -0,0 +1,732 @@
+//! Health of services and their instances
+
+use crate::{null_to_default, BoxError, Consul, ConsulMeta, Error, Query, Weights};
+use bytes::Bytes;
+use futures::future;
+use futures::future::Either;
+use futures::stream::Stream;
+use futures::Future;
+use http::header::{HeaderValue, CACHE_CONTROL};
+use http::{Method, Request};
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tower_http_util::service::HttpService;
+
+impl<T> Consul<T>
+where
+    T: HttpService<Bytes, ResponseBody = Bytes> + Send + 'static,
+    T::Future: Send + 'static,
+    T::Error: Into<BoxError> + Send + Sync,
+{
+    /// Get the instances of a service along with their health checks
+    ///
+    /// The request is always sent to `/v1/health/service/:service`, the
+    /// options of `query` including its tags are query parameters.
+    pub fn health_service(
+        &mut self,
+        service: &str,
+        query: HealthQuery,
+    ) -> impl Future<Item = Vec<HealthService>, Error = Error> {
+        let request = match self.health_service_request(service, &query) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        Either::B(self.call(request))
+    }
+
+    fn health_service_request(
+        &mut self,
+        service: &str,
+        query: &HealthQuery,
+    ) -> Result<Request<Bytes>, Error> {
+        let url = query.append_to(&format!("/v1/health/service/{}", service));
+        let mut request = self.build(&url, Method::GET, Bytes::new())?;
+
+        if let Some(max_age) = query.max_age {
+            let value = format!("max-age={}", max_age.as_secs());
+            let value = HeaderValue::from_str(&value).expect("max-age is a valid header");
+            request.headers_mut().insert(CACHE_CONTROL, value);
+        }
+
+        Ok(request)
+    }
+
+    /// Get the node and service checks of a node
+    ///
+    /// An unknown node has no checks.
+    pub fn health_node(
+        &mut self,
+        node: &str,
+    ) -> impl Future<Item = Vec<HealthCheck>, Error = Error> {
+        let url = format!("/v1/health/node/{}", node);
+        let request = match self.build(&url, Method::GET, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        Either::B(self.call(request))
+    }
+
+    /// Get the health of a service, the worst status of any check of
+    /// any of its instances
+    ///
+    /// A service without instances is `HealthStatus::Unknown`.
+    pub fn service_health(
+        &mut self,
+        service: &str,
+    ) -> impl Future<Item = HealthStatus, Error = Error> {
+        let warnings_are_healthy = self.warnings_are_healthy;
+
+        self.health_service(service, HealthQuery::default())
+            .map(move |instances| {
+                instances
+                    .iter()
+                    .map(HealthService::status)
+                    .map(|status| match status {
+                        HealthStatus::Warning if warnings_are_healthy => HealthStatus::Passing,
+                        status => status,
+                    })
+                    .fold(None, |worst: Option<HealthStatus>, status| match worst {
+                        Some(worst) => Some(worst.worst(status)),
+                        None => Some(status),
+                    })
+                    .unwrap_or(HealthStatus::Unknown)
+            })
+    }
+
+    /// Get the health of each of `services` concurrently, keyed by service
+    ///
+    /// A service whose health could not be read is `HealthStatus::Unknown`
+    /// rather than failing the whole call.
+    pub fn services_health(
+        &mut self,
+        services: &[&str],
+    ) -> impl Future<Item = HashMap<String, HealthStatus>, Error = Error> {
+        let healths = services
+            .iter()
+            .map(|service| {
+                let service = service.to_string();
+                self.service_health(&service).then(|res| {
+                    let status = res.unwrap_or(HealthStatus::Unknown);
+                    Ok((service, status))
+                })
+            })
+            .collect::<Vec<_>>();
+
+        future::join_all(healths).map(|healths| healths.into_iter().collect())
+    }
+
+    /// Get the addresses of every passing instance of a service
+    ///
+    /// The address of an instance is picked with `HealthService::address`,
+    /// preferring the tagged address set with `with_tagged_address`.
+    /// Instances whose address is a hostname rather than an ip are skipped
+    /// since resolving them would block, they are counted in
+    /// `skipped_hostnames`. Use `discover_hosts` for services registered
+    /// with hostnames.
+    pub fn discover(
+        &mut self,
+        service: &str,
+    ) -> impl Future<Item = Vec<SocketAddr>, Error = Error> {
+        self.discover_query(service, HealthQuery::default())
+    }
+
+    /// Get the host and port of every passing instance of a service, like
+    /// `discover` but without parsing the address
+    ///
+    /// This keeps instances registered with a hostname, which the caller
+    /// resolves with the resolver of its choice.
+    pub fn discover_hosts(
+        &mut self,
+        service: &str,
+    ) -> impl Future<Item = Vec<(String, u16)>, Error = Error> {
+        let warnings_are_healthy = self.warnings_are_healthy;
+        let tagged = self.tagged_address;
+        let query = HealthQuery {
+            passing: !warnings_are_healthy,
+            ..HealthQuery::default()
+        };
+
+        self.health_service(service, query).map(move |instances| {
+            healthy(&instances, warnings_are_healthy)
+                .map(|instance| {
+                    let (host, port) = instance.address(tagged);
+                    (host.to_string(), port)
+                })
+                .collect()
+        })
+    }
+
+    /// Get the address of one passing instance of a service, picked with
+    /// the strategy set with `with_load_balance`
+    ///
+    /// The instances are fetched like `discover` on every call, the state
+    /// of round robin and least recently used is shared by the client and
+    /// its clones. Fails with `Error::NotFound` when the service has no
+    /// passing instance.
+    pub fn discover_one(&mut self, service: &str) -> impl Future<Item = SocketAddr, Error = Error> {
+        let strategy = self.load_balance;
+        let balancer = self.balancer.clone();
+        let service = service.to_string();
+
+        self.discover(&service).and_then(move |addrs| {
+            let mut balancer = balancer.lock().unwrap();
+            balancer
+                .pick(strategy, &service, addrs)
+                .ok_or(Error::NotFound)
+        })
+    }
+
+    /// Get the addresses of every passing instance of a service that
+    /// also matches `query`, like `discover`
+    ///
+    /// `passing` is always set, which allows routing to the healthy
+    /// instances selected by a `filter` such as `Service.Meta.version == "2"`.
+    /// With `warnings_are_healthy` instances whose checks are warning are
+    /// included, they are then filtered by the client rather than Consul.
+    pub fn discover_query(
+        &mut self,
+        service: &str,
+        query: HealthQuery,
+    ) -> impl Future<Item = Vec<SocketAddr>, Error = Error> {
+        let warnings_are_healthy = self.warnings_are_healthy;
+        let tagged = self.tagged_address;
+        let skipped = self.skipped_hostnames.clone();
+        let query = HealthQuery {
+            passing: !warnings_are_healthy,
+            ..query
+        };
+
+        self.health_service(service, query)
+            .map(move |instances| healthy_addrs(&instances, warnings_are_healthy, tagged, &skipped))
+    }
+
+    /// Get the addresses of every passing instance of a service named the
+    /// way Consul DNS names it
+    ///
+    /// Both the standard `[tag.]<service>.service[.<datacenter>].<domain>`
+    /// form, like `v1.web.service.dc1.consul`, and the RFC 2782
+    /// `_<service>._<tag>.service[.<datacenter>].<domain>` form are
+    /// accepted, with a `_tcp` tag meaning no tag as in Consul DNS. The
+    /// domain is not checked so agents with a custom domain work too. Any
+    /// other name, like a `.node.` or `.query.` lookup, fails with
+    /// `Error::InvalidArgument`.
+    ///
+    /// Like `discover` only passing instances are returned, Consul DNS also
+    /// returns instances with a warning unless `only_passing` is set.
+    pub fn discover_by_dns_name(
+        &mut self,
+        name: &str,
+    ) -> impl Future<Item = Vec<SocketAddr>, Error = Error> {
+        let name = match DnsName::parse(name) {
+            Ok(name) => name,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        let query = HealthQuery {
+            dc: name.datacenter,
+            tags: name.tag.into_iter().collect(),
+            ..HealthQuery::default()
+        };
+
+        Either::B(self.discover_query(&name.service, query))
+    }
+
+    /// Get the addresses of every passing instance of a service like
+    /// `discover`, served from the cache of the local agent
+    ///
+    /// The agent answers from its cache while the cached result is at most
+    /// `max_age` old, otherwise it reads from the servers and caches the
+    /// result for the next call. This keeps repeated discovery local to the
+    /// agent, `ConsulMeta::cache_hit` tells whether the cache was used.
+    pub fn discover_cached(
+        &mut self,
+        service: &str,
+        max_age: Duration,
+    ) -> impl Future<Item = (Vec<SocketAddr>, ConsulMeta), Error = Error> {
+        let warnings_are_healthy = self.warnings_are_healthy;
+        let tagged = self.tagged_address;
+        let skipped = self.skipped_hostnames.clone();
+        let query = HealthQuery {
+            passing: !warnings_are_healthy,
+            max_age: Some(max_age),
+            ..HealthQuery::default()
+        };
+        let request = match self.health_service_request(service, &query) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        Either::B(self.call_with_meta(request).map(
+            move |(instances, meta): (Vec<HealthService>, _)| {
+                let addrs = healthy_addrs(&instances, warnings_are_healthy, tagged, &skipped);
+                (addrs, meta)
+            },
+        ))
+    }
+
+    /// Watch the healthy and unhealthy instances of a service
+    ///
+    /// The returned stream issues blocking queries against
+    /// `/v1/health/service/:service` and yields the instances every time
+    /// the `X-Consul-Index` of the service advances. Each blocking query
+    /// is held by Consul for at most `wait` before it is reissued.
+    ///
+    /// An advancing index does not guarantee that the instances changed,
+    /// the health index also moves when unrelated checks on the same nodes
+    /// update, so consecutive items may be equal.
+    pub fn watch_service(
+        &mut self,
+        service: &str,
+        wait: Duration,
+    ) -> impl Stream<Item = Vec<HealthService>, Error = Error> {
+        let url = format!("/v1/health/service/{}", service);
+        self.watch_url(url, wait)
+    }
+}
+
+/// Options used to filter the instances returned by
+/// `Consul::health_service`
+#[derive(Debug, Clone, Default)]
+pub struct HealthQuery {
+    /// The datacenter to query, defaults to the datacenter of the agent
+    pub dc: Option<String>,
+    /// Only return instances that have all of these tags
+    ///
+    /// Each tag is sent as its own `?tag=` parameter, the form Consul
+    /// documents, rather than in the path, which only ever held one tag.
+    pub tags: Vec<String>,
+    /// Only return instances where every check is passing
+    pub passing: bool,
+    /// A filter expression applied by Consul to the instances, combined
+    /// with `passing` an instance must match both
+    pub filter: Option<String>,
+    /// Serve the read from the cache of the local agent, accepting a
+    /// cached result at most this old. Consul only takes whole seconds so
+    /// the age is rounded down.
+    pub max_age: Option<Duration>,
+}
+
+impl HealthQuery {
+    fn append_to(&self, path: &str) -> String {
+        let mut query = Query::default();
+        query.opt("dc", self.dc.as_ref());
+        for tag in &self.tags {
+            query.param("tag", tag);
+        }
+        if self.passing {
+            query.flag("passing");
+        }
+        query.opt("filter", self.filter.as_ref());
+        if self.max_age.is_some() {
+            query.flag("cached");
+        }
+        query.append_to(path)
+    }
+}
+
+/// The healthy instances, counting warning checks as healthy if
+/// `warnings_are_healthy` is set.
+fn healthy(
+    instances: &[HealthService],
+    warnings_are_healthy: bool,
+) -> impl Iterator<Item = &HealthService> {
+    instances.iter().filter(move |instance| {
+        if warnings_are_healthy {
+            instance.is_healthy_or_warning()
+        } else {
+            instance.is_healthy()
+        }
+    })
+}
+
+/// The addresses of the healthy instances, counting those that are not
+/// an ip in `skipped`.
+fn healthy_addrs(
+    instances: &[HealthService],
+    warnings_are_healthy: bool,
+    tagged: Option<TaggedAddress>,
+    skipped: &AtomicUsize,
+) -> Vec<SocketAddr> {
+    healthy(instances, warnings_are_healthy)
+        .filter_map(|instance| {
+            let addr = instance.socket_addr_tagged(tagged);
+            if addr.is_none() {
+                skipped.fetch_add(1, Ordering::Relaxed);
+            }
+            addr
+        })
+        .collect()
+}
+
+/// The tagged address of a service or node to prefer over its default
+/// address, see `Consul::with_tagged_address`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TaggedAddress {
+    /// The `lan` address, reachable from within the datacenter
+    Lan,
+    /// The `wan` address, reachable from other datacenters
+    Wan,
+}
+
+impl TaggedAddress {
+    /// The key of the address in `TaggedAddresses`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TaggedAddress::Lan => "lan",
+            TaggedAddress::Wan => "wan",
+        }
+    }
+}
+
+/// How `Consul::discover_one` picks one of the passing instances of a
+/// service
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LoadBalance {
+    /// Pick an instance at random
+    Random,
+    /// Cycle through the instances in address order, across calls
+    #[default]
+    RoundRobin,
+    /// Pick the instance returned the longest ago, instances that were
+    /// never returned come first
+    LeastRecentlyUsed,
+}
+
+/// The state of `Consul::discover_one`, shared by a client and its clones.
+#[derive(Debug, Default)]
+pub(crate) struct Balancer {
+    picks: u64,
+    next: HashMap<String, usize>,
+    last_used: HashMap<String, HashMap<SocketAddr, u64>>,
+}
+
+impl Balancer {
+    fn pick(
+        &mut self,
+        strategy: LoadBalance,
+        service: &str,
+        mut addrs: Vec<SocketAddr>,
+    ) -> Option<SocketAddr> {
+        if addrs.is_empty() {
+            return None;
+        }
+
+        // Consul does not promise an order, sorting keeps round robin from
+        // skipping or repeating instances when it changes.
+        addrs.sort();
+        self.picks += 1;
+
+        let addr = match strategy {
+            LoadBalance::Random => {
+                let random = RandomState::new().hash_one(self.picks);
+                addrs[random as usize % addrs.len()]
+            }
+            LoadBalance::RoundRobin => {
+                let next = self.next.entry(service.to_string()).or_insert(0);
+                let addr = addrs[*next % addrs.len()];
+                *next = next.wrapping_add(1);
+                addr
+            }
+            LoadBalance::LeastRecentlyUsed => {
+                let last_used = self.last_used.entry(service.to_string()).or_default();
+                // Forget instances that are gone so the map does not grow.
+                last_used.retain(|addr, _| addrs.contains(addr));
+
+                let addr = *addrs
+                    .iter()
+                    .min_by_key(|addr| last_used.get(addr).copied().unwrap_or(0))
+                    .expect("addrs is not empty");
+                last_used.insert(addr, self.picks);
+                addr
+            }
+        };
+
+        Some(addr)
+    }
+}
+
+/// A service lookup in the form used by Consul DNS.
+#[derive(Debug, PartialEq, Eq)]
+struct DnsName {
+    service: String,
+    tag: Option<String>,
+    datacenter: Option<String>,
+}
+
+impl DnsName {
+    fn parse(name: &str) -> Result<Self, Error> {
+        let invalid = || {
+            Error::InvalidArgument(format!(
+                "unrecognized DNS name {}, expected [tag.]service.service[.datacenter].domain",
+                name
+            ))
+        };
+
+        let labels = name.trim_end_matches('.').split('.').collect::<Vec<_>>();
+        if labels.iter().any(|label| label.is_empty()) {
+            return Err(invalid());
+        }
+
+        // The domain is the last label, the datacenter may sit between it
+        // and the `service` label.
+        let at = labels
+            .iter()
+            .rposition(|label| *label == "service")
+            .ok_or_else(invalid)?;
+        let datacenter = match labels.len() - at {
+            2 => None,
+            3 => Some(labels[at + 1].to_string()),
+            _ => return Err(invalid()),
+        };
+
+        let (service, tag) = match &labels[..at] {
+            [service, tag] if service.starts_with('_') && tag.starts_with('_') => {
+                let tag = &tag[1..];
+                let tag = if tag == "tcp" { None } else { Some(tag) };
+                (&service[1..], tag)
+            }
+            [tag, service] => (*service, Some(*tag)),
+            [service] => (*service, None),
+            _ => return Err(invalid()),
+        };
+
+        if service.is_empty() || service.starts_with('_') || tag.is_some_and(str::is_empty) {
+            return Err(invalid());
+        }
+
+        Ok(DnsName {
+            service: service.to_string(),
+            tag: tag.map(String::from),
+            datacenter,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// A service instance returned from Consul on health requests along
+/// with the node it runs on and all of its checks
+///
+/// For more information on this go [here][value]
+/// [value]: https://www.consul.io/api/health.html#list-nodes-for-service
+pub struct HealthService {
+    pub node: HealthNode,
+    pub service: HealthServiceInstance,
+    #[serde(default, deserialize_with = "null_to_default")]
+    pub checks: Vec<HealthCheck>,
+}
+
+impl HealthService {
+    /// The address the instance can be connected to, the service address
+    /// is used when it was registered with one otherwise the node address
+    /// is used. Returns `None` if the address is not an ip.
+    pub fn socket_addr(&self) -> Option<SocketAddr> {
+        self.socket_addr_tagged(None)
+    }
+
+    /// Like `socket_addr` but preferring the `tagged` address, see
+    /// `address`. Returns `None` if the address is not an ip.
+    pub fn socket_addr_tagged(&self, tagged: Option<TaggedAddress>) -> Option<SocketAddr> {
+        let (address, port) = self.address(tagged);
+        let ip = address.parse::<IpAddr>().ok()?;
+        Some(SocketAddr::new(ip, port))
+    }
+
+    /// The host and port the instance can be connected to, the host may
+    /// be an ip or a hostname
+    ///
+    /// Like Consul DNS the `tagged` address of the service is preferred,
+    /// with its own port, then the service address, then the `tagged`
+    /// address of the node and finally the address of the node.
+    pub fn address(&self, tagged: Option<TaggedAddress>) -> (&str, u16) {
+        let tagged = tagged.map(TaggedAddress::as_str);
+        let service = tagged.and_then(|tag| self.service.tagged_addresses.get(tag));
+        if let Some(service) = service.filter(|service| !service.address.is_empty()) {
+            return (&service.address, service.port);
+        }
+
+        if !self.service.address.is_empty() {
+            return (&self.service.address, self.service.port);
+        }
+
+        let node = tagged.and_then(|tag| self.node.tagged_addresses.get(tag));
+        match node.filter(|node| !node.is_empty()) {
+            Some(node) => (node, self.service.port),
+            None => (&self.node.address, self.service.port),
+        }
+    }
+
+    /// The health of the instance, the worst status of its checks
+    pub fn status(&self) -> HealthStatus {
+        self.checks
+            .iter()
+            .map(|check| HealthStatus::from(check.status.as_str()))
+            .fold(HealthStatus::Passing, HealthStatus::worst)
+    }
+
+    /// Returns true if every check of the instance is passing
+    ///
+    /// Node checks, like the `serfHealth` check of the agent, count as
+    /// much as the checks of the service, an instance on a failed node is
+    /// not healthy even when its own checks still pass. This matches the
+    /// instances `passing` queries and Consul DNS return.
+    pub fn is_healthy(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| HealthStatus::from(check.status.as_str()) == HealthStatus::Passing)
+    }
+
+    /// Returns true if every check of the instance is passing or warning,
+    /// like `is_healthy` for clients that route to instances with warnings
+    pub fn is_healthy_or_warning(&self) -> bool {
+        self.checks.iter().all(|check| {
+            let status = HealthStatus::from(check.status.as_str());
+            matches!(status, HealthStatus::Passing | HealthStatus::Warning)
+        })
+    }
+
+    /// The weight of the instance for load balancing based on its checks
+    ///
+    /// This is the warning weight if any check is warning, `0` if any
+    /// check is critical and the passing weight otherwise.
+    pub fn weight(&self) -> u32 {
+        let statuses = self.checks.iter().map(|check| check.status.as_str());
+        let mut weight = self.service.weights.passing;
+
+        for status in statuses {
+            match status {
+                "passing" => {}
+                "warning" => weight = self.service.weights.warning,
+                _ => return 0,
+            }
+        }
+
+        weight
+    }
+}
+
+/// The health of a check, instance or service
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HealthStatus {
+    /// Every check is passing
+    Passing,
+    /// At least one check is warning and none are critical
+    Warning,
+    /// At least one check is critical or in maintenance
+    Critical,
+    /// The health could not be determined
+    Unknown,
+}
+
+impl HealthStatus {
+    /// The worse of two statuses, an `Unknown` status is only better
+    /// than `Critical`
+    pub fn worst(self, other: HealthStatus) -> HealthStatus {
+        if other.severity() > self.severity() {
+            other
+        } else {
+            self
+        }
+    }
+
+    fn severity(self) -> u8 {
+        match self {
+            HealthStatus::Passing => 0,
+            HealthStatus::Warning => 1,
+            HealthStatus::Unknown => 2,
+            HealthStatus::Critical => 3,
+        }
+    }
+}
+
+impl From<&str> for HealthStatus {
+    fn from(status: &str) -> Self {
+        match status {
+            "passing" => HealthStatus::Passing,
+            "warning" => HealthStatus::Warning,
+            "critical" | "maintenance" => HealthStatus::Critical,
+            _ => HealthStatus::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// The node a `HealthService` instance runs on
+pub struct HealthNode {
+    #[serde(rename = "ID", default)]
+    pub id: String,
+    pub node: String,
+    pub address: String,
+    #[serde(default)]
+    pub datacenter: String,
+    #[serde(default, deserialize_with = "null_to_default")]
+    pub tagged_addresses: HashMap<String, String>,
+    #[serde(default, deserialize_with = "null_to_default")]
+    pub meta: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// The service definition of a `HealthService` instance
+pub struct HealthServiceInstance {
+    #[serde(rename = "ID")]
+    pub id: String,
+    pub service: String,
+    #[serde(default, deserialize_with = "null_to_default")]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub address: String,
+    #[serde(default)]
+    pub port: u16,
+    /// The addresses of the service keyed by tag like `lan` or `wan`
+    #[serde(default, deserialize_with = "null_to_default")]
+    pub tagged_addresses: HashMap<String, ServiceAddress>,
+    #[serde(default, deserialize_with = "null_to_default")]
+    pub meta: HashMap<String, String>,
+    #[serde(default)]
+    pub weights: Weights,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// A tagged address of a `HealthServiceInstance`
+pub struct ServiceAddress {
+    #[serde(default)]
+    pub address: String,
+    #[serde(default)]
+    pub port: u16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// A node or service check returned from Consul on health requests
+pub struct HealthCheck {
+    pub node: String,
+    #[serde(rename = "CheckID")]
+    pub check_id: String,
+    pub name: String,
+    pub status: String,
+    #[serde(default)]
+    pub notes: String,
+    #[serde(default)]
+    pub output: String,
+    #[serde(rename = "ServiceID", default)]
+    pub service_id: String,
+    #[serde(default)]
+    pub service_name: String,
+}
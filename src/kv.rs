@@ -0,0 +1,1155 @@
+//! The KV store
+
+use crate::error::from_json;
+use crate::{
+    duration_ms, null_to_default, BoxConsulFuture, BoxError, BoxStream, Consul, ConsulMeta, Error,
+    KVTxnOp, KVVerb, Query, TxnOp, MAX_WAIT,
+};
+use bytes::Bytes;
+use futures::future;
+use futures::future::Either;
+use futures::future::Loop;
+use futures::stream;
+use futures::stream::Stream;
+use futures::Future;
+use http::{Method, StatusCode};
+use serde::de::{self, SeqAccess, Visitor};
+use serde::Deserialize;
+use serde::Deserializer as _;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::time::Duration;
+use tokio_timer::clock;
+use tower_http_util::service::HttpService;
+
+/// How many times `compare_and_swap` and `update` write before giving up
+/// when the key keeps being modified concurrently.
+const CAS_ATTEMPTS: usize = 5;
+
+impl<T> Consul<T>
+where
+    T: HttpService<Bytes, ResponseBody = Bytes> + Send + 'static,
+    T::Future: Send + 'static,
+    T::Error: Into<BoxError> + Send + Sync,
+{
+    /// The path of `key` under the prefix set with `with_kv_prefix`.
+    fn kv_path(&self, key: &str) -> String {
+        format!("/v1/kv/{}", self.kv_prefix.join(key))
+    }
+
+    /// Get a list of all Service members
+    pub fn get(&mut self, key: &str) -> impl Future<Item = Vec<KVValue>, Error = Error> {
+        let url = self.kv_path(key);
+        let request = match self.build(&url, Method::GET, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        let prefix = self.kv_prefix.clone();
+        Either::B(
+            self.call(request)
+                .map(move |values| prefix.strip_values(values)),
+        )
+    }
+
+    /// Get a key along with the metadata Consul returned in the response
+    /// headers
+    pub fn get_with_meta(
+        &mut self,
+        key: &str,
+    ) -> impl Future<Item = (Vec<KVValue>, ConsulMeta), Error = Error> {
+        let url = self.kv_path(key);
+        let request = match self.build(&url, Method::GET, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        let prefix = self.kv_prefix.clone();
+        Either::B(
+            self.call_with_meta(request)
+                .map(move |(values, meta)| (prefix.strip_values(values), meta)),
+        )
+    }
+
+    /// Read a key with every option of the KV read endpoint available
+    ///
+    /// The shape of the result depends on the options, `raw` returns
+    /// `KvRead::Raw`, `keys` returns `KvRead::Keys` and otherwise
+    /// `KvRead::Values` is returned.
+    pub fn get_with_opts(
+        &mut self,
+        key: &str,
+        opts: KvReadOptions,
+    ) -> impl Future<Item = KvRead, Error = Error> {
+        let mut query = Query::default();
+        if opts.recurse {
+            query.flag("recurse");
+        }
+        if opts.keys {
+            query.flag("keys");
+        }
+        query.opt("separator", opts.separator.as_ref());
+        if opts.raw {
+            query.flag("raw");
+        }
+        query.opt("dc", opts.dc.as_ref());
+        match opts.consistency {
+            Consistency::Default => {}
+            Consistency::Consistent => {
+                query.flag("consistent");
+            }
+            Consistency::Stale => {
+                query.flag("stale");
+            }
+        }
+        query.opt("index", opts.index.map(|index| index.to_string()));
+        query.opt(
+            "wait",
+            opts.wait.map(|wait| duration_ms(cmp::min(wait, MAX_WAIT))),
+        );
+
+        let url = query.append_to(&self.kv_path(key));
+        let request = match self.build(&url, Method::GET, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        let prefix = self.kv_prefix.clone();
+        let fut = self.call_raw(request).and_then(move |body| {
+            if opts.raw {
+                Ok(KvRead::Raw(body))
+            } else if opts.keys {
+                from_json(&body[..]).map(|keys| KvRead::Keys(prefix.strip_keys(keys)))
+            } else {
+                from_json(&body[..]).map(|values| KvRead::Values(prefix.strip_values(values)))
+            }
+        });
+
+        Either::B(fut)
+    }
+
+    /// Get the value of a single key as the raw bytes stored in Consul
+    ///
+    /// Unlike `get_value` this uses `?raw` so Consul returns the value
+    /// itself rather than a JSON document containing the base64 encoded
+    /// value.
+    pub fn get_raw(&mut self, key: &str) -> impl Future<Item = Bytes, Error = Error> {
+        let url = format!("{}?raw", self.kv_path(key));
+        let request = match self.build(&url, Method::GET, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        Either::B(self.call_raw(request))
+    }
+
+    /// Get the decoded value of a single key
+    pub fn get_value(&mut self, key: &str) -> impl Future<Item = Bytes, Error = Error> {
+        let raw = self.raw_kv_values;
+
+        self.get(key).and_then(move |mut values| {
+            let value = values.pop().ok_or(Error::NotFound)?;
+            Self::value_bytes(&value, raw)
+        })
+    }
+
+    /// Get the decoded value of a single key along with its flags
+    ///
+    /// Consul does not interpret flags, tools that store typed values
+    /// commonly use them to record how the value is encoded, for example
+    /// as a content type identifier or a format version. Reading both
+    /// from the same response guarantees they belong to the same write,
+    /// which separate `get_raw` and `get` calls would not.
+    pub fn get_raw_typed(&mut self, key: &str) -> impl Future<Item = (Bytes, u64), Error = Error> {
+        let raw = self.raw_kv_values;
+
+        self.get(key).and_then(move |mut values| {
+            let value = values.pop().ok_or(Error::NotFound)?;
+            Ok((Self::value_bytes(&value, raw)?, value.flags))
+        })
+    }
+
+    /// Get a single key along with its metadata and decoded value,
+    /// resolving to `None` if the key does not exist
+    pub fn get_entry(&mut self, key: &str) -> impl Future<Item = Option<KvEntry>, Error = Error> {
+        let raw = self.raw_kv_values;
+
+        self.get(key)
+            .then(|res| match res {
+                Err(Error::NotFound) => Ok(Vec::new()),
+                res => res,
+            })
+            .and_then(move |mut values| {
+                let value = match values.pop() {
+                    Some(value) => value,
+                    None => return Ok(None),
+                };
+
+                Ok(Some(KvEntry {
+                    value: Self::value_bytes(&value, raw)?,
+                    key: value.key,
+                    flags: value.flags,
+                    create_index: value.create_index,
+                    modify_index: value.modify_index,
+                    session: value.session,
+                }))
+            })
+    }
+
+    /// The bytes of a KV value, which is base64 encoded unless `raw`
+    fn value_bytes(value: &KVValue, raw: bool) -> Result<Bytes, Error> {
+        if raw {
+            Ok(Bytes::from(value.value.as_bytes()))
+        } else {
+            value.decoded_value()
+        }
+    }
+
+    /// Get a single key and deserialize its value as a flat JSON object
+    /// of strings
+    pub fn get_object(
+        &mut self,
+        key: &str,
+    ) -> impl Future<Item = HashMap<String, String>, Error = Error> {
+        self.get_value(key).and_then(|body| from_json(&body[..]))
+    }
+
+    /// Get a single key and transform its decoded value with `decode`
+    ///
+    /// This can be used to decrypt, decompress or parse values stored in
+    /// a custom format, errors returned from `decode` are returned as
+    /// `Error::Decode`.
+    pub fn get_decoded<F, R>(
+        &mut self,
+        key: &str,
+        decode: F,
+    ) -> impl Future<Item = R, Error = Error>
+    where
+        F: FnOnce(Bytes) -> Result<R, BoxError>,
+    {
+        self.get_value(key)
+            .and_then(|body| decode(body).map_err(Error::Decode))
+    }
+
+    /// Get a list of all Service members
+    pub fn get_keys(&mut self, key: &str) -> impl Future<Item = Vec<String>, Error = Error> {
+        let url = format!("{}?keys", self.kv_path(key));
+        let request = match self.build(&url, Method::GET, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        let prefix = self.kv_prefix.clone();
+        Either::B(self.call(request).map(move |keys| prefix.strip_keys(keys)))
+    }
+
+    /// Get the `X-Consul-Index` of everything under `prefix`, without
+    /// decoding the keys
+    ///
+    /// The index advances whenever any key under the prefix changes, so
+    /// this is the cheapest way to detect a change to a whole prefix. An
+    /// empty prefix still has an index. Fails with `Error::MissingIndex` if
+    /// the response has no valid index.
+    pub fn prefix_index(&mut self, prefix: &str) -> impl Future<Item = i64, Error = Error> {
+        let url = format!("{}?keys", self.kv_path(prefix));
+        let request = match self.build(&url, Method::GET, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        let fut = self.send(request).and_then(|res| {
+            let index = Self::consul_index(&res).ok_or(Error::MissingIndex)?;
+
+            if res.status() != StatusCode::NOT_FOUND {
+                Self::handle_status(res)?;
+            }
+
+            Ok(index)
+        });
+
+        Either::B(fut)
+    }
+
+    /// Get every key under `prefix` along with its value
+    ///
+    /// If nothing exists under the prefix an empty list is returned.
+    pub fn get_recurse(&mut self, prefix: &str) -> impl Future<Item = Vec<KVValue>, Error = Error> {
+        let url = format!("{}?recurse", self.kv_path(prefix));
+        let request = match self.build(&url, Method::GET, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        let prefix = self.kv_prefix.clone();
+        let fut = self
+            .call(request)
+            .or_else(|e| match e {
+                Error::NotFound => Ok(Vec::new()),
+                e => Err(e),
+            })
+            .map(move |values| prefix.strip_values(values));
+
+        Either::B(fut)
+    }
+
+    /// Get every key under `prefix` along with its value, failing with
+    /// `Error::BodyTooLarge` if the decoded values add up to more than
+    /// `max_bytes`
+    ///
+    /// The values are parsed and decoded one at a time and parsing stops
+    /// at the first value that takes the total over the budget, so an
+    /// oversized tree is never decoded or held as a whole. What is
+    /// downloaded can not be limited, the inner `HttpService` buffers the
+    /// whole response body before the client sees any of it, so the body
+    /// of an oversized tree is still read into memory once. No partial
+    /// results are returned when the budget is exceeded.
+    pub fn get_recurse_bounded(
+        &mut self,
+        prefix: &str,
+        max_bytes: usize,
+    ) -> impl Future<Item = Vec<KVValue>, Error = Error> {
+        let url = format!("{}?recurse", self.kv_path(prefix));
+        let request = match self.build(&url, Method::GET, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        let raw = self.raw_kv_values;
+        let prefix = self.kv_prefix.clone();
+        let fut = self.call_raw(request).then(move |res| {
+            let body = match res {
+                Ok(body) => body,
+                Err(Error::NotFound) => return Ok(Vec::new()),
+                Err(e) => return Err(e),
+            };
+
+            let values = bounded_values(&body[..], max_bytes, raw)?;
+            Ok(prefix.strip_values(values))
+        });
+
+        Either::B(fut)
+    }
+
+    /// Get the entry under `prefix` that was modified last, the one with
+    /// the highest `ModifyIndex`, or `None` if nothing exists under it
+    pub fn get_latest(
+        &mut self,
+        prefix: &str,
+    ) -> impl Future<Item = Option<KVValue>, Error = Error> {
+        self.get_recurse(prefix)
+            .map(|values| values.into_iter().max_by_key(|value| value.modify_index))
+    }
+
+    /// List the names of the keys under `prefix`
+    ///
+    /// Without a `separator` every key under the prefix is listed
+    /// recursively. With a `separator` only the immediate children are
+    /// listed, keys that continue past the separator are collapsed into
+    /// a single entry ending with the separator, like a directory listing.
+    pub fn list_keys(
+        &mut self,
+        prefix: &str,
+        separator: Option<&str>,
+    ) -> impl Future<Item = Vec<String>, Error = Error> {
+        let url = Query::default()
+            .flag("keys")
+            .opt("separator", separator)
+            .append_to(&self.kv_path(prefix));
+
+        let request = match self.build(&url, Method::GET, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        let prefix = self.kv_prefix.clone();
+        Either::B(self.call(request).map(move |keys| prefix.strip_keys(keys)))
+    }
+
+    /// Set a value of bytes into the key
+    pub fn set(
+        &mut self,
+        key: &str,
+        value: impl Into<Bytes>,
+    ) -> impl Future<Item = bool, Error = Error> {
+        let url = self.kv_path(key);
+        let request = match self.build(&url, Method::PUT, value.into()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        Either::B(self.call(request))
+    }
+
+    /// Set a value of bytes into the key and acquire its lock with
+    /// `session`, resolving to `false` if another session holds the lock
+    ///
+    /// After the session that held a lock is invalidated the lock can
+    /// not be acquired until the `lock_delay` of that session has passed,
+    /// an `acquire` within that window resolves to `false`.
+    ///
+    /// The session must belong to the datacenter this client sends
+    /// requests to, see `validate_session`.
+    pub fn acquire(
+        &mut self,
+        key: &str,
+        value: impl Into<Bytes>,
+        session: &str,
+    ) -> impl Future<Item = bool, Error = Error> {
+        let url = Query::default()
+            .param("acquire", session)
+            .append_to(&self.kv_path(key));
+
+        let request = match self.build(&url, Method::PUT, value.into()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        Either::B(self.call(request))
+    }
+
+    /// Release the lock on the key held by `session`, which must belong
+    /// to the datacenter this client sends requests to
+    pub fn release(&mut self, key: &str, session: &str) -> impl Future<Item = bool, Error = Error> {
+        let url = Query::default()
+            .param("release", session)
+            .append_to(&self.kv_path(key));
+
+        let request = match self.build(&url, Method::PUT, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        Either::B(self.call(request))
+    }
+
+    /// Check whether the lock on the key is still held by `session`,
+    /// resolving to `false` if the key does not exist
+    ///
+    /// A lock is lost once its session is invalidated, for example when
+    /// its TTL runs out or a check it depends on fails. Lock holders should
+    /// call this periodically before acting as the holder.
+    pub fn holds_lock(
+        &mut self,
+        key: &str,
+        session: &str,
+    ) -> impl Future<Item = bool, Error = Error> {
+        let session = session.to_string();
+
+        self.get(key).then(move |res| match res {
+            Ok(values) => Ok(values
+                .iter()
+                .any(|value| value.session.as_ref() == Some(&session))),
+            Err(Error::NotFound) => Ok(false),
+            Err(e) => Err(e),
+        })
+    }
+
+    /// Set a value of bytes into the key only if its `ModifyIndex` is
+    /// still `index`, resolving to `false` if it was modified since
+    ///
+    /// An `index` of `0` only sets the key if it does not exist.
+    pub fn set_cas(
+        &mut self,
+        key: &str,
+        value: impl Into<Bytes>,
+        index: i64,
+    ) -> impl Future<Item = bool, Error = Error> {
+        let url = Query::default()
+            .param("cas", &index.to_string())
+            .append_to(&self.kv_path(key));
+
+        let request = match self.build(&url, Method::PUT, value.into()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        Either::B(self.call(request))
+    }
+
+    /// Replace the value of the key with `new` only if it currently
+    /// equals `old`, resolving to whether the value was replaced
+    ///
+    /// The value is compared client side and written with `set_cas`. If
+    /// the key is modified between the read and the write but still holds
+    /// `old`, like a write of the same value, the swap is retried a few
+    /// times before resolving to `false`. A key that does not exist never
+    /// matches.
+    pub fn compare_and_swap(
+        &mut self,
+        key: &str,
+        old: &[u8],
+        new: impl Into<Bytes>,
+    ) -> impl Future<Item = bool, Error = Error> {
+        let key = key.to_string();
+        let old = Bytes::from(old);
+        let new = new.into();
+        let raw = self.raw_kv_values;
+
+        future::loop_fn((self.clone(), 1), move |(mut client, attempt)| {
+            let (key, old, new) = (key.clone(), old.clone(), new.clone());
+
+            client
+                .get(&key)
+                .then(|res| match res {
+                    Err(Error::NotFound) => Ok(Vec::new()),
+                    res => res,
+                })
+                .and_then(move |mut values| {
+                    let current = match values.pop() {
+                        Some(current) => current,
+                        None => return Either::A(future::ok(Loop::Break(false))),
+                    };
+
+                    let index = current.modify_index;
+                    match Self::value_bytes(&current, raw) {
+                        Ok(value) if value == old => {}
+                        Ok(_) => return Either::A(future::ok(Loop::Break(false))),
+                        Err(e) => return Either::A(future::err(e)),
+                    }
+
+                    let fut = client.set_cas(&key, new, index).map(move |swapped| {
+                        if swapped || attempt >= CAS_ATTEMPTS {
+                            Loop::Break(swapped)
+                        } else {
+                            Loop::Continue((client, attempt + 1))
+                        }
+                    });
+
+                    Either::B(fut)
+                })
+        })
+    }
+
+    /// Read the key, apply `f` to its value and write the result back
+    ///
+    /// `f` receives `None` if the key does not exist. If it returns
+    /// `Some` the key is set to the new value, if it returns `None` the
+    /// key is deleted. The write is guarded by the `ModifyIndex` that was
+    /// read, if the key is modified concurrently the read and `f` are
+    /// retried a few times before failing with `Error::CasConflict`.
+    pub fn update<F>(&mut self, key: &str, f: F) -> impl Future<Item = (), Error = Error>
+    where
+        F: FnMut(Option<Bytes>) -> Option<Bytes> + Send + 'static,
+    {
+        let key = key.to_string();
+        let raw = self.raw_kv_values;
+
+        future::loop_fn((self.clone(), f, 1), move |(mut client, mut f, attempt)| {
+            let key = key.clone();
+
+            client
+                .get(&key)
+                .then(|res| match res {
+                    Err(Error::NotFound) => Ok(Vec::new()),
+                    res => res,
+                })
+                .and_then(move |mut values| {
+                    let (current, index) = match values.pop() {
+                        Some(current) => match Self::value_bytes(&current, raw) {
+                            Ok(value) => (Some(value), current.modify_index),
+                            Err(e) => return Either::A(future::err(e)),
+                        },
+                        None => (None, 0),
+                    };
+
+                    let written: BoxConsulFuture<bool> = match (f(current), index) {
+                        (None, 0) => return Either::A(future::ok(Loop::Break(()))),
+                        (None, index) => Box::new(client.delete_cas(&key, index)),
+                        (Some(value), index) => Box::new(client.set_cas(&key, value, index)),
+                    };
+
+                    let fut = written.and_then(move |written| {
+                        if written {
+                            Ok(Loop::Break(()))
+                        } else if attempt < CAS_ATTEMPTS {
+                            Ok(Loop::Continue((client, f, attempt + 1)))
+                        } else {
+                            Err(Error::CasConflict(key))
+                        }
+                    });
+
+                    Either::B(fut)
+                })
+        })
+    }
+
+    /// Set a value of bytes into the key only if the key currently
+    /// exists and its flags equal `expected_flags`.
+    ///
+    /// The flags are compared client side and the write is performed
+    /// through a transaction guarded by the `ModifyIndex` that was read,
+    /// so a concurrent modification between the read and the write causes
+    /// this to resolve to `false` rather than overwriting the new value.
+    pub fn set_if_flags(
+        &mut self,
+        key: &str,
+        value: impl Into<Bytes>,
+        expected_flags: u64,
+    ) -> impl Future<Item = bool, Error = Error> {
+        let mut client = self.clone();
+        let key = key.to_string();
+        let value = value.into();
+
+        self.get(&key)
+            .then(|res| match res {
+                Err(Error::NotFound) => Ok(Vec::new()),
+                res => res,
+            })
+            .and_then(move |mut values| {
+                let current = match values.pop() {
+                    Some(current) => current,
+                    None => return Either::A(future::ok(false)),
+                };
+
+                if current.flags != expected_flags {
+                    return Either::A(future::ok(false));
+                }
+
+                let op = KVTxnOp {
+                    verb: KVVerb::Cas,
+                    key: client.kv_prefix.join(&key),
+                    value: Some(base64::encode(&value[..])),
+                    flags: Some(expected_flags),
+                    index: Some(current.modify_index),
+                    session: None,
+                };
+
+                Either::B(client.txn(vec![TxnOp::KV(op)]).map(|res| res.is_success()))
+            })
+    }
+
+    /// Compute the changes that turn the tree under `prefix` into
+    /// `desired`, a map of full keys to their values, relative to the
+    /// prefix set with `with_kv_prefix`
+    ///
+    /// Keys in `desired` that do not exist are created, keys whose value
+    /// differs are updated and keys under `prefix` missing from `desired`
+    /// are deleted, nothing is written until the plan is passed to
+    /// `apply`. Every key of `desired` must start with `prefix`, otherwise
+    /// this fails with `Error::InvalidArgument`.
+    pub fn plan(
+        &mut self,
+        prefix: &str,
+        desired: HashMap<String, Bytes>,
+    ) -> impl Future<Item = KvPlan, Error = Error> {
+        if let Some(key) = desired.keys().find(|key| !key.starts_with(prefix)) {
+            let msg = format!("{} is not under the prefix {}", key, prefix);
+            return Either::A(future::err(Error::InvalidArgument(msg)));
+        }
+
+        let raw = self.raw_kv_values;
+
+        Either::B(self.get_recurse(prefix).and_then(move |current| {
+            let mut desired = desired.into_iter().collect::<BTreeMap<_, _>>();
+            let mut plan = KvPlan::default();
+
+            for value in current {
+                let bytes = Self::value_bytes(&value, raw)?;
+                match desired.remove(&value.key) {
+                    Some(wanted) if wanted == bytes => {}
+                    Some(wanted) => plan.update.push(KvChange {
+                        key: value.key,
+                        value: wanted,
+                        flags: value.flags,
+                        modify_index: value.modify_index,
+                    }),
+                    None => plan.delete.push(KvChange {
+                        key: value.key,
+                        value: bytes,
+                        flags: value.flags,
+                        modify_index: value.modify_index,
+                    }),
+                }
+            }
+
+            plan.create = desired
+                .into_iter()
+                .map(|(key, value)| KvChange {
+                    key,
+                    value,
+                    flags: 0,
+                    modify_index: 0,
+                })
+                .collect();
+
+            Ok(plan)
+        }))
+    }
+
+    /// Execute a plan computed with `plan` in a single transaction,
+    /// resolving to whether it was committed
+    ///
+    /// Every write is guarded by the `ModifyIndex` read by `plan`, so if
+    /// any key was created, modified or deleted since, nothing is written
+    /// and this resolves to `false`, the plan should then be recomputed.
+    /// Consul limits the number of operations in a transaction, 64 by
+    /// default, larger plans fail with `Error::ConsulClient`. An empty
+    /// plan resolves to `true` without sending a request.
+    pub fn apply(&mut self, plan: KvPlan) -> impl Future<Item = bool, Error = Error> {
+        if plan.is_empty() {
+            return Either::A(future::ok(true));
+        }
+
+        let prefix = &self.kv_prefix;
+        let writes = plan.create.into_iter().chain(plan.update).map(|change| {
+            TxnOp::KV(KVTxnOp {
+                verb: KVVerb::Cas,
+                key: prefix.join(&change.key),
+                value: Some(base64::encode(&change.value[..])),
+                flags: Some(change.flags),
+                index: Some(change.modify_index),
+                session: None,
+            })
+        });
+        let deletes = plan.delete.into_iter().map(|change| {
+            TxnOp::KV(KVTxnOp {
+                verb: KVVerb::DeleteCas,
+                key: prefix.join(&change.key),
+                value: None,
+                flags: None,
+                index: Some(change.modify_index),
+                session: None,
+            })
+        });
+
+        let ops = writes.chain(deletes).collect();
+        Either::B(self.txn(ops).map(|res| res.is_success()))
+    }
+
+    /// Move the value of `from` to `to` in a single transaction,
+    /// resolving to whether the key was renamed
+    ///
+    /// The value and flags of `from` are read, then written to `to` and
+    /// `from` is deleted atomically. The delete is guarded by the
+    /// `ModifyIndex` that was read and the write requires `to` to not
+    /// exist, so if `from` was modified or deleted concurrently, or `to`
+    /// already exists, nothing is changed and this resolves to `false`.
+    /// A missing `from` also resolves to `false`.
+    pub fn rename(&mut self, from: &str, to: &str) -> impl Future<Item = bool, Error = Error> {
+        let mut client = self.clone();
+        let from = from.to_string();
+        let to = to.to_string();
+        let raw = self.raw_kv_values;
+
+        self.get(&from)
+            .then(|res| match res {
+                Err(Error::NotFound) => Ok(Vec::new()),
+                res => res,
+            })
+            .and_then(move |mut values| {
+                let current = match values.pop() {
+                    Some(current) => current,
+                    None => return Either::A(future::ok(false)),
+                };
+
+                let value = match Self::value_bytes(&current, raw) {
+                    Ok(value) => value,
+                    Err(e) => return Either::A(future::err(e)),
+                };
+
+                let ops = vec![
+                    TxnOp::KV(KVTxnOp {
+                        verb: KVVerb::Cas,
+                        key: client.kv_prefix.join(&to),
+                        value: Some(base64::encode(&value[..])),
+                        flags: Some(current.flags),
+                        index: Some(0),
+                        session: None,
+                    }),
+                    TxnOp::KV(KVTxnOp {
+                        verb: KVVerb::DeleteCas,
+                        key: client.kv_prefix.join(&from),
+                        value: None,
+                        flags: None,
+                        index: Some(current.modify_index),
+                        session: None,
+                    }),
+                ];
+
+                Either::B(client.txn(ops).map(|res| res.is_success()))
+            })
+    }
+
+    /// Delete a key and its value
+    pub fn delete(&mut self, key: &str) -> impl Future<Item = bool, Error = Error> {
+        let url = self.kv_path(key);
+        let request = match self.build(&url, Method::DELETE, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        Either::B(self.call(request))
+    }
+
+    /// Delete a key only if its `ModifyIndex` is still `index`, resolving
+    /// to `false` if it was modified since
+    pub fn delete_cas(&mut self, key: &str, index: i64) -> impl Future<Item = bool, Error = Error> {
+        let url = Query::default()
+            .param("cas", &index.to_string())
+            .append_to(&self.kv_path(key));
+
+        let request = match self.build(&url, Method::DELETE, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        Either::B(self.call(request))
+    }
+
+    /// Get a key using a blocking query, returning the values along with
+    /// the `X-Consul-Index` of the response
+    ///
+    /// The query returns once the index of the key is greater than
+    /// `index` or `wait` has elapsed. `wait` is capped at 10 minutes, the
+    /// maximum Consul allows, and Consul adds up to `wait / 16` of random
+    /// jitter so the query may take slightly longer than `wait`. If the key
+    /// does not exist an empty list is returned.
+    ///
+    /// An `index` of `0` does not block, it is a regular read that returns
+    /// the current values and the index to block on next. Calling this in a
+    /// loop with `0` rather than the returned index never blocks, use
+    /// `watch` to have the index tracked.
+    pub fn get_blocking(
+        &mut self,
+        key: &str,
+        index: i64,
+        wait: Duration,
+    ) -> impl Future<Item = (Vec<KVValue>, i64), Error = Error> {
+        let url = self.kv_path(key);
+        let prefix = self.kv_prefix.clone();
+        self.blocking(&url, index, wait)
+            .map(move |(values, index)| (prefix.strip_values(values), index))
+    }
+
+    /// Wait until the value of `key` equals `expected`, failing with
+    /// `Error::Timeout` once `timeout` has elapsed
+    ///
+    /// The key is read with blocking queries, each held by Consul for at
+    /// most the time left, and the decoded value is compared after every
+    /// change. A key that does not exist does not match and is waited on
+    /// like any other. Consul adds jitter to blocking queries so this may
+    /// fail slightly after `timeout`.
+    pub fn await_value(
+        &mut self,
+        key: &str,
+        expected: &[u8],
+        timeout: Duration,
+    ) -> impl Future<Item = (), Error = Error> {
+        let client = self.clone();
+        let key = key.to_string();
+        let expected = Bytes::from(expected);
+        let deadline = clock::now() + timeout;
+        let raw = self.raw_kv_values;
+
+        future::loop_fn((client, 0), move |(mut client, index)| {
+            let expected = expected.clone();
+            let remaining = deadline.saturating_duration_since(clock::now());
+
+            // Blocking waits are sent in whole milliseconds and Consul
+            // treats a wait of `0ms` as its default of several minutes.
+            if remaining < Duration::from_millis(1) {
+                return Either::A(future::err(Error::Timeout));
+            }
+
+            let fut =
+                client
+                    .get_blocking(&key, index, remaining)
+                    .and_then(move |(values, next)| {
+                        let matches = match values.first() {
+                            Some(value) => Self::value_bytes(value, raw)? == expected,
+                            None => false,
+                        };
+
+                        if matches {
+                            return Ok(Loop::Break(()));
+                        }
+
+                        // Start over with a regular read if the index went
+                        // backwards, like `watch` does.
+                        let next = if next < index { 0 } else { cmp::max(next, 1) };
+                        Ok(Loop::Continue((client, next)))
+                    });
+
+            Either::B(fut)
+        })
+    }
+
+    /// Watch a key, yielding its values every time it changes
+    ///
+    /// The first item is the current value of the key, if the key does
+    /// not exist an empty list is yielded. Each blocking query is held by
+    /// Consul for at most `wait` before it is reissued.
+    ///
+    /// The first read does not block, every following query blocks on the
+    /// index of the previous response.
+    pub fn watch(
+        &mut self,
+        key: &str,
+        wait: Duration,
+    ) -> impl Stream<Item = Vec<KVValue>, Error = Error> {
+        let url = self.kv_path(key);
+        let prefix = self.kv_prefix.clone();
+        self.watch_url(url, wait)
+            .map(move |values| prefix.strip_values(values))
+    }
+
+    /// Watch several keys concurrently, yielding the values of a key
+    /// along with the key every time it changes
+    ///
+    /// Every key is watched with its own blocking query and index, so a
+    /// change to one key does not cause the others to be yielded again.
+    pub fn watch_many(
+        &mut self,
+        keys: &[&str],
+        wait: Duration,
+    ) -> impl Stream<Item = (String, Vec<KVValue>), Error = Error> {
+        let empty: BoxStream<(String, Vec<KVValue>)> = Box::new(stream::empty());
+
+        keys.iter().fold(empty, |merged, key| {
+            let tag = key.to_string();
+            let watch = self
+                .watch(key, wait)
+                .map(move |values| (tag.clone(), values));
+
+            Box::new(merged.select(watch))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// The value returned from consul
+///
+/// For more information on this go [here][value]
+/// [value]: https://www.consul.io/api/kv.html#read-key
+pub struct KVValue {
+    pub create_index: i64,
+    pub modify_index: i64,
+    pub lock_index: i64,
+    pub key: String,
+    pub flags: u64,
+    #[serde(default, deserialize_with = "null_to_default")]
+    pub value: String,
+    pub session: Option<String>,
+    /// Fields this version does not know of, like the `Namespace` and
+    /// `Partition` returned by enterprise clusters
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+impl KVValue {
+    /// Decode the base64 encoded value, failing with `Error::Base64`
+    /// naming the key if it is not valid base64
+    pub fn decoded_value(&self) -> Result<Bytes, Error> {
+        base64::decode(&self.value)
+            .map(Bytes::from)
+            .map_err(|error| Error::Base64 {
+                key: self.key.clone(),
+                error,
+            })
+    }
+}
+
+/// A key read with `Consul::get_entry` along with its decoded value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KvEntry {
+    /// The full path of the key
+    pub key: String,
+    /// The value of the key, already decoded
+    pub value: Bytes,
+    /// The opaque flags set along with the value
+    pub flags: u64,
+    /// The index the key was created at
+    pub create_index: i64,
+    /// The index the key was last modified at, used for `set_cas`
+    pub modify_index: i64,
+    /// The session holding the lock on the key, if any
+    pub session: Option<String>,
+}
+
+/// Deserialize the values of a recurse read one at a time, failing with
+/// `Error::BodyTooLarge` as soon as their decoded values add up to more
+/// than `max_bytes`.
+fn bounded_values(body: &[u8], max_bytes: usize, raw: bool) -> Result<Vec<KVValue>, Error> {
+    struct Bounded<'a> {
+        max_bytes: usize,
+        raw: bool,
+        failure: &'a RefCell<Option<Error>>,
+    }
+
+    impl<'de, 'a> Visitor<'de> for Bounded<'a> {
+        type Value = Vec<KVValue>;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "a list of KV values")
+        }
+
+        fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+        where
+            A: SeqAccess<'de>,
+        {
+            let mut values = Vec::new();
+            let mut total = 0usize;
+
+            while let Some(value) = seq.next_element::<KVValue>()? {
+                let len = if self.raw {
+                    Ok(value.value.len())
+                } else {
+                    value.decoded_value().map(|value| value.len())
+                };
+
+                let failure = match len {
+                    Ok(len) => {
+                        total = total.saturating_add(len);
+                        if total <= self.max_bytes {
+                            values.push(value);
+                            continue;
+                        }
+                        Error::BodyTooLarge(self.max_bytes)
+                    }
+                    Err(e) => e,
+                };
+
+                // Stop parsing, the real error is picked up from `failure`.
+                *self.failure.borrow_mut() = Some(failure);
+                return Err(de::Error::custom("stopped reading KV values"));
+            }
+
+            Ok(values)
+        }
+    }
+
+    let failure = RefCell::new(None);
+    let mut deserializer = serde_json::Deserializer::from_slice(body);
+    let bounded = Bounded {
+        max_bytes,
+        raw,
+        failure: &failure,
+    };
+
+    let res = deserializer
+        .deserialize_seq(bounded)
+        .and_then(|values| deserializer.end().map(|_| values));
+
+    match (res, failure.into_inner()) {
+        (Ok(values), _) => Ok(values),
+        (Err(_), Some(failure)) => Err(failure),
+        (Err(e), None) => Err(Error::from(e)),
+    }
+}
+
+/// The prefix set with `Consul::with_kv_prefix`, added to the keys sent
+/// and removed from the keys read back.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct KvPrefix(pub(crate) Option<String>);
+
+impl KvPrefix {
+    fn join(&self, key: &str) -> String {
+        match &self.0 {
+            Some(prefix) => format!("{}{}", prefix, key),
+            None => key.to_string(),
+        }
+    }
+
+    fn strip(&self, key: String) -> String {
+        match &self.0 {
+            Some(prefix) if key.starts_with(prefix.as_str()) => key[prefix.len()..].to_string(),
+            _ => key,
+        }
+    }
+
+    fn strip_keys(&self, keys: Vec<String>) -> Vec<String> {
+        keys.into_iter().map(|key| self.strip(key)).collect()
+    }
+
+    fn strip_values(&self, mut values: Vec<KVValue>) -> Vec<KVValue> {
+        for value in &mut values {
+            value.key = self.strip(std::mem::take(&mut value.key));
+        }
+        values
+    }
+}
+
+/// The changes computed by `Consul::plan`, each list is sorted by key
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KvPlan {
+    /// Keys that do not exist yet
+    pub create: Vec<KvChange>,
+    /// Keys whose value is replaced
+    pub update: Vec<KvChange>,
+    /// Keys that are not part of the desired tree
+    pub delete: Vec<KvChange>,
+}
+
+impl KvPlan {
+    /// Returns true if the tree already matches and there is nothing to do
+    pub fn is_empty(&self) -> bool {
+        self.create.is_empty() && self.update.is_empty() && self.delete.is_empty()
+    }
+}
+
+/// A single key changed by a `KvPlan`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KvChange {
+    /// The full path of the key
+    pub key: String,
+    /// The value written, or for a deletion the current value
+    pub value: Bytes,
+    /// The flags of the key, kept when its value is updated
+    pub flags: u64,
+    /// The index the key was last modified at when planned, `0` for a
+    /// key that does not exist yet
+    pub modify_index: i64,
+}
+
+/// Options for reading keys with `Consul::get_with_opts`
+#[derive(Debug, Clone, Default)]
+pub struct KvReadOptions {
+    /// Return every key with the key as a prefix
+    pub recurse: bool,
+    /// Only return the names of the keys
+    pub keys: bool,
+    /// Collapse keys past this separator, only used with `keys`
+    pub separator: Option<String>,
+    /// Return the raw value of the key rather than a JSON document
+    pub raw: bool,
+    /// The datacenter to query, defaults to the datacenter of the agent
+    pub dc: Option<String>,
+    /// The consistency mode of the read
+    pub consistency: Consistency,
+    /// Block until the index of the key is greater than this
+    pub index: Option<i64>,
+    /// How long to block for when `index` is set, capped at 10 minutes
+    pub wait: Option<Duration>,
+}
+
+/// The consistency mode of a read
+///
+/// For more information on this go [here][value]
+/// [value]: https://www.consul.io/api/features/consistency.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Consistency {
+    /// Reads are served by the leader unless it recently lost leadership
+    #[default]
+    Default,
+    /// Reads are always verified with a quorum of servers
+    Consistent,
+    /// Reads may be served by any server and may be stale
+    Stale,
+}
+
+/// The result of `Consul::get_with_opts`, which depends on the options
+/// used to read the key
+#[derive(Debug, Clone)]
+pub enum KvRead {
+    /// The values of the keys that were read
+    Values(Vec<KVValue>),
+    /// The names of the keys that were read
+    Keys(Vec<String>),
+    /// The raw value of the key that was read
+    Raw(Bytes),
+}
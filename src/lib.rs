@@ -1,27 +1,92 @@
 //! Tower based HttpService interface to Consul
+//!
+//! Response models ignore fields they do not know of, so a newer Consul
+//! that returns additional fields does not break deserialization. Models
+//! must never use `deny_unknown_fields`, `KVValue` keeps the fields it
+//! does not know of in `extra`.
 
 #![warn(missing_docs)]
 
+mod abandon;
+mod acl;
+mod agent;
+mod catalog;
+mod client;
+mod clock;
+mod de;
+mod duration;
+mod error;
+mod health;
+mod kv;
+mod meta;
+mod metrics;
+mod operator;
+mod query;
+mod session;
+mod transport;
+mod txn;
+mod watch;
+mod worker;
+
+pub use crate::acl::{AclToken, AclTokenPolicy};
+pub use crate::agent::{
+    log_entries, service_id, AgentCheck, AgentConfig, AgentSelf, AgentServiceCheck,
+    AgentServiceRegistration, Heartbeat, LogEntry, LogLevel, Member, MemberStatus,
+};
+pub use crate::catalog::{
+    ConsulService, ServiceInventory, ServiceKind, ServiceProxy, ServiceQuery, Weights,
+};
+pub use crate::clock::{Clock, SystemClock};
+pub use crate::duration::ConsulDuration;
+pub use crate::error::Error;
+pub use crate::health::{
+    HealthCheck, HealthNode, HealthQuery, HealthService, HealthServiceInstance, HealthStatus,
+    LoadBalance, ServiceAddress, TaggedAddress,
+};
+pub use crate::kv::{Consistency, KVValue, KvChange, KvEntry, KvPlan, KvRead, KvReadOptions};
+pub use crate::meta::ConsulMeta;
+pub use crate::metrics::{ConsulMetrics, Operation};
+pub use crate::operator::{AutopilotConfig, RaftConfiguration, RaftServer};
+pub use crate::session::{SessionEntry, SessionKeepAlive, SessionRequest};
+pub use crate::transport::ConsulFuture;
+pub use crate::txn::{
+    CatalogVerb, CheckTxnOp, KVTxnOp, KVVerb, NodeTxnOp, ServiceTxnOp, TxnCheck,
+    TxnCheckDefinition, TxnError, TxnNode, TxnOp, TxnResponse, TxnResult, TxnService,
+};
+pub use crate::worker::{WorkerExit, WorkerMonitor};
+
 use bytes::Bytes;
-use futures::future::{self, Either};
-use futures::{try_ready, Async, Future, Poll};
-use http::{Method, Request, Response, StatusCode, Uri};
-use serde::{Deserialize, Serialize};
+use futures::stream::Stream;
+use futures::Future;
+use http::header::HeaderName;
+use http::Request;
 use std::collections::HashMap;
-use std::marker::PhantomData;
-use std::string::FromUtf8Error;
+use std::sync::atomic::AtomicUsize;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
-use tower_buffer::error::SpawnError;
-use tower_buffer::future::ResponseFuture;
 use tower_buffer::Buffer;
 use tower_http_util::service::{HttpService, IntoService};
 
+use crate::abandon::Abandonable;
+use crate::client::{AgentCache, BodyStats};
+use crate::de::null_to_default;
+use crate::duration::duration_ms;
+use crate::health::Balancer;
+use crate::kv::KvPrefix;
+use crate::metrics::Counters;
+use crate::query::Query;
+use crate::transport::Reconnect;
+use crate::watch::MAX_WAIT;
+
 /// The future returned by Consul requests where `T` is the response
 /// and `E` is the inner Http error and a Box allocation is needed.
-pub type BoxConsulFuture<T> = Box<Future<Item = T, Error = Error> + Send>;
+pub type BoxConsulFuture<T> = Box<dyn Future<Item = T, Error = Error> + Send>;
+
+type BoxStream<T> = Box<dyn Stream<Item = T, Error = Error> + Send>;
 
 /// Standard box error type
-pub type BoxError = Box<std::error::Error + Send + Sync>;
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
 /// Create new [Consul][consul] service that will talk with
 /// the consul agent api. It takes some `HttpService` that takes
@@ -29,6 +94,11 @@ pub type BoxError = Box<std::error::Error + Send + Sync>;
 ///
 /// Currently only the KV api is available, with more to come.
 ///
+/// Requests are sent through a `Buffer` of size `bound`, every method
+/// checks the buffer for capacity and must be called from within a task.
+/// Once `bound` requests are queued further requests fail immediately
+/// with `Error::BufferFull`.
+///
 /// [consul]: https://www.hashicorp.com/products/consul
 pub struct Consul<T>
 where
@@ -36,9 +106,37 @@ where
 {
     scheme: String,
     authority: String,
-    inner: Buffer<IntoService<T>, Request<Bytes>>,
+    max_redirects: u8,
+    compression_threshold: Option<usize>,
+    raw_kv_values: bool,
+    pretty: bool,
+    capture_raw_body: bool,
+    datacenter: Option<String>,
+    default_tags: Vec<String>,
+    kv_prefix: KvPrefix,
+    token: Option<String>,
+    renewed_token: Option<Arc<Mutex<Option<String>>>>,
+    request_id_header: HeaderName,
+    request_id: Option<String>,
+    body_warning_threshold: Option<usize>,
+    body_stats: Arc<BodyStats>,
+    metrics: Arc<Counters>,
+    agent_cache: Arc<Mutex<AgentCache>>,
+    session_renewals: Arc<Mutex<HashMap<String, SystemTime>>>,
+    load_balance: LoadBalance,
+    balancer: Arc<Mutex<Balancer>>,
+    warnings_are_healthy: bool,
+    tagged_address: Option<TaggedAddress>,
+    skipped_hostnames: Arc<AtomicUsize>,
+    clock: Arc<dyn Clock>,
+    reconnect: Option<(usize, Arc<Mutex<Reconnect<T>>>)>,
+    epoch: Arc<AtomicUsize>,
+    inner: Inner<T>,
 }
 
+/// The buffer the requests of a client and its clones are sent through.
+type Inner<T> = Buffer<Abandonable<IntoService<T>>, Request<Bytes>>;
+
 impl<T> Clone for Consul<T>
 where
     T: HttpService<Bytes>,
@@ -47,301 +145,32 @@ where
         Consul {
             scheme: self.scheme.clone(),
             authority: self.authority.clone(),
+            max_redirects: self.max_redirects,
+            compression_threshold: self.compression_threshold,
+            raw_kv_values: self.raw_kv_values,
+            pretty: self.pretty,
+            capture_raw_body: self.capture_raw_body,
+            datacenter: self.datacenter.clone(),
+            default_tags: self.default_tags.clone(),
+            kv_prefix: self.kv_prefix.clone(),
+            token: self.token.clone(),
+            renewed_token: self.renewed_token.clone(),
+            request_id_header: self.request_id_header.clone(),
+            request_id: self.request_id.clone(),
+            body_warning_threshold: self.body_warning_threshold,
+            body_stats: self.body_stats.clone(),
+            metrics: self.metrics.clone(),
+            agent_cache: self.agent_cache.clone(),
+            session_renewals: self.session_renewals.clone(),
+            load_balance: self.load_balance,
+            balancer: self.balancer.clone(),
+            warnings_are_healthy: self.warnings_are_healthy,
+            tagged_address: self.tagged_address,
+            skipped_hostnames: self.skipped_hostnames.clone(),
+            clock: self.clock.clone(),
+            reconnect: self.reconnect.clone(),
+            epoch: self.epoch.clone(),
             inner: self.inner.clone(),
         }
     }
 }
-
-/// The future that represents the eventual value
-/// returned from the consul request.
-pub struct ConsulFuture<T, R>
-where
-    for<'de> R: Deserialize<'de>,
-    T: HttpService<Bytes, ResponseBody = Bytes>,
-    T::Future: futures::future::Future,
-    T::Error: Into<BoxError>,
-{
-    inner: ResponseFuture<T::Future>,
-    _pd: PhantomData<R>,
-}
-
-// == impl Consul ===
-
-impl<T> Consul<T>
-where
-    T: HttpService<Bytes, ResponseBody = Bytes> + Send + 'static,
-    T::Future: Send + 'static,
-    T::Error: Into<BoxError> + Send + Sync,
-{
-    /// Create a new consul client
-    pub fn new(inner: T, bound: usize, scheme: String, authority: String) -> Result<Self, Error> {
-        let inner = Buffer::new(inner.into_service(), bound);
-
-        Ok(Consul {
-            scheme,
-            authority,
-            inner,
-        })
-    }
-
-    /// Get a list of all Service members
-    pub fn get(&mut self, key: &str) -> impl Future<Item = Vec<KVValue>, Error = Error> {
-        let url = format!("/v1/kv/{}", key);
-        let request = match self.build(&url, Method::GET, Bytes::new()) {
-            Ok(req) => req,
-            Err(e) => return Either::A(future::err(e)),
-        };
-
-        Either::B(self.call(request))
-    }
-
-    /// Get a list of all Service members
-    pub fn get_keys(&mut self, key: &str) -> impl Future<Item = Vec<String>, Error = Error> {
-        let url = format!("/v1/kv/{}?keys", key);
-        let request = match self.build(&url, Method::GET, Bytes::new()) {
-            Ok(req) => req,
-            Err(e) => return Either::A(future::err(e)),
-        };
-
-        Either::B(self.call(request))
-    }
-
-    /// Set a value of bytes into the key
-    pub fn set(
-        &mut self,
-        key: &str,
-        value: impl Into<Bytes>,
-    ) -> impl Future<Item = bool, Error = Error> {
-        let url = format!("/v1/kv/{}", key);
-        let request = match self.build(&url, Method::PUT, value.into()) {
-            Ok(req) => req,
-            Err(e) => return Either::A(future::err(e)),
-        };
-
-        Either::B(self.call(request))
-    }
-
-    /// Delete a key and its value
-    pub fn delete(&mut self, key: &str) -> impl Future<Item = bool, Error = Error> {
-        let url = format!("/v1/kv/{}", key);
-        let request = match self.build(&url, Method::DELETE, Bytes::new()) {
-            Ok(req) => req,
-            Err(e) => return Either::A(future::err(e)),
-        };
-
-        Either::B(self.call(request))
-    }
-
-    /// Get a list of nodes that have registered via the provided service
-    pub fn service_nodes(
-        &mut self,
-        service: &str,
-    ) -> impl Future<Item = Vec<ConsulService>, Error = Error> {
-        let url = format!("/v1/catalog/service/{}", service);
-        let request = match self.build(&url, Method::GET, Bytes::new()) {
-            Ok(req) => req,
-            Err(e) => return Either::A(future::err(e)),
-        };
-
-        Either::B(self.call(request))
-    }
-
-    /// Register with the current agent with the service config
-    pub fn register(&mut self, service: impl Into<Bytes>) -> BoxConsulFuture<()> {
-        let url = "/v1/agent/service/register";
-        let request = match self.build(url, Method::PUT, service.into()) {
-            Ok(req) => req,
-            Err(e) => return Box::new(future::lazy(move || Box::new(future::err(e)))),
-        };
-
-        let fut = self
-            .inner
-            .call(request)
-            .map_err(|e| Error::Inner(e))
-            .then(|res| match res {
-                Ok(res) => Self::handle_status(res),
-                Err(e) => Err(e),
-            })
-            .map(|_| ());
-
-        Box::new(fut)
-    }
-
-    fn call<R>(&mut self, request: Request<Bytes>) -> ConsulFuture<T, R>
-    where
-        for<'de> R: Deserialize<'de> + Send + 'static,
-    {
-        let fut = self.inner.call(request);
-
-        ConsulFuture {
-            inner: fut,
-            _pd: PhantomData,
-        }
-    }
-
-    fn build(&self, url: &str, method: Method, body: Bytes) -> Result<Request<Bytes>, Error> {
-        let uri = Uri::builder()
-            .scheme(self.scheme.as_str())
-            .authority(self.authority.as_str())
-            .path_and_query(url)
-            .build()?;
-
-        Request::builder()
-            .uri(uri)
-            .method(method)
-            .body(body)
-            .map_err(Error::from)
-    }
-
-    fn handle_status(response: Response<Bytes>) -> Result<Response<Bytes>, Error> {
-        let status = response.status();
-
-        if status.is_success() | status.is_redirection() | status.is_informational() {
-            Ok(response)
-        } else if status == StatusCode::NOT_FOUND {
-            Err(Error::NotFound)
-        } else if status.is_client_error() {
-            let body = response.into_body();
-            let body = String::from_utf8_lossy(&body[..]).into_owned();
-            Err(Error::ConsulClient(body))
-        } else if status.is_server_error() {
-            let body = response.into_body();
-            let body = String::from_utf8_lossy(&body[..]).into_owned();
-            Err(Error::ConsulServer(body))
-        } else {
-            unreachable!("This is a bug!")
-        }
-    }
-}
-
-#[derive(Debug)]
-/// The Error returned by the client
-pub enum Error {
-    /// The requested resource does not exist
-    NotFound,
-    /// The consul http request returned a `4xx` response that is not
-    /// a `404`
-    ConsulClient(String),
-    /// The consul http request returned a `5xx` response
-    ConsulServer(String),
-    /// The inner service returned an error
-    Inner(Box<::std::error::Error + Send>),
-    /// There was an error creating and reading Response/Requests
-    Http(http::Error),
-    /// The error returned if the json parsing has failed
-    Json(serde_json::Error),
-    /// Error parsing the response string as utf8
-    StringUtf8(FromUtf8Error),
-    /// Error attempting to spawn the Buffer service
-    SpawnError,
-}
-
-impl From<serde_json::Error> for Error {
-    fn from(e: serde_json::Error) -> Self {
-        Error::Json(e)
-    }
-}
-
-impl From<FromUtf8Error> for Error {
-    fn from(e: FromUtf8Error) -> Self {
-        Error::StringUtf8(e)
-    }
-}
-
-impl From<http::Error> for Error {
-    fn from(e: http::Error) -> Self {
-        Error::Http(e)
-    }
-}
-
-impl From<SpawnError> for Error {
-    fn from(_: SpawnError) -> Self {
-        Error::SpawnError
-    }
-}
-
-impl From<Box<dyn std::error::Error + Send + Sync>> for Error {
-    fn from(e: Box<dyn std::error::Error + Send + Sync>) -> Self {
-        Error::Inner(e)
-    }
-}
-
-// == impl ConsulFuture ==
-
-impl<T, R> Future for ConsulFuture<T, R>
-where
-    for<'de> R: Deserialize<'de> + Send + 'static,
-    T: HttpService<Bytes, ResponseBody = Bytes>,
-    T::Error: Into<BoxError>,
-{
-    type Item = R;
-    type Error = Error;
-
-    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        let response = try_ready!(self.inner.poll().map_err(|e| Error::Inner(e)));
-
-        let status = response.status();
-
-        let body = if status.is_success() | status.is_redirection() | status.is_informational() {
-            response.into_body()
-        } else if status == StatusCode::NOT_FOUND {
-            return Err(Error::NotFound);
-        } else if status.is_client_error() {
-            let body = response.into_body();
-            let body = String::from_utf8_lossy(&body[..]).into_owned();
-            return Err(Error::ConsulClient(body));
-        } else if status.is_server_error() {
-            let body = response.into_body();
-            let body = String::from_utf8_lossy(&body[..]).into_owned();
-            return Err(Error::ConsulServer(body));
-        } else {
-            unreachable!("This is a bug!")
-        };
-
-        let body = serde_json::from_slice(&body[..])?;
-
-        Ok(Async::Ready(body))
-    }
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "PascalCase")]
-#[allow(missing_docs)]
-/// The value returned from consul
-///
-/// For more information on this go [here][value]
-/// [value]: https://www.consul.io/api/kv.html#read-key
-pub struct KVValue {
-    pub create_index: i64,
-    pub modify_index: i64,
-    pub lock_index: i64,
-    pub key: String,
-    pub flags: u8,
-    pub value: String,
-    pub session: Option<String>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "PascalCase")]
-#[allow(missing_docs)]
-/// The value returned from Consul on Service requests
-///
-/// For more information on this go [here][value]
-/// [value]: https://www.consul.io/api/agent/service.html#sample-response-1
-pub struct ConsulService {
-    #[serde(rename = "ServiceKind")]
-    pub kind: String,
-    #[serde(rename = "ID")]
-    pub id: String,
-    #[serde(rename = "ServiceID")]
-    pub service_id: String,
-    #[serde(rename = "ServiceName")]
-    pub service_name: String,
-    #[serde(rename = "ServiceTags")]
-    pub tags: Vec<String>,
-    #[serde(rename = "ServiceMeta")]
-    pub meta: HashMap<String, String>,
-    pub node: String,
-    pub address: String,
-    pub datacenter: String,
-}
@@ -0,0 +1,70 @@
+//! The metadata Consul returns in response headers
+
+use bytes::Bytes;
+use http::Response;
+use std::convert::TryFrom;
+use std::time::Duration;
+
+/// The metadata Consul returns in the headers of read responses
+///
+/// Every field is `None` when Consul did not send the header, not every
+/// endpoint or Consul version sends all of them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConsulMeta {
+    /// The `X-Consul-Index` to use for a blocking query on the result
+    pub index: Option<i64>,
+    /// Whether the server that answered knew of a leader
+    pub known_leader: Option<bool>,
+    /// How long ago the server that answered last heard from the leader,
+    /// only relevant to stale reads
+    pub last_contact: Option<Duration>,
+    /// The consistency mode the read was actually served with, which may
+    /// differ from the requested one, like `leader`, `stale` or
+    /// `consistent`
+    pub effective_consistency: Option<String>,
+    /// The body exactly as Consul returned it, only set when the client
+    /// was built with `with_raw_body_capture`
+    pub raw_body: Option<Bytes>,
+    /// Whether a read from the agent cache was answered from the cache,
+    /// only set for cached reads like `discover_cached`
+    pub cache_hit: Option<bool>,
+    /// How old the cached result was, only set on a cache hit
+    pub cache_age: Option<Duration>,
+}
+
+impl ConsulMeta {
+    pub(crate) fn from_response(response: &Response<Bytes>) -> Self {
+        let header = |name: &str| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+        };
+
+        ConsulMeta {
+            index: header("X-Consul-Index").and_then(parse_index),
+            known_leader: header("X-Consul-KnownLeader").and_then(|known| known.parse().ok()),
+            last_contact: header("X-Consul-LastContact")
+                .and_then(|ms| ms.parse().ok())
+                .map(Duration::from_millis),
+            effective_consistency: header("X-Consul-Effective-Consistency").map(String::from),
+            raw_body: None,
+            cache_hit: header("X-Cache").map(|cache| cache == "HIT"),
+            cache_age: header("Age")
+                .and_then(|secs| secs.parse().ok())
+                .map(Duration::from_secs),
+        }
+    }
+}
+
+/// Parse an `X-Consul-Index` header.
+///
+/// Consul indices are unsigned 64 bit integers on the wire, values that do
+/// not fit in an `i64` saturate at `i64::MAX` rather than being rejected or
+/// wrapping around to a negative index.
+pub(crate) fn parse_index(index: &str) -> Option<i64> {
+    index
+        .parse::<u64>()
+        .ok()
+        .map(|index| i64::try_from(index).unwrap_or(i64::MAX))
+}
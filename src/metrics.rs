@@ -0,0 +1,152 @@
+//! Request counters labeled by the area of the API
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The area of the Consul API a request was sent to, taken from the
+/// first segment of the path after `/v1/`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    /// `/v1/kv`
+    Kv,
+    /// `/v1/catalog`
+    Catalog,
+    /// `/v1/health`
+    Health,
+    /// `/v1/agent`
+    Agent,
+    /// `/v1/session`
+    Session,
+    /// `/v1/acl`
+    Acl,
+    /// `/v1/txn`
+    Txn,
+    /// `/v1/status`
+    Status,
+    /// `/v1/operator`
+    Operator,
+    /// Any other path, like those sent with `Consul::execute`
+    Other,
+}
+
+impl Operation {
+    const ALL: [Operation; 10] = [
+        Operation::Kv,
+        Operation::Catalog,
+        Operation::Health,
+        Operation::Agent,
+        Operation::Session,
+        Operation::Acl,
+        Operation::Txn,
+        Operation::Status,
+        Operation::Operator,
+        Operation::Other,
+    ];
+
+    pub(crate) fn from_path(path: &str) -> Self {
+        let area = match path.strip_prefix("/v1/") {
+            Some(rest) => rest.split('/').next().unwrap_or(""),
+            None => return Operation::Other,
+        };
+
+        match area {
+            "kv" => Operation::Kv,
+            "catalog" => Operation::Catalog,
+            "health" => Operation::Health,
+            "agent" => Operation::Agent,
+            "session" => Operation::Session,
+            "acl" => Operation::Acl,
+            "txn" => Operation::Txn,
+            "status" => Operation::Status,
+            "operator" => Operation::Operator,
+            _ => Operation::Other,
+        }
+    }
+
+    /// The label of the operation, like `kv` or `catalog`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Operation::Kv => "kv",
+            Operation::Catalog => "catalog",
+            Operation::Health => "health",
+            Operation::Agent => "agent",
+            Operation::Session => "session",
+            Operation::Acl => "acl",
+            Operation::Txn => "txn",
+            Operation::Status => "status",
+            Operation::Operator => "operator",
+            Operation::Other => "other",
+        }
+    }
+
+    fn slot(self) -> usize {
+        self as usize
+    }
+}
+
+/// The counters shared by a client and its clones.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    requests: [AtomicUsize; 10],
+    errors: [AtomicUsize; 10],
+}
+
+impl Counters {
+    pub(crate) fn record(&self, operation: Operation, failed: bool) {
+        self.requests[operation.slot()].fetch_add(1, Ordering::Relaxed);
+
+        if failed {
+            self.errors[operation.slot()].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> ConsulMetrics {
+        let load = |counters: &[AtomicUsize; 10]| {
+            Operation::ALL
+                .iter()
+                .map(|op| (*op, counters[op.slot()].load(Ordering::Relaxed)))
+                .filter(|(_, count)| *count > 0)
+                .collect()
+        };
+
+        ConsulMetrics {
+            requests: load(&self.requests),
+            errors: load(&self.errors),
+        }
+    }
+}
+
+/// A snapshot of the requests sent by a client and its clones, labeled
+/// by `Operation`
+///
+/// Every request sent to the inner service is counted, including each hop
+/// of a followed redirect. A request is counted as an error when the inner
+/// service fails or Consul responds with a `5xx`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConsulMetrics {
+    requests: HashMap<Operation, usize>,
+    errors: HashMap<Operation, usize>,
+}
+
+impl ConsulMetrics {
+    /// The number of requests sent for `operation`
+    pub fn requests(&self, operation: Operation) -> usize {
+        self.requests.get(&operation).cloned().unwrap_or(0)
+    }
+
+    /// The number of requests for `operation` that failed
+    pub fn errors(&self, operation: Operation) -> usize {
+        self.errors.get(&operation).cloned().unwrap_or(0)
+    }
+
+    /// The number of requests sent for every operation
+    pub fn total_requests(&self) -> usize {
+        self.requests.values().sum()
+    }
+
+    /// The number of requests sent per operation, operations without any
+    /// requests are left out
+    pub fn by_operation(&self) -> &HashMap<Operation, usize> {
+        &self.requests
+    }
+}
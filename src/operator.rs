@@ -0,0 +1,231 @@
+//! Cluster status and raft operations
+
+use crate::{BoxConsulFuture, BoxError, Consul, ConsulDuration, Error, Query};
+use bytes::Bytes;
+use futures::future;
+use futures::future::Either;
+use futures::Future;
+use http::Method;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use tower_http_util::service::HttpService;
+
+impl<T> Consul<T>
+where
+    T: HttpService<Bytes, ResponseBody = Bytes> + Send + 'static,
+    T::Future: Send + 'static,
+    T::Error: Into<BoxError> + Send + Sync,
+{
+    /// Get the address of the raft leader of the datacenter
+    pub fn status_leader(&mut self) -> impl Future<Item = String, Error = Error> {
+        self.status_leader_in(None)
+    }
+
+    /// Send a cheap request so the transport opens its connection before
+    /// real traffic, sparing the first request the connection setup
+    ///
+    /// This reads the raft leader and discards it. Any response from
+    /// Consul, including one saying there is no leader, means the
+    /// connection is open and resolves successfully, only transport
+    /// failures are returned. Warming up only helps when the inner
+    /// `HttpService` keeps connections open in a pool, like the hyper
+    /// client, otherwise every request still connects anew.
+    pub fn warm_up(&mut self) -> impl Future<Item = (), Error = Error> {
+        self.status_leader().then(|res| match res {
+            Ok(_)
+            | Err(Error::NotFound)
+            | Err(Error::ConsulClient(_))
+            | Err(Error::ConsulServer(_))
+            | Err(Error::NoClusterLeader) => Ok(()),
+            Err(e) => Err(e),
+        })
+    }
+
+    /// Get the address of the raft leader of every known datacenter,
+    /// keyed by datacenter
+    ///
+    /// The leaders are queried concurrently. A datacenter that has no
+    /// leader, or whose servers can not be reached, maps to an empty
+    /// string.
+    pub fn all_dc_leaders(&mut self) -> impl Future<Item = HashMap<String, String>, Error = Error> {
+        let mut client = self.clone();
+
+        self.datacenters().and_then(move |dcs| {
+            let leaders = dcs
+                .into_iter()
+                .map(|dc| {
+                    client.status_leader_in(Some(&dc)).then(|res| match res {
+                        Ok(leader) => Ok((dc, leader)),
+                        Err(Error::ConsulServer(_)) | Err(Error::NoClusterLeader) => {
+                            Ok((dc, String::new()))
+                        }
+                        Err(e) => Err(e),
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            future::join_all(leaders).map(|leaders| leaders.into_iter().collect())
+        })
+    }
+
+    fn status_leader_in(&mut self, dc: Option<&str>) -> impl Future<Item = String, Error = Error> {
+        let url = Query::default()
+            .opt("dc", dc)
+            .append_to("/v1/status/leader");
+        let request = match self.build(&url, Method::GET, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        Either::B(self.call(request))
+    }
+
+    /// Get the raft peer configuration of the cluster
+    pub fn raft_configuration(&mut self) -> impl Future<Item = RaftConfiguration, Error = Error> {
+        let url = "/v1/operator/raft/configuration";
+        let request = match self.build(url, Method::GET, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        Either::B(self.call(request))
+    }
+
+    /// Get the autopilot configuration of the cluster
+    pub fn autopilot_configuration(
+        &mut self,
+    ) -> impl Future<Item = AutopilotConfig, Error = Error> {
+        let url = "/v1/operator/autopilot/configuration";
+        let request = match self.build(url, Method::GET, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        Either::B(self.call(request))
+    }
+
+    /// Replace the autopilot configuration of the cluster
+    ///
+    /// Every field is written, Consul resets any field it is not sent. To
+    /// change a single setting read the configuration with
+    /// `autopilot_configuration` first and update it.
+    pub fn set_autopilot_configuration(&mut self, cfg: AutopilotConfig) -> BoxConsulFuture<()> {
+        let body = match serde_json::to_vec(&cfg) {
+            Ok(body) => body,
+            Err(e) => return Box::new(future::err(Error::from(e))),
+        };
+
+        let url = "/v1/operator/autopilot/configuration";
+        let request = match self.build(url, Method::PUT, body.into()) {
+            Ok(req) => req,
+            Err(e) => return Box::new(future::err(e)),
+        };
+
+        let fut = self
+            .send(request)
+            .then(|res| match res {
+                Ok(res) => Self::handle_status(res),
+                Err(e) => Err(e),
+            })
+            .map(|_| ());
+
+        Box::new(fut)
+    }
+
+    /// Remove the server with the provided address from the raft peer set
+    ///
+    /// This should only be used to evict a server that has permanently
+    /// failed and can not leave the cluster gracefully.
+    pub fn raft_remove_peer(&mut self, address: &str) -> BoxConsulFuture<()> {
+        self.raft_remove("address", address)
+    }
+
+    /// Remove the server with the provided id from the raft peer set
+    pub fn raft_remove_peer_by_id(&mut self, id: &str) -> BoxConsulFuture<()> {
+        self.raft_remove("id", id)
+    }
+
+    fn raft_remove(&mut self, param: &str, value: &str) -> BoxConsulFuture<()> {
+        if value.is_empty() {
+            let e = Error::InvalidArgument(format!("raft peer {} must not be empty", param));
+            return Box::new(future::err(e));
+        }
+
+        let url = Query::default()
+            .param(param, value)
+            .append_to("/v1/operator/raft/peer");
+        let request = match self.build(&url, Method::DELETE, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Box::new(future::err(e)),
+        };
+
+        let fut = self
+            .send(request)
+            .then(|res| match res {
+                Ok(res) => Self::handle_status(res),
+                Err(e) => Err(e),
+            })
+            .map(|_| ());
+
+        Box::new(fut)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// The raft configuration returned from Consul
+///
+/// For more information on this go [here][value]
+/// [value]: https://www.consul.io/api/operator/raft.html#read-configuration
+pub struct RaftConfiguration {
+    pub servers: Vec<RaftServer>,
+    pub index: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// A single server within the raft configuration
+pub struct RaftServer {
+    #[serde(rename = "ID")]
+    pub id: String,
+    pub node: String,
+    pub address: String,
+    pub leader: bool,
+    pub voter: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// The autopilot configuration of the cluster
+///
+/// For more information on this go [here][value]
+/// [value]: https://www.consul.io/api/operator/autopilot.html#read-configuration
+pub struct AutopilotConfig {
+    /// Remove failed servers once a replacement has joined
+    pub cleanup_dead_servers: bool,
+    /// How long a server may go without contacting the leader before it
+    /// is considered unhealthy
+    pub last_contact_threshold: ConsulDuration,
+    /// How many log entries a server may trail the leader by before it is
+    /// considered unhealthy
+    pub max_trailing_logs: u64,
+    #[serde(default)]
+    pub min_quorum: u32,
+    /// How long a new server must be healthy before it is promoted to a
+    /// voter
+    pub server_stabilization_time: ConsulDuration,
+    #[serde(default)]
+    pub redundancy_zone_tag: String,
+    #[serde(default)]
+    pub disable_upgrade_migration: bool,
+    #[serde(default)]
+    pub upgrade_version_tag: String,
+    #[serde(default, skip_serializing)]
+    pub create_index: i64,
+    #[serde(default, skip_serializing)]
+    pub modify_index: i64,
+}
@@ -0,0 +1,54 @@
+//! Query strings of requests
+
+/// Builds the query string of a request, every value is percent
+/// encoded
+#[derive(Debug, Default)]
+pub(crate) struct Query {
+    pairs: Vec<String>,
+}
+
+impl Query {
+    /// Add a parameter without a value, like `?keys`
+    pub(crate) fn flag(&mut self, key: &str) -> &mut Self {
+        self.pairs.push(key.to_string());
+        self
+    }
+
+    pub(crate) fn param(&mut self, key: &str, value: &str) -> &mut Self {
+        self.pairs.push(format!("{}={}", key, encode(value)));
+        self
+    }
+
+    pub(crate) fn opt<V: AsRef<str>>(&mut self, key: &str, value: Option<V>) -> &mut Self {
+        if let Some(value) = value {
+            self.param(key, value.as_ref());
+        }
+        self
+    }
+
+    /// Append the query to `path`, which may already contain a query
+    pub(crate) fn append_to(&self, path: &str) -> String {
+        if self.pairs.is_empty() {
+            return path.to_string();
+        }
+
+        let separator = if path.contains('?') { '&' } else { '?' };
+        format!("{}{}{}", path, separator, self.pairs.join("&"))
+    }
+}
+
+/// Percent encode a value for use within a query string
+fn encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+
+    for b in value.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(b as char)
+            }
+            b => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+
+    encoded
+}
@@ -0,0 +1,489 @@
+//! Sessions
+
+use crate::acl::{RenewStep, RENEW_RETRY};
+use crate::{null_to_default, BoxError, Consul, ConsulDuration, ConsulFuture, Error};
+use bytes::Bytes;
+use futures::future;
+use futures::future::Either;
+use futures::future::Loop;
+use futures::sync::oneshot;
+use futures::Future;
+use http::Method;
+use serde::de;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use std::cmp;
+use std::collections::HashSet;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+use tokio_executor::{DefaultExecutor, Executor};
+use tower_http_util::service::HttpService;
+
+impl<T> Consul<T>
+where
+    T: HttpService<Bytes, ResponseBody = Bytes> + Send + 'static,
+    T::Future: Send + 'static,
+    T::Error: Into<BoxError> + Send + Sync,
+{
+    /// Create a new session, returning its id
+    ///
+    /// Sessions can not span datacenters, the session is created in the
+    /// datacenter set with `with_datacenter` or the one of the agent. Locks
+    /// must be acquired and released in that same datacenter, use a clone
+    /// with `with_datacenter` to manage sessions in another datacenter.
+    ///
+    /// A session with the `delete` behavior that depends on no checks at
+    /// all must have a `ttl`, otherwise it is never invalidated and the
+    /// keys it locks are never deleted. Such a session fails with
+    /// `Error::InvalidArgument` without being sent.
+    pub fn create_session(
+        &mut self,
+        session: &SessionRequest,
+    ) -> impl Future<Item = String, Error = Error> {
+        let unchecked =
+            session.checks.is_empty() && session.node_checks.as_ref().is_some_and(Vec::is_empty);
+        if session.behavior.as_deref() == Some("delete") && unchecked && session.ttl.is_none() {
+            let msg = "a delete session without checks needs a TTL".to_string();
+            return Either::A(future::err(Error::InvalidArgument(msg)));
+        }
+
+        let body = match serde_json::to_vec(session) {
+            Ok(body) => body,
+            Err(e) => return Either::A(future::err(Error::from(e))),
+        };
+
+        let request = match self.build("/v1/session/create", Method::PUT, body.into()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        let renewals = self.session_renewals.clone();
+        let wall_clock = self.clock.clone();
+
+        // Consul does not report when a session was created, the creation
+        // counts as its first renewal for `session_ttl_remaining`.
+        let fut: ConsulFuture<T, SessionId> = self.call(request);
+        Either::B(fut.map(move |session| {
+            let mut renewals = renewals.lock().unwrap();
+            renewals.insert(session.id.clone(), wall_clock.now());
+            session.id
+        }))
+    }
+
+    /// Destroy a session, releasing or deleting any locks it holds
+    /// depending on its `Behavior`
+    pub fn destroy_session(&mut self, id: &str) -> impl Future<Item = bool, Error = Error> {
+        self.session_renewals.lock().unwrap().remove(id);
+
+        let url = format!("/v1/session/destroy/{}", id);
+        let request = match self.build(&url, Method::PUT, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        Either::B(self.call(request))
+    }
+
+    /// Renew a session with a `TTL`, resolving to the renewed session
+    ///
+    /// Fails with `Error::NotFound` if the session does not exist in the
+    /// datacenter, either because it was invalidated or because it was
+    /// created in another one.
+    pub fn renew_session(&mut self, id: &str) -> impl Future<Item = SessionEntry, Error = Error> {
+        let url = format!("/v1/session/renew/{}", id);
+        let request = match self.build(&url, Method::PUT, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        let id = id.to_string();
+        let renewals = self.session_renewals.clone();
+        let wall_clock = self.clock.clone();
+
+        let fut: ConsulFuture<T, Vec<SessionEntry>> = self.call(request);
+        Either::B(fut.and_then(move |mut sessions| {
+            let mut renewals = renewals.lock().unwrap();
+            match sessions.pop() {
+                Some(session) => {
+                    renewals.insert(id, wall_clock.now());
+                    Ok(session)
+                }
+                None => {
+                    renewals.remove(&id);
+                    Err(Error::NotFound)
+                }
+            }
+        }))
+    }
+
+    /// Get how much of the `TTL` of a session is left before Consul may
+    /// invalidate it
+    ///
+    /// Consul does not report when a session was last renewed, the TTL is
+    /// counted from the creation or last renewal of the session through
+    /// this client or its clones, or from the `CreateTime` if Consul
+    /// reports one. Renewals by other clients are not seen, so the result
+    /// is a lower bound. Consul waits up to twice the TTL before it invalidates a
+    /// session, a remaining TTL of zero does not mean it is gone yet.
+    ///
+    /// Resolves to `None` if the session does not exist, has no TTL, or
+    /// was neither created nor renewed through this client and Consul
+    /// did not report its create time.
+    pub fn session_ttl_remaining(
+        &mut self,
+        id: &str,
+    ) -> impl Future<Item = Option<Duration>, Error = Error> {
+        let renewals = self.session_renewals.clone();
+        let wall_clock = self.clock.clone();
+
+        self.session_info(id).map(move |session| {
+            let session = session?;
+            let ttl = Duration::from(session.ttl?);
+            let renewed = renewals.lock().unwrap().get(&session.id).cloned();
+            let since = match (renewed, session.created_at()) {
+                (Some(renewed), Some(created)) => cmp::max(renewed, created),
+                (renewed, created) => renewed.or(created)?,
+            };
+
+            let elapsed = wall_clock.now().duration_since(since).unwrap_or_default();
+            Some(ttl.checked_sub(elapsed).unwrap_or_default())
+        })
+    }
+
+    /// Keep a session with a `TTL` alive until the returned guard is
+    /// dropped
+    ///
+    /// This spawns a task on the default executor that renews the session
+    /// right away and then whenever half of its TTL has passed. A failed
+    /// renewal is retried every 5 seconds. The task stops when the guard is
+    /// dropped, when the session no longer exists or when it has no TTL.
+    /// Dropping the guard does not destroy the session, it expires once
+    /// its TTL runs out unless it is destroyed with `destroy_session`.
+    pub fn renew_session_loop(&mut self, id: &str) -> Result<SessionKeepAlive, Error> {
+        let client = self.clone();
+        let id = id.to_string();
+
+        let renewals = future::loop_fn((client, id), |(mut client, id)| {
+            let step = client.renew_session(&id).then(move |res| {
+                let wait = match res {
+                    Ok(session) => match session.ttl {
+                        Some(ttl) => cmp::max(Duration::from(ttl) / 2, Duration::from_secs(1)),
+                        None => return Box::new(future::ok(Loop::Break(()))) as RenewStep<_>,
+                    },
+                    Err(Error::NotFound) => {
+                        return Box::new(future::ok(Loop::Break(()))) as RenewStep<_>
+                    }
+                    Err(_) => RENEW_RETRY,
+                };
+
+                Self::renew_later(wait, client, id)
+            });
+
+            Box::new(step) as RenewStep<_>
+        });
+
+        let (stop, stopped) = oneshot::channel();
+        let task = renewals.select2(stopped).then(|_| Ok(()));
+
+        DefaultExecutor::current()
+            .spawn(Box::new(task))
+            .map_err(|_| Error::SpawnError)?;
+
+        Ok(SessionKeepAlive { _stop: stop })
+    }
+
+    /// Read a session, resolving to `None` if it does not exist in the
+    /// datacenter
+    pub fn session_info(
+        &mut self,
+        id: &str,
+    ) -> impl Future<Item = Option<SessionEntry>, Error = Error> {
+        let url = format!("/v1/session/info/{}", id);
+        let request = match self.build(&url, Method::GET, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        // Consul responds with `null` rather than an empty list for an
+        // unknown session.
+        let fut: ConsulFuture<T, Option<Vec<SessionEntry>>> = self.call(request);
+        Either::B(fut.map(|sessions| sessions.and_then(|mut sessions| sessions.pop())))
+    }
+
+    /// Check that the session exists in the datacenter this client sends
+    /// requests to, failing with `Error::InvalidArgument` if it does not
+    ///
+    /// A lock can only be acquired by a session of the same datacenter,
+    /// this detects a session created through a client configured with a
+    /// different datacenter before it is used with `acquire`.
+    pub fn validate_session(&mut self, id: &str) -> impl Future<Item = (), Error = Error> {
+        let id = id.to_string();
+        let dc = self.datacenter.clone();
+
+        self.session_info(&id)
+            .and_then(move |session| match session {
+                Some(_) => Ok(()),
+                None => {
+                    let dc = dc.unwrap_or_else(|| "of the agent".into());
+                    let msg = format!("session {} does not exist in datacenter {}", id, dc);
+                    Err(Error::InvalidArgument(msg))
+                }
+            })
+    }
+
+    /// List every active session in the datacenter
+    pub fn list_sessions(&mut self) -> impl Future<Item = Vec<SessionEntry>, Error = Error> {
+        let request = match self.build("/v1/session/list", Method::GET, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        Either::B(self.call(request))
+    }
+
+    /// Destroy every session that was neither created nor renewed in the
+    /// last `older_than` and holds no lock under `lock_prefix`, returning
+    /// how many sessions were destroyed
+    ///
+    /// Consul does not report when a session was created or renewed, the
+    /// age of a session is counted from its creation or last renewal
+    /// through this client or its clones, or from the `CreateTime` if
+    /// Consul reports one. Sessions whose age is unknown are never
+    /// destroyed.
+    ///
+    /// Consul can not look up the locks of a session, the keys under
+    /// `lock_prefix` are read to find them, only when at least one session
+    /// is old enough to be destroyed. `lock_prefix` is relative to the
+    /// prefix set with `with_kv_prefix`, locks held outside of it do not
+    /// keep their session alive.
+    pub fn destroy_expired_sessions(
+        &mut self,
+        older_than: Duration,
+        lock_prefix: &str,
+    ) -> impl Future<Item = usize, Error = Error> {
+        let now = self.clock.now();
+        let renewals = self.session_renewals.clone();
+        let lock_prefix = lock_prefix.to_string();
+        let mut client = self.clone();
+
+        self.list_sessions().and_then(move |sessions| {
+            let renewals = renewals.lock().unwrap();
+            let expired = sessions
+                .into_iter()
+                .filter(|session| {
+                    let renewed = renewals.get(&session.id).cloned();
+                    let since = match (renewed, session.created_at()) {
+                        (Some(renewed), Some(created)) => cmp::max(renewed, created),
+                        (renewed, created) => match renewed.or(created) {
+                            Some(since) => since,
+                            None => return false,
+                        },
+                    };
+
+                    now.duration_since(since)
+                        .map(|age| age > older_than)
+                        .unwrap_or(false)
+                })
+                .map(|session| session.id)
+                .collect::<Vec<_>>();
+            drop(renewals);
+
+            if expired.is_empty() {
+                return Either::A(future::ok(0));
+            }
+
+            let mut destroyer = client.clone();
+            let fut = client.get_recurse(&lock_prefix).and_then(move |values| {
+                let locked = values
+                    .into_iter()
+                    .filter_map(|value| value.session)
+                    .collect::<HashSet<_>>();
+
+                let destroys = expired
+                    .into_iter()
+                    .filter(|id| !locked.contains(id))
+                    .map(|id| destroyer.destroy_session(&id))
+                    .collect::<Vec<_>>();
+
+                future::join_all(destroys)
+                    .map(|destroyed| destroyed.into_iter().filter(|ok| *ok).count())
+            });
+
+            Either::B(fut)
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// The session to create with `Consul::create_session`
+///
+/// For more information on this go [here][value]
+/// [value]: https://www.consul.io/api/session.html#create-session
+pub struct SessionRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The node the session belongs to, by default the node of the agent
+    /// that handles the request
+    ///
+    /// A node registered through the catalog rather than by an agent has
+    /// no `serfHealth` check, set `node_checks` to leave it out.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node: Option<String>,
+    /// How long a lock held by the session stays unavailable after the
+    /// session is invalidated, Consul defaults to 15 seconds
+    ///
+    /// See `Consul::acquire`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lock_delay: Option<ConsulDuration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub behavior: Option<String>,
+    #[serde(rename = "TTL", skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<ConsulDuration>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub checks: Vec<String>,
+    /// The node checks the session depends on, `None` uses Consul's
+    /// default of `serfHealth` and `Some(vec![])` depends on none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node_checks: Option<Vec<String>>,
+}
+
+/// Keeps a session alive while it is held, see
+/// `Consul::renew_session_loop`
+///
+/// Dropping the guard stops the renewals.
+#[derive(Debug)]
+pub struct SessionKeepAlive {
+    _stop: oneshot::Sender<()>,
+}
+
+#[derive(Deserialize)]
+struct SessionId {
+    #[serde(rename = "ID")]
+    id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// An active session returned from Consul
+///
+/// For more information on this go [here][value]
+/// [value]: https://www.consul.io/api/session.html#list-sessions
+pub struct SessionEntry {
+    #[serde(rename = "ID")]
+    pub id: String,
+    #[serde(default)]
+    pub name: String,
+    pub node: String,
+    #[serde(default, deserialize_with = "null_to_default")]
+    pub checks: Vec<String>,
+    /// The lock delay in nanoseconds
+    #[serde(default)]
+    pub lock_delay: u64,
+    #[serde(default)]
+    pub behavior: String,
+    #[serde(rename = "TTL", default, deserialize_with = "empty_as_none")]
+    pub ttl: Option<ConsulDuration>,
+    /// The RFC 3339 time the session was created at, not every version
+    /// of Consul reports this
+    #[serde(default)]
+    pub create_time: Option<String>,
+    pub create_index: i64,
+    pub modify_index: i64,
+}
+
+impl SessionEntry {
+    /// The time the session was created at, if Consul reported a valid
+    /// create time
+    pub fn created_at(&self) -> Option<SystemTime> {
+        self.create_time
+            .as_ref()
+            .and_then(|time| parse_rfc3339(time))
+    }
+}
+
+/// Deserialize an empty string as `None`, Consul reports unset
+/// durations as `""`
+fn empty_as_none<'de, D>(deserializer: D) -> Result<Option<ConsulDuration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = Option::<String>::deserialize(deserializer)?.unwrap_or_default();
+    if value.is_empty() {
+        return Ok(None);
+    }
+
+    value.parse().map(Some).map_err(de::Error::custom)
+}
+
+/// Parse an RFC 3339 timestamp like `2019-04-10T16:30:59.123Z` as
+/// produced by Consul
+pub(crate) fn parse_rfc3339(time: &str) -> Option<SystemTime> {
+    fn num(s: &str) -> Option<i64> {
+        if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        s.parse().ok()
+    }
+
+    let (date, time) = (time.get(..10)?, time.get(11..)?);
+    let (year, month, day) = (
+        num(date.get(..4)?)?,
+        num(date.get(5..7)?)?,
+        num(date.get(8..)?)?,
+    );
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // The offset starts at the `Z` or the sign after the seconds
+    let offset_at = time.find(['Z', 'z', '+', '-'])?;
+    let (clock, offset) = time.split_at(offset_at);
+    let offset = match offset {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = if offset.starts_with('-') { -1 } else { 1 };
+            let hours = num(offset.get(1..3)?)?;
+            let minutes = num(offset.get(4..)?)?;
+            sign * (hours * 3600 + minutes * 60)
+        }
+    };
+
+    let (clock, nanos) = match clock.find('.') {
+        Some(dot) => {
+            let fraction = &clock[dot + 1..];
+            let digits = fraction.get(..cmp::min(fraction.len(), 9))?;
+            let nanos = num(digits)? * 10i64.pow(9 - digits.len() as u32);
+            (&clock[..dot], nanos as u32)
+        }
+        None => (clock, 0),
+    };
+    let (hour, minute, second) = (
+        num(clock.get(..2)?)?,
+        num(clock.get(3..5)?)?,
+        num(clock.get(6..)?)?,
+    );
+
+    // Days since the epoch from the civil date
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * m + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+
+    let secs = days * 86_400 + hour * 3600 + minute * 60 + second - offset;
+    if secs < 0 {
+        return None;
+    }
+
+    Some(UNIX_EPOCH + Duration::new(secs as u64, nanos))
+}
@@ -0,0 +1,454 @@
+//! Sending requests through the buffered service, following redirects
+//! and compressing bodies
+
+use crate::abandon::{Abandonable, Abandoned, Epoch};
+use crate::error::{from_json, server_error};
+use crate::{BoxConsulFuture, BoxError, Consul, ConsulMeta, Error, Inner, Operation, Query};
+use bytes::Bytes;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::future::{self, Either, Loop};
+use futures::{try_ready, Async, Future, Poll};
+use http::header::{CONTENT_ENCODING, LOCATION};
+use http::uri::PathAndQuery;
+use http::{Method, Request, Response, StatusCode, Uri};
+use serde::Deserialize;
+use std::io::Write;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tower_buffer::Buffer;
+use tower_http_util::service::HttpService;
+
+/// The header used to authenticate requests with an ACL token
+const CONSUL_TOKEN: &str = "X-Consul-Token";
+
+/// The factory and buffer shared by the clones of a client created with
+/// `Consul::from_factory`.
+pub(crate) struct Reconnect<T>
+where
+    T: HttpService<Bytes>,
+{
+    pub(crate) factory: Box<dyn FnMut() -> T + Send>,
+    pub(crate) bound: usize,
+    pub(crate) failure_threshold: usize,
+    pub(crate) failures: usize,
+    pub(crate) generation: usize,
+    pub(crate) epoch: Arc<AtomicUsize>,
+    pub(crate) inner: Inner<T>,
+}
+
+impl<T> Reconnect<T>
+where
+    T: HttpService<Bytes, ResponseBody = Bytes>,
+    T: Send + 'static,
+    T::Future: Send + 'static,
+    T::Error: Into<BoxError> + Send + Sync,
+{
+    fn fail(&mut self) {
+        self.failures += 1;
+
+        if self.failures >= self.failure_threshold {
+            self.failures = 0;
+            self.generation += 1;
+            let service = Abandonable::new((self.factory)().into_service(), self.epoch.clone());
+            self.inner = Buffer::new(service, self.bound);
+        }
+    }
+}
+
+/// The future that represents the eventual value
+/// returned from the consul request.
+pub struct ConsulFuture<T, R>
+where
+    for<'de> R: Deserialize<'de>,
+    T: HttpService<Bytes, ResponseBody = Bytes>,
+    T::Future: futures::future::Future,
+    T::Error: Into<BoxError>,
+{
+    inner: BoxConsulFuture<Response<Bytes>>,
+    _pd: PhantomData<(T, R)>,
+}
+
+// == impl Consul ===
+
+impl<T> Consul<T>
+where
+    T: HttpService<Bytes, ResponseBody = Bytes> + Send + 'static,
+    T::Future: Send + 'static,
+    T::Error: Into<BoxError> + Send + Sync,
+{
+    /// Send a fully formed request through the buffered service
+    ///
+    /// This is the lowest level entry point of the client. If the URI of
+    /// `request` is relative, like `/v1/agent/self`, the scheme and
+    /// authority of the client are filled in, otherwise the request is
+    /// sent as is. Nothing else is added: no token, request id,
+    /// datacenter or compression, redirects are not followed and the
+    /// status of the response is not checked.
+    pub fn execute(
+        &mut self,
+        request: Request<Bytes>,
+    ) -> impl Future<Item = Response<Bytes>, Error = Error> {
+        let (mut parts, body) = request.into_parts();
+
+        if parts.uri.authority_part().is_none() {
+            let mut uri = parts.uri.into_parts();
+            uri.scheme = Some(match self.scheme.parse() {
+                Ok(scheme) => scheme,
+                Err(e) => return Either::A(future::err(http::Error::from(e).into())),
+            });
+            uri.authority = Some(match self.authority.parse() {
+                Ok(authority) => authority,
+                Err(e) => return Either::A(future::err(http::Error::from(e).into())),
+            });
+            if uri.path_and_query.is_none() {
+                uri.path_and_query = Some(PathAndQuery::from_static("/"));
+            }
+
+            parts.uri = match Uri::from_parts(uri) {
+                Ok(uri) => uri,
+                Err(e) => return Either::A(future::err(http::Error::from(e).into())),
+            };
+        }
+
+        Either::B(self.send_once(Request::from_parts(parts, body)))
+    }
+
+    /// Send a request to an endpoint that responds with JSON and
+    /// deserialize the body into `R`.
+    ///
+    /// Endpoints that respond with a bare JSON value, like
+    /// `/v1/status/leader`, still belong here. Endpoints that respond with
+    /// arbitrary bytes, like `?raw` KV reads, must use `call_raw`.
+    pub(crate) fn call<R>(&mut self, request: Request<Bytes>) -> ConsulFuture<T, R>
+    where
+        for<'de> R: Deserialize<'de> + Send + 'static,
+    {
+        ConsulFuture {
+            inner: Box::new(self.send(request)),
+            _pd: PhantomData,
+        }
+    }
+
+    /// Like `call` but also return the metadata of the response
+    pub(crate) fn call_with_meta<R>(
+        &mut self,
+        request: Request<Bytes>,
+    ) -> impl Future<Item = (R, ConsulMeta), Error = Error>
+    where
+        for<'de> R: Deserialize<'de> + Send + 'static,
+    {
+        let capture = self.capture_raw_body;
+
+        self.send(request).and_then(move |res| {
+            let res = Self::handle_status(res)?;
+            let mut meta = ConsulMeta::from_response(&res);
+            let value = from_json(&res.body()[..])?;
+
+            if capture {
+                meta.raw_body = Some(res.into_body());
+            }
+
+            Ok((value, meta))
+        })
+    }
+
+    /// Send a request to an endpoint that responds with a body that is
+    /// not JSON and return the body as is.
+    pub(crate) fn call_raw(
+        &mut self,
+        request: Request<Bytes>,
+    ) -> impl Future<Item = Bytes, Error = Error> {
+        self.send(request)
+            .and_then(|res| Self::handle_status(res).map(Response::into_body))
+    }
+
+    pub(crate) fn send(
+        &mut self,
+        request: Request<Bytes>,
+    ) -> impl Future<Item = Response<Bytes>, Error = Error> {
+        if self.max_redirects == 0 {
+            return Either::A(self.send_once(request));
+        }
+
+        let hops = self.max_redirects;
+        let client = self.clone();
+
+        let fut = future::loop_fn((client, request, hops), |(mut client, request, hops)| {
+            let (parts, body) = request.into_parts();
+            let method = parts.method.clone();
+            let uri = parts.uri.clone();
+            let headers = parts.headers.clone();
+            let retry_body = body.clone();
+
+            client
+                .send_once(Request::from_parts(parts, body))
+                .and_then(move |res| {
+                    if hops == 0 || !Self::is_redirect(res.status()) {
+                        return Ok(Loop::Break(res));
+                    }
+
+                    let mut request = Request::builder()
+                        .method(method)
+                        .uri(Self::redirect_uri(&uri, &res)?)
+                        .body(retry_body)?;
+                    *request.headers_mut() = headers;
+
+                    Ok(Loop::Continue((client, request, hops - 1)))
+                })
+        });
+
+        Either::B(fut)
+    }
+
+    pub(crate) fn send_once(
+        &mut self,
+        mut request: Request<Bytes>,
+    ) -> impl Future<Item = Response<Bytes>, Error = Error> {
+        self.refresh();
+
+        if let Err(e) = self.poll_ready() {
+            if let Error::ServiceClosed = e {
+                self.record_failure();
+            }
+            return Either::A(future::err(e));
+        }
+
+        let stats = self.body_stats.clone();
+        let threshold = self.body_warning_threshold;
+        let reconnect = self.reconnect.clone();
+        let metrics = self.metrics.clone();
+        let operation = Operation::from_path(request.uri().path());
+
+        let epoch = Epoch(self.epoch.load(Ordering::SeqCst));
+        request.extensions_mut().insert(epoch);
+
+        let fut = self
+            .inner
+            .call(request)
+            .map_err(|e| match e.downcast::<Abandoned>() {
+                Ok(_) => Error::Abandoned,
+                Err(e) => Error::Inner(e),
+            })
+            .then(move |res| {
+                // The request never reached the inner service.
+                if let Err(Error::Abandoned) = res {
+                    return res;
+                }
+
+                if let Some((_, reconnect)) = reconnect {
+                    let mut reconnect = reconnect.lock().unwrap();
+                    match res {
+                        Ok(_) => reconnect.failures = 0,
+                        Err(_) => reconnect.fail(),
+                    }
+                }
+
+                let failed = match &res {
+                    Ok(res) => res.status().is_server_error(),
+                    Err(_) => true,
+                };
+                metrics.record(operation, failed);
+
+                let res = res?;
+                stats.record(res.body().len(), threshold);
+                Ok(res)
+            });
+
+        Either::B(fut)
+    }
+
+    /// Switch to the latest service built by the factory, if any
+    pub(crate) fn refresh(&mut self) {
+        if let Some((generation, reconnect)) = &mut self.reconnect {
+            let reconnect = reconnect.lock().unwrap();
+            if *generation != reconnect.generation {
+                *generation = reconnect.generation;
+                self.inner = reconnect.inner.clone();
+            }
+        }
+    }
+
+    pub(crate) fn record_failure(&mut self) {
+        if let Some((_, reconnect)) = &self.reconnect {
+            reconnect.lock().unwrap().fail();
+        }
+        self.refresh();
+    }
+
+    pub(crate) fn is_redirect(status: StatusCode) -> bool {
+        matches!(
+            status,
+            StatusCode::MOVED_PERMANENTLY
+                | StatusCode::FOUND
+                | StatusCode::TEMPORARY_REDIRECT
+                | StatusCode::PERMANENT_REDIRECT
+        )
+    }
+
+    /// Resolve the `Location` of a redirect against the uri of the request
+    /// that was redirected, refusing to leave the original host.
+    pub(crate) fn redirect_uri(uri: &Uri, response: &Response<Bytes>) -> Result<Uri, Error> {
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .ok_or_else(|| Error::Redirect("missing Location header".into()))?;
+
+        let redirect = location
+            .parse::<Uri>()
+            .map_err(|_| Error::Redirect(location.to_string()))?;
+
+        if redirect.authority_part().is_some() {
+            if redirect.host() != uri.host() {
+                return Err(Error::Redirect(location.to_string()));
+            }
+
+            return Ok(redirect);
+        }
+
+        let path = redirect
+            .path_and_query()
+            .map(|path| path.as_str())
+            .unwrap_or("/");
+
+        Uri::builder()
+            .scheme(uri.scheme_str().unwrap_or("http"))
+            .authority(uri.authority_part().map(|a| a.as_str()).unwrap_or(""))
+            .path_and_query(path)
+            .build()
+            .map_err(Error::from)
+    }
+
+    /// Check that the buffer has capacity for another request, this
+    /// must be called before every call into the buffer.
+    pub(crate) fn poll_ready(&mut self) -> Result<(), Error> {
+        match self.inner.poll_ready() {
+            Ok(Async::Ready(())) => Ok(()),
+            Ok(Async::NotReady) => Err(Error::BufferFull),
+            Err(_) => Err(Error::ServiceClosed),
+        }
+    }
+
+    /// The path and query `url` is sent to, with the parameters set on
+    /// the client added.
+    pub(crate) fn path_and_query(&self, url: &str, method: &Method) -> String {
+        let mut params = Query::default();
+        if let Some(dc) = &self.datacenter {
+            let has_dc = url.contains("?dc=") || url.contains("&dc=");
+            if !has_dc && !url.starts_with("/v1/agent/") {
+                params.param("dc", dc);
+            }
+        }
+        if self.pretty && method == Method::GET {
+            params.flag("pretty");
+        }
+        params.append_to(url)
+    }
+
+    pub(crate) fn build(
+        &self,
+        url: &str,
+        method: Method,
+        body: Bytes,
+    ) -> Result<Request<Bytes>, Error> {
+        let url = self.path_and_query(url, &method);
+
+        let uri = Uri::builder()
+            .scheme(self.scheme.as_str())
+            .authority(self.authority.as_str())
+            .path_and_query(url.as_str())
+            .build()?;
+
+        let mut request = Request::builder();
+        request.uri(uri).method(method);
+
+        let token = match &self.renewed_token {
+            Some(renewed) => renewed.lock().unwrap().clone(),
+            None => self.token.clone(),
+        };
+        if let Some(token) = token {
+            request.header(CONSUL_TOKEN, token.as_str());
+        }
+
+        if let Some(id) = &self.request_id {
+            request.header(&self.request_id_header, id.as_str());
+        }
+
+        let body = match self.compression_threshold {
+            Some(threshold) if body.len() > threshold => {
+                request.header(CONTENT_ENCODING, "gzip");
+                Self::gzip(&body[..])
+            }
+            _ => body,
+        };
+
+        request.body(body).map_err(Error::from)
+    }
+
+    pub(crate) fn gzip(body: &[u8]) -> Bytes {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+
+        // Writing into a `Vec` can not fail.
+        encoder
+            .write_all(body)
+            .and_then(|_| encoder.finish())
+            .map(Bytes::from)
+            .expect("gzip into memory")
+    }
+
+    pub(crate) fn handle_status(response: Response<Bytes>) -> Result<Response<Bytes>, Error> {
+        let status = response.status();
+
+        if status.is_success() | status.is_redirection() | status.is_informational() {
+            Ok(response)
+        } else if status == StatusCode::NOT_FOUND {
+            Err(Error::NotFound)
+        } else if status.is_client_error() {
+            let body = response.into_body();
+            let body = String::from_utf8_lossy(&body[..]).into_owned();
+            Err(Error::ConsulClient(body))
+        } else if status.is_server_error() {
+            Err(server_error(&response.into_body()[..]))
+        } else {
+            unreachable!("This is a bug!")
+        }
+    }
+}
+
+// == impl ConsulFuture ==
+
+impl<T, R> Future for ConsulFuture<T, R>
+where
+    for<'de> R: Deserialize<'de> + Send + 'static,
+    T: HttpService<Bytes, ResponseBody = Bytes>,
+    T::Error: Into<BoxError>,
+{
+    type Item = R;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let response = try_ready!(self.inner.poll());
+
+        let status = response.status();
+
+        let body = if status.is_success() | status.is_redirection() | status.is_informational() {
+            response.into_body()
+        } else if status == StatusCode::NOT_FOUND {
+            return Err(Error::NotFound);
+        } else if status.is_client_error() {
+            let body = response.into_body();
+            let body = String::from_utf8_lossy(&body[..]).into_owned();
+            return Err(Error::ConsulClient(body));
+        } else if status.is_server_error() {
+            return Err(server_error(&response.into_body()[..]));
+        } else {
+            unreachable!("This is a bug!")
+        };
+
+        let body = from_json(&body[..])?;
+
+        Ok(Async::Ready(body))
+    }
+}
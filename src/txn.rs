@@ -0,0 +1,280 @@
+//! Transactions
+
+use crate::error::from_json;
+use crate::{null_to_default, BoxError, Consul, ConsulDuration, Error, KVValue};
+use bytes::Bytes;
+use futures::future;
+use futures::future::Either;
+use futures::Future;
+use http::Method;
+use http::StatusCode;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use tower_http_util::service::HttpService;
+
+impl<T> Consul<T>
+where
+    T: HttpService<Bytes, ResponseBody = Bytes> + Send + 'static,
+    T::Future: Send + 'static,
+    T::Error: Into<BoxError> + Send + Sync,
+{
+    /// Execute a list of operations atomically
+    ///
+    /// A transaction that was rolled back is not an error, the returned
+    /// `TxnResponse` will contain the errors that caused the rollback.
+    pub fn txn(&mut self, ops: Vec<TxnOp>) -> impl Future<Item = TxnResponse, Error = Error> {
+        let body = match serde_json::to_vec(&ops) {
+            Ok(body) => body,
+            Err(e) => return Either::A(future::err(Error::from(e))),
+        };
+
+        let request = match self.build("/v1/txn", Method::PUT, body.into()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        let fut = self.send(request).and_then(|res| {
+            // Consul responds with a `409` when the transaction was
+            // rolled back, the body still contains the errors.
+            let res = if res.status() == StatusCode::CONFLICT {
+                res
+            } else {
+                Self::handle_status(res)?
+            };
+
+            let mut txn: TxnResponse = from_json(&res.body()[..])?;
+            txn.warnings.extend(Self::warnings(&res));
+
+            Ok(txn)
+        });
+
+        Either::B(fut)
+    }
+}
+
+/// A single operation within a transaction
+///
+/// For more information on this go [here][txn]
+/// [txn]: https://www.consul.io/api/txn.html
+#[derive(Debug, Clone, Serialize)]
+pub enum TxnOp {
+    /// An operation against the KV store
+    KV(KVTxnOp),
+    /// An operation against a node in the catalog
+    Node(NodeTxnOp),
+    /// An operation against a service in the catalog
+    Service(ServiceTxnOp),
+    /// An operation against a check in the catalog
+    Check(CheckTxnOp),
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// A KV operation within a transaction, the `value` must be
+/// base64 encoded.
+pub struct KVTxnOp {
+    pub verb: KVVerb,
+    pub key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flags: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[allow(missing_docs)]
+/// The verbs available to KV transaction operations
+pub enum KVVerb {
+    Set,
+    Cas,
+    Lock,
+    Unlock,
+    Get,
+    GetTree,
+    CheckIndex,
+    CheckSession,
+    CheckNotExists,
+    Delete,
+    DeleteTree,
+    DeleteCas,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[allow(missing_docs)]
+/// The verbs available to node, service and check transaction operations
+pub enum CatalogVerb {
+    Set,
+    Cas,
+    Get,
+    Delete,
+    DeleteCas,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// A node operation within a transaction
+pub struct NodeTxnOp {
+    pub verb: CatalogVerb,
+    pub node: TxnNode,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// A service operation within a transaction, `node` is the name of the
+/// node the service belongs to
+pub struct ServiceTxnOp {
+    pub verb: CatalogVerb,
+    pub node: String,
+    pub service: TxnService,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// A check operation within a transaction
+pub struct CheckTxnOp {
+    pub verb: CatalogVerb,
+    pub check: TxnCheck,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// A node in the catalog as used by transactions
+///
+/// `modify_index` is only required by the `cas` and `delete-cas` verbs.
+pub struct TxnNode {
+    #[serde(rename = "ID", default, skip_serializing_if = "String::is_empty")]
+    pub id: String,
+    pub node: String,
+    #[serde(default)]
+    pub address: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub datacenter: String,
+    #[serde(default, deserialize_with = "null_to_default")]
+    pub tagged_addresses: HashMap<String, String>,
+    #[serde(default, deserialize_with = "null_to_default")]
+    pub meta: HashMap<String, String>,
+    #[serde(default)]
+    pub modify_index: i64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// A service in the catalog as used by transactions
+///
+/// `modify_index` is only required by the `cas` and `delete-cas` verbs.
+pub struct TxnService {
+    #[serde(rename = "ID")]
+    pub id: String,
+    #[serde(default)]
+    pub service: String,
+    #[serde(default, deserialize_with = "null_to_default")]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub address: String,
+    #[serde(default)]
+    pub port: u16,
+    #[serde(default, deserialize_with = "null_to_default")]
+    pub meta: HashMap<String, String>,
+    #[serde(default)]
+    pub modify_index: i64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// A check in the catalog as used by transactions
+///
+/// `modify_index` is only required by the `cas` and `delete-cas` verbs.
+pub struct TxnCheck {
+    pub node: String,
+    #[serde(rename = "CheckID")]
+    pub check_id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub status: String,
+    #[serde(default)]
+    pub notes: String,
+    #[serde(rename = "ServiceID", default)]
+    pub service_id: String,
+    #[serde(default)]
+    pub definition: TxnCheckDefinition,
+    #[serde(default)]
+    pub modify_index: i64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// How Consul runs a check registered through a transaction
+pub struct TxnCheckDefinition {
+    #[serde(rename = "HTTP", default, skip_serializing_if = "Option::is_none")]
+    pub http: Option<String>,
+    #[serde(rename = "TCP", default, skip_serializing_if = "Option::is_none")]
+    pub tcp: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<ConsulDuration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<ConsulDuration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deregister_critical_service_after: Option<ConsulDuration>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// The value returned from consul after executing a transaction
+///
+/// `warnings` contains any `Warnings` in the body along with the values
+/// of any `Warning` response headers, current Consul versions do not
+/// return warnings from this endpoint so it is usually empty.
+pub struct TxnResponse {
+    #[serde(default, deserialize_with = "null_to_default")]
+    pub results: Vec<TxnResult>,
+    #[serde(default, deserialize_with = "null_to_default")]
+    pub errors: Vec<TxnError>,
+    #[serde(default, deserialize_with = "null_to_default")]
+    pub warnings: Vec<String>,
+}
+
+impl TxnResponse {
+    /// Returns true if the transaction was committed
+    pub fn is_success(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+/// The result of a single operation within a committed transaction
+pub enum TxnResult {
+    /// The result of a KV operation
+    KV(KVValue),
+    /// The result of a node operation
+    Node(TxnNode),
+    /// The result of a service operation
+    Service(TxnService),
+    /// The result of a check operation
+    Check(TxnCheck),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+#[allow(missing_docs)]
+/// The reason an operation caused a transaction to be rolled back
+pub struct TxnError {
+    pub op_index: usize,
+    pub what: String,
+}
@@ -0,0 +1,130 @@
+//! Blocking queries and watches
+
+use crate::duration::duration_ms;
+use crate::error::from_json;
+use crate::meta::parse_index;
+use crate::{BoxError, Consul, Error, Query};
+use bytes::Bytes;
+use futures::future::{self, Either, Loop};
+use futures::stream::{self, Stream};
+use futures::Future;
+use http::header::WARNING;
+use http::{Method, Response, StatusCode};
+use serde::Deserialize;
+use std::cmp;
+use std::time::Duration;
+use tower_http_util::service::HttpService;
+
+/// The longest a blocking query may wait, Consul silently clamps
+/// anything longer to this.
+pub(crate) const MAX_WAIT: Duration = Duration::from_secs(10 * 60);
+
+impl<T> Consul<T>
+where
+    T: HttpService<Bytes, ResponseBody = Bytes> + Send + 'static,
+    T::Future: Send + 'static,
+    T::Error: Into<BoxError> + Send + Sync,
+{
+    /// Repeatedly issue blocking queries against `url`, yielding the
+    /// response every time the index advances.
+    pub(crate) fn watch_url<R>(
+        &mut self,
+        url: String,
+        wait: Duration,
+    ) -> impl Stream<Item = R, Error = Error>
+    where
+        for<'de> R: Deserialize<'de> + Default + Send + 'static,
+    {
+        let client = self.clone();
+
+        stream::unfold((client, 0), move |(client, last)| {
+            let url = url.clone();
+
+            let fut = future::loop_fn((client, last), move |(mut client, last)| {
+                client
+                    .blocking::<R>(&url, last, wait)
+                    .map(move |(value, index)| {
+                        // Consul recommends treating an index of zero as one
+                        // so the next query blocks rather than returning
+                        // immediately.
+                        let index = cmp::max(index, 1);
+
+                        // Indices are only ever compared, never incremented,
+                        // so an index at `i64::MAX` cannot overflow.
+                        match index.cmp(&last) {
+                            cmp::Ordering::Greater => Loop::Break((value, (client, index))),
+                            cmp::Ordering::Equal => Loop::Continue((client, last)),
+                            // The index went backwards, for example after a
+                            // snapshot restore, so start over.
+                            cmp::Ordering::Less => Loop::Continue((client, 0)),
+                        }
+                    })
+            });
+
+            Some(fut)
+        })
+    }
+
+    /// Issue a blocking query against `url` that returns once the index
+    /// is greater than `index` or `wait` has elapsed, `wait` is capped at
+    /// `MAX_WAIT`.
+    ///
+    /// A `404` is returned as the default value of `R` so that resources
+    /// that do not exist yet can still be watched. An `index` of `0` is a
+    /// regular read.
+    pub(crate) fn blocking<R>(
+        &mut self,
+        url: &str,
+        index: i64,
+        wait: Duration,
+    ) -> impl Future<Item = (R, i64), Error = Error>
+    where
+        for<'de> R: Deserialize<'de> + Default + Send + 'static,
+    {
+        let mut params = Query::default();
+        if index > 0 {
+            params
+                .param("index", &index.to_string())
+                .param("wait", &duration_ms(cmp::min(wait, MAX_WAIT)));
+        }
+        let url = params.append_to(url);
+
+        let request = match self.build(&url, Method::GET, Bytes::new()) {
+            Ok(req) => req,
+            Err(e) => return Either::A(future::err(e)),
+        };
+
+        let fut = self.send(request).and_then(|res| {
+            let index = Self::consul_index(&res).ok_or(Error::MissingIndex)?;
+
+            if res.status() == StatusCode::NOT_FOUND {
+                return Ok((R::default(), index));
+            }
+
+            let res = Self::handle_status(res)?;
+            let value = from_json(&res.body()[..])?;
+
+            Ok((value, index))
+        });
+
+        Either::B(fut)
+    }
+
+    pub(crate) fn warnings(response: &Response<Bytes>) -> Vec<String> {
+        response
+            .headers()
+            .get_all(WARNING)
+            .iter()
+            .filter_map(|warning| warning.to_str().ok())
+            .map(String::from)
+            .collect()
+    }
+
+    pub(crate) fn consul_index(response: &Response<Bytes>) -> Option<i64> {
+        response
+            .headers()
+            .get("X-Consul-Index")
+            .and_then(|index| index.to_str().ok())
+            .and_then(parse_index)
+    }
+}
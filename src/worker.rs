@@ -0,0 +1,86 @@
+//! Observing the buffer worker of a client
+
+use futures::sync::oneshot;
+use futures::{Async, Future, Poll};
+use std::any::Any;
+use std::panic::AssertUnwindSafe;
+use tokio_executor::{DefaultExecutor, Executor, SpawnError, TypedExecutor};
+
+/// The reason the buffer worker of a client stopped, see
+/// `Consul::new_monitored`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WorkerExit {
+    /// The worker returned, because the inner service failed or every
+    /// handle to the buffer was dropped
+    Stopped,
+    /// The worker panicked, with the panic message when it was a string
+    Panicked(Option<String>),
+    /// The worker was dropped before it finished, either it could not be
+    /// spawned or the executor shut down
+    Dropped,
+}
+
+/// A future that resolves with the `WorkerExit` once the buffer worker
+/// of a client has stopped
+#[derive(Debug)]
+pub struct WorkerMonitor {
+    rx: oneshot::Receiver<WorkerExit>,
+}
+
+impl Future for WorkerMonitor {
+    type Item = WorkerExit;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.rx.poll() {
+            Ok(Async::Ready(exit)) => Ok(Async::Ready(exit)),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(oneshot::Canceled) => Ok(Async::Ready(WorkerExit::Dropped)),
+        }
+    }
+}
+
+/// Spawns the buffer worker on the default executor and reports how it
+/// stopped to a `WorkerMonitor`.
+pub(crate) struct MonitoredExecutor {
+    exit: Option<oneshot::Sender<WorkerExit>>,
+}
+
+impl MonitoredExecutor {
+    pub(crate) fn new() -> (Self, WorkerMonitor) {
+        let (tx, rx) = oneshot::channel();
+
+        (MonitoredExecutor { exit: Some(tx) }, WorkerMonitor { rx })
+    }
+}
+
+impl<F> TypedExecutor<F> for MonitoredExecutor
+where
+    F: Future<Item = (), Error = ()> + Send + 'static,
+{
+    fn spawn(&mut self, worker: F) -> Result<(), SpawnError> {
+        let exit = self.exit.take();
+
+        let task = AssertUnwindSafe(worker).catch_unwind().then(move |res| {
+            let reason = match res {
+                Ok(_) => WorkerExit::Stopped,
+                Err(panic) => WorkerExit::Panicked(panic_message(&*panic)),
+            };
+
+            if let Some(exit) = exit {
+                let _ = exit.send(reason);
+            }
+
+            Ok(())
+        });
+
+        Executor::spawn(&mut DefaultExecutor::current(), Box::new(task))
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> Option<String> {
+    panic
+        .downcast_ref::<&str>()
+        .map(|msg| msg.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+}
@@ -6,10 +6,10 @@ use std::panic;
 use std::process::{Command, Stdio};
 use tokio::runtime::Runtime;
 use tower::service_fn;
-use tower_consul::Consul;
+use tower_consul::{Consul, Error};
 use tower_util::ServiceFn;
 
-static CONSUL_ADDRESS: &'static str = "127.0.0.1:8500";
+static CONSUL_ADDRESS: &str = "127.0.0.1:8500";
 
 #[test]
 fn check_consul() {
@@ -53,6 +53,58 @@ fn get_one() {
     consul_del("tower-consul/test-key");
 }
 
+#[test]
+fn get_value() {
+    consul_put("tower-consul/test-value", "test-value");
+
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = client(hyper);
+        client.get_value("tower-consul/test-value")
+    }));
+
+    assert_eq!(response.unwrap(), Bytes::from("test-value"));
+
+    consul_del("tower-consul/test-value");
+}
+
+#[test]
+fn get_raw_typed() {
+    consul_put_flags("tower-consul/test-typed", "test-value", 7);
+
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = client(hyper);
+        client.get_raw_typed("tower-consul/test-typed")
+    }));
+
+    let (value, flags) = response.unwrap();
+    assert_eq!(value, Bytes::from("test-value"));
+    assert_eq!(flags, 7);
+
+    consul_del("tower-consul/test-typed");
+}
+
+#[test]
+fn get_object() {
+    consul_put("tower-consul/test-object", r#"{"a": "1", "b": "2"}"#);
+
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = client(hyper);
+        client.get_object("tower-consul/test-object")
+    }));
+
+    let object = response.unwrap();
+    assert_eq!(object.get("a").map(String::as_str), Some("1"));
+    assert_eq!(object.get("b").map(String::as_str), Some("2"));
+
+    consul_del("tower-consul/test-object");
+}
+
 #[test]
 fn get_keys_empty() {
     let mut rt = Runtime::new().unwrap();
@@ -101,6 +153,32 @@ fn set_key() {
     consul_del("tower-consul/test-set");
 }
 
+#[test]
+fn set_if_flags() {
+    consul_put_flags("tower-consul/test-set-if-flags", "value", 42);
+
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = client(hyper);
+        let mut client2 = client.clone();
+
+        client
+            .set_if_flags("tower-consul/test-set-if-flags", "other", 7)
+            .and_then(move |mismatch| {
+                client2
+                    .set_if_flags("tower-consul/test-set-if-flags", "new-value", 42)
+                    .map(move |matched| (mismatch, matched))
+            })
+    }));
+
+    let (mismatch, matched) = response.unwrap();
+    assert!(!mismatch);
+    assert!(matched);
+
+    consul_del("tower-consul/test-set-if-flags");
+}
+
 #[test]
 fn delete_key() {
     consul_put("tower-consul/test-set", "some-value-to-be-deleted");
@@ -166,7 +244,39 @@ fn register_service() {
     assert!(response.is_ok());
 }
 
-type ResponseFuture = Box<Future<Item = Response<Bytes>, Error = hyper::Error> + Send + 'static>;
+#[test]
+fn raft_configuration() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = client(hyper);
+
+        client.raft_configuration()
+    }));
+
+    let config = response.unwrap();
+
+    assert!(config.servers.iter().any(|server| server.leader));
+}
+
+#[test]
+fn raft_remove_peer_empty() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = client(hyper);
+
+        client.raft_remove_peer("")
+    }));
+
+    match response {
+        Err(Error::InvalidArgument(_)) => {}
+        res => panic!("expected InvalidArgument, got {:?}", res),
+    }
+}
+
+type ResponseFuture =
+    Box<dyn Future<Item = Response<Bytes>, Error = hyper::Error> + Send + 'static>;
 
 fn client<F>(f: F) -> Consul<ServiceFn<F>>
 where
@@ -186,7 +296,7 @@ fn hyper(req: Request<Bytes>) -> ResponseFuture {
     let fut = client
         .request(req.map(Body::from))
         .and_then(|res| {
-            let status = res.status().clone();
+            let status = res.status();
             let headers = res.headers().clone();
 
             res.into_body().concat2().join(Ok((status, headers)))
@@ -215,6 +325,20 @@ fn consul_put(key: &str, value: &str) {
         .unwrap();
 }
 
+fn consul_put_flags(key: &str, value: &str, flags: u64) {
+    Command::new("consul")
+        .arg("kv")
+        .arg("put")
+        .arg(format!("-flags={}", flags))
+        .arg(key)
+        .arg(value)
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap()
+        .wait()
+        .unwrap();
+}
+
 fn consul_del(key: &str) {
     Command::new("consul")
         .arg("kv")
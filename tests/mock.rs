@@ -0,0 +1,3065 @@
+use bytes::Bytes;
+use flate2::read::GzDecoder;
+use futures::future::Loop;
+use futures::{future, Async, Future, Poll, Stream};
+use http::header::{HeaderName, CONTENT_ENCODING, LOCATION, WARNING};
+use http::{Request, Response, StatusCode};
+use std::collections::HashMap;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tower::{service_fn, Service};
+use tower_consul::{
+    AgentServiceCheck, AgentServiceRegistration, Clock, Consistency, Consul, Error, HealthQuery,
+    HealthStatus, KvChange, KvEntry, KvRead, KvReadOptions, LoadBalance, Operation, ServiceQuery,
+    SessionRequest, TaggedAddress, WorkerExit,
+};
+use tower_layer::Layer;
+use tower_util::ServiceFn;
+
+/// A service that is never ready, so every request sent to the
+/// buffer stays queued.
+struct Slow;
+
+impl Service<Request<Bytes>> for Slow {
+    type Response = Response<Bytes>;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+    type Future = future::Empty<Response<Bytes>, Self::Error>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(Async::NotReady)
+    }
+
+    fn call(&mut self, _: Request<Bytes>) -> Self::Future {
+        future::empty()
+    }
+}
+
+#[test]
+fn buffer_full() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = match Consul::new(Slow, 1, "http".into(), "127.0.0.1:8500".into()) {
+            Ok(c) => c,
+            Err(_) => panic!("Unable to spawn!"),
+        };
+
+        // The worker holds at most one request while waiting on `Slow`
+        // and the buffer holds one more, so the next calls must be shed.
+        for _ in 0..3 {
+            let _ = client.get_keys("tower-consul/test-buffer");
+        }
+
+        client.get_keys("tower-consul/test-buffer")
+    }));
+
+    match response {
+        Err(Error::BufferFull) => {}
+        res => panic!("expected BufferFull, got {:?}", res),
+    }
+}
+
+type MockFuture = future::FutureResult<Response<Bytes>, Box<dyn std::error::Error + Send + Sync>>;
+
+fn mock<F>(f: F) -> Consul<ServiceFn<F>>
+where
+    F: Fn(Request<Bytes>) -> MockFuture + Send + 'static,
+{
+    match Consul::new(service_fn(f), 100, "http".into(), "127.0.0.1:8500".into()) {
+        Ok(c) => c,
+        Err(_) => panic!("Unable to spawn!"),
+    }
+}
+
+fn upgrade_to(location: &'static str) -> impl Fn(Request<Bytes>) -> MockFuture {
+    move |req| {
+        let response = if req.uri().scheme_str() == Some("https") {
+            Response::new(Bytes::from(&b"[\"tower-consul/a\"]"[..]))
+        } else {
+            Response::builder()
+                .status(StatusCode::MOVED_PERMANENTLY)
+                .header(LOCATION, location)
+                .body(Bytes::new())
+                .unwrap()
+        };
+
+        future::ok(response)
+    }
+}
+
+#[test]
+fn follow_redirect() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(upgrade_to("https://127.0.0.1:8501/v1/kv/tower-consul?keys"))
+            .with_follow_redirects(1);
+
+        client.get_keys("tower-consul")
+    }));
+
+    assert_eq!(response.unwrap(), vec!["tower-consul/a".to_string()]);
+}
+
+#[test]
+fn redirect_other_host() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(upgrade_to("https://example.com/v1/kv/tower-consul?keys"))
+            .with_follow_redirects(1);
+
+        client.get_keys("tower-consul")
+    }));
+
+    match response {
+        Err(Error::Redirect(_)) => {}
+        res => panic!("expected Redirect, got {:?}", res),
+    }
+}
+
+#[test]
+fn register_many_failures() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|req: Request<Bytes>| {
+            let body = String::from_utf8_lossy(&req.body()[..]).into_owned();
+
+            let status = if body.contains("broken") {
+                StatusCode::INTERNAL_SERVER_ERROR
+            } else {
+                StatusCode::OK
+            };
+
+            let response = Response::builder()
+                .status(status)
+                .body(Bytes::new())
+                .unwrap();
+
+            future::ok(response)
+        });
+
+        let registrations = vec![
+            AgentServiceRegistration {
+                name: "working".into(),
+                ..Default::default()
+            },
+            AgentServiceRegistration {
+                id: Some("broken-1".into()),
+                name: "broken".into(),
+                ..Default::default()
+            },
+        ];
+
+        client.register_many(&registrations)
+    }));
+
+    match response {
+        Err(Error::Registration(failed)) => {
+            let ids = failed.iter().map(|(id, _)| id.as_str()).collect::<Vec<_>>();
+            assert_eq!(ids, vec!["broken-1"]);
+        }
+        res => panic!("expected Registration, got {:?}", res),
+    }
+}
+
+#[test]
+fn watch_service_advancing_index() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(move || {
+        let mut client = mock(move |_| {
+            // Every index is returned twice, the second time simulates a
+            // blocking query that timed out without any change.
+            let call = counter.fetch_add(1, Ordering::SeqCst);
+            let index = call / 2 + 1;
+
+            let response = Response::builder()
+                .header("X-Consul-Index", index.to_string().as_str())
+                .body(Bytes::from(&b"[]"[..]))
+                .unwrap();
+
+            future::ok(response)
+        });
+
+        client
+            .watch_service("tower-consul", Duration::from_secs(1))
+            .take(3)
+            .collect()
+    }));
+
+    assert_eq!(response.unwrap().len(), 3);
+    assert_eq!(calls.load(Ordering::SeqCst), 5);
+}
+
+#[test]
+fn await_value_until_match() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(move || {
+        let mut client = mock(move |_| {
+            // The key is missing, then "closed" and finally "open".
+            let call = counter.fetch_add(1, Ordering::SeqCst);
+            let (status, body) = match call {
+                0 => (StatusCode::NOT_FOUND, ""),
+                1 => (
+                    StatusCode::OK,
+                    r#"[{"CreateIndex":2,"ModifyIndex":2,"LockIndex":0,"Key":"gate","Flags":0,"Value":"Y2xvc2Vk"}]"#,
+                ),
+                _ => (
+                    StatusCode::OK,
+                    r#"[{"CreateIndex":2,"ModifyIndex":3,"LockIndex":0,"Key":"gate","Flags":0,"Value":"b3Blbg=="}]"#,
+                ),
+            };
+
+            let response = Response::builder()
+                .status(status)
+                .header("X-Consul-Index", (call + 1).to_string().as_str())
+                .body(Bytes::from(body))
+                .unwrap();
+
+            future::ok(response)
+        });
+
+        client.await_value("gate", b"open", Duration::from_secs(60))
+    }));
+
+    response.unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn resolve_url_with_defaults() {
+    let mut rt = Runtime::new().unwrap();
+
+    let urls = rt
+        .block_on(future::lazy(|| {
+            let client = mock(|_| -> MockFuture { panic!("no request is sent") })
+                .with_datacenter("dc2")
+                .with_token("secret");
+
+            Ok::<_, ()>((
+                client.resolve_url("/v1/kv/config?token=secret&recurse"),
+                client.resolve_url("/v1/agent/self"),
+            ))
+        }))
+        .unwrap();
+
+    assert_eq!(urls.0, "http://127.0.0.1:8500/v1/kv/config?recurse&dc=dc2");
+    assert_eq!(urls.1, "http://127.0.0.1:8500/v1/agent/self");
+}
+
+#[test]
+fn await_value_timeout_wait() {
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(move || {
+        let mut client = mock(move |req: Request<Bytes>| {
+            let query = req.uri().query().unwrap_or("").to_string();
+            tx.lock().unwrap().send(query).unwrap();
+
+            let response = Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .header("X-Consul-Index", "7")
+                .body(Bytes::new())
+                .unwrap();
+
+            future::ok(response)
+        });
+
+        client.await_value("gate", b"open", Duration::from_micros(1500))
+    }));
+
+    match response {
+        Err(Error::Timeout) => {}
+        res => panic!("expected Timeout, got {:?}", res),
+    }
+
+    let queries = rx.try_iter().collect::<Vec<_>>();
+    assert!(!queries.is_empty());
+    assert!(queries.iter().all(|query| !query.contains("wait=0ms")));
+}
+
+#[test]
+fn get_decoded_error() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|_| {
+            let body = r#"[{"CreateIndex":1,"ModifyIndex":1,"LockIndex":0,"Key":"tower-consul/a","Flags":0,"Value":"aGVsbG8=","Session":null}]"#;
+            future::ok(Response::new(Bytes::from(body)))
+        });
+
+        client.get_decoded("tower-consul/a", |_| -> Result<(), _> {
+            Err("unable to decrypt".into())
+        })
+    }));
+
+    match response {
+        Err(Error::Decode(e)) => assert_eq!(e.to_string(), "unable to decrypt"),
+        res => panic!("expected Decode, got {:?}", res),
+    }
+}
+
+fn echo_gzip(req: Request<Bytes>) -> MockFuture {
+    let compressed = req.headers().get(CONTENT_ENCODING).is_some();
+
+    let body = if compressed {
+        let mut body = Vec::new();
+        GzDecoder::new(&req.body()[..])
+            .read_to_end(&mut body)
+            .unwrap();
+        body
+    } else {
+        req.body().to_vec()
+    };
+
+    let registration: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+    let status = match (compressed, registration["Name"].as_str()) {
+        (true, Some("compressed")) | (false, Some("plain")) => StatusCode::OK,
+        _ => StatusCode::BAD_REQUEST,
+    };
+
+    let response = Response::builder()
+        .status(status)
+        .body(Bytes::new())
+        .unwrap();
+
+    future::ok(response)
+}
+
+#[test]
+fn request_compression() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(echo_gzip).with_request_compression(64);
+        let mut client2 = client.clone();
+
+        let large = AgentServiceRegistration {
+            name: "compressed".into(),
+            tags: (0..32).map(|i| format!("tag-{}", i)).collect(),
+            ..Default::default()
+        };
+
+        let small = AgentServiceRegistration {
+            name: "plain".into(),
+            ..Default::default()
+        };
+
+        client
+            .register_service(&large)
+            .and_then(move |_| client2.register_service(&small))
+    }));
+
+    response.unwrap();
+}
+
+#[test]
+fn json_error_excerpt() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|_| future::ok(Response::new(Bytes::from(r#"["a", "b", oops]"#))));
+
+        client.get_keys("tower-consul")
+    }));
+
+    match response {
+        Err(Error::JsonBody { excerpt, .. }) => assert!(excerpt.contains("oops")),
+        res => panic!("expected JsonBody, got {:?}", res),
+    }
+}
+
+#[test]
+fn watch_many_tags_keys() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|req: Request<Bytes>| {
+            let query = req.uri().query().unwrap_or("");
+
+            // Each key changes exactly once, afterwards every blocking
+            // query times out with the same index.
+            let index = if query.contains("index=") { "10" } else { "5" };
+
+            let response = Response::builder()
+                .header("X-Consul-Index", index)
+                .status(StatusCode::NOT_FOUND)
+                .body(Bytes::new())
+                .unwrap();
+
+            future::ok(response)
+        });
+
+        client
+            .watch_many(
+                &["tower-consul/a", "tower-consul/b"],
+                Duration::from_secs(1),
+            )
+            .take(4)
+            .collect()
+    }));
+
+    let mut keys = response
+        .unwrap()
+        .into_iter()
+        .map(|(key, values)| {
+            assert!(values.is_empty());
+            key
+        })
+        .collect::<Vec<_>>();
+    keys.sort();
+
+    assert_eq!(
+        keys,
+        vec![
+            "tower-consul/a",
+            "tower-consul/a",
+            "tower-consul/b",
+            "tower-consul/b"
+        ]
+    );
+}
+
+#[test]
+fn list_keys_separator() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|req: Request<Bytes>| {
+            let body = match req.uri().query() {
+                Some("keys&separator=%2F") => r#"["tower-consul/a/"]"#,
+                Some("keys") => r#"["tower-consul/a/b", "tower-consul/a/c"]"#,
+                _ => "null",
+            };
+
+            future::ok(Response::new(Bytes::from(body)))
+        });
+        let mut client2 = client.clone();
+
+        client
+            .list_keys("tower-consul/", Some("/"))
+            .join(client2.list_keys("tower-consul/", None))
+    }));
+
+    let (children, all) = response.unwrap();
+    assert_eq!(children, vec!["tower-consul/a/"]);
+    assert_eq!(all, vec!["tower-consul/a/b", "tower-consul/a/c"]);
+}
+
+#[test]
+fn bare_json_and_raw_bodies() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|req: Request<Bytes>| {
+            let body = match req.uri().path() {
+                "/v1/status/leader" => r#""10.0.0.1:8300""#,
+                _ => "not { json",
+            };
+
+            future::ok(Response::new(Bytes::from(body)))
+        });
+        let mut client2 = client.clone();
+
+        client
+            .status_leader()
+            .join(client2.get_raw("tower-consul/raw"))
+    }));
+
+    let (leader, raw) = response.unwrap();
+    assert_eq!(leader, "10.0.0.1:8300");
+    assert_eq!(raw, Bytes::from("not { json"));
+}
+
+#[test]
+fn blocking_wait_capped() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|req: Request<Bytes>| {
+            let status = match req.uri().query() {
+                Some("index=7&wait=600000ms") => StatusCode::OK,
+                _ => StatusCode::BAD_REQUEST,
+            };
+
+            let response = Response::builder()
+                .status(status)
+                .header("X-Consul-Index", "8")
+                .body(Bytes::from("[]"))
+                .unwrap();
+
+            future::ok(response)
+        });
+
+        client.get_blocking("tower-consul/a", 7, Duration::from_secs(60 * 60))
+    }));
+
+    let (values, index) = response.unwrap();
+    assert!(values.is_empty());
+    assert_eq!(index, 8);
+}
+
+#[test]
+fn register_with_ttl_heartbeat() {
+    let (tx, rx) = mpsc::channel();
+
+    let mut rt = Runtime::new().unwrap();
+
+    let heartbeat = rt
+        .block_on(future::lazy(move || {
+            let mut client = mock(move |req: Request<Bytes>| {
+                let body = String::from_utf8_lossy(&req.body()[..]).into_owned();
+                tx.send((req.uri().path().to_string(), body)).unwrap();
+
+                future::ok(Response::new(Bytes::new()))
+            });
+
+            let registration = AgentServiceRegistration {
+                id: Some("ttl-1".into()),
+                name: "ttl".into(),
+                checks: vec![AgentServiceCheck {
+                    check_id: Some("old-check".into()),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            };
+
+            client.register_with_ttl(registration, Duration::from_secs(10))
+        }))
+        .unwrap();
+
+    let timeout = Duration::from_secs(5);
+
+    let (path, body) = rx.recv_timeout(timeout).unwrap();
+    assert_eq!(path, "/v1/agent/service/register");
+    assert!(body.contains(r#""TTL":"10s""#));
+    assert!(!body.contains("old-check"));
+
+    let (path, _) = rx.recv_timeout(timeout).unwrap();
+    assert_eq!(path, "/v1/agent/check/pass/service:ttl-1");
+
+    drop(heartbeat);
+
+    let deregistered = (0..3)
+        .filter_map(|_| rx.recv_timeout(timeout).ok())
+        .any(|(path, _)| path == "/v1/agent/service/deregister/ttl-1");
+    assert!(deregistered);
+}
+
+#[test]
+fn register_with_ttl_exact() {
+    let (tx, rx) = mpsc::channel();
+
+    let mut rt = Runtime::new().unwrap();
+
+    let (heartbeat, short) = rt
+        .block_on(future::lazy(move || {
+            let mut client = mock(move |req: Request<Bytes>| {
+                let body = String::from_utf8_lossy(&req.body()[..]).into_owned();
+                tx.send((req.uri().path().to_string(), body)).unwrap();
+
+                future::ok(Response::new(Bytes::new()))
+            });
+
+            let registration = AgentServiceRegistration {
+                id: Some("ttl-1".into()),
+                name: "ttl".into(),
+                ..Default::default()
+            };
+
+            let short = client
+                .register_with_ttl(registration.clone(), Duration::from_millis(500))
+                .then(Ok::<_, ()>);
+            let exact = client.register_with_ttl(registration, Duration::from_millis(1900));
+            short.join(exact.map_err(|e| panic!("{:?}", e)))
+        }))
+        .map(|(short, heartbeat)| (heartbeat, short))
+        .unwrap();
+
+    match short {
+        Err(Error::InvalidArgument(_)) => {}
+        res => panic!("expected InvalidArgument, got {:?}", res.map(|_| ())),
+    }
+
+    // Only the accepted registration reaches the agent.
+    let (path, body) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(path, "/v1/agent/service/register");
+    assert!(body.contains(r#""TTL":"1.9s""#));
+
+    drop(heartbeat);
+}
+
+type MockFn = Box<dyn Fn(Request<Bytes>) -> MockFuture + Send>;
+
+/// A client that responds with an empty list to every request and sends
+/// the query of the request to the returned receiver.
+fn capture_query() -> (Consul<ServiceFn<MockFn>>, mpsc::Receiver<String>) {
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+
+    let client = mock(Box::new(move |req: Request<Bytes>| {
+        let query = req.uri().query().unwrap_or("").to_string();
+        tx.lock().unwrap().send(query).unwrap();
+
+        future::ok(Response::new(Bytes::from("[]")))
+    }) as MockFn);
+
+    (client, rx)
+}
+
+#[test]
+fn service_nodes_query_encoding() {
+    let queries = vec![
+        (ServiceQuery::default(), ""),
+        (
+            ServiceQuery {
+                dc: Some("dc2".into()),
+                ..Default::default()
+            },
+            "dc=dc2",
+        ),
+        (
+            ServiceQuery {
+                tags: vec!["v1".into(), "primary".into()],
+                ..Default::default()
+            },
+            "tag=v1&tag=primary",
+        ),
+        (
+            ServiceQuery {
+                node_meta: vec![("rack".into(), "r1".into())],
+                ..Default::default()
+            },
+            "node-meta=rack%3Ar1",
+        ),
+        (
+            ServiceQuery {
+                near: Some("_agent".into()),
+                ..Default::default()
+            },
+            "near=_agent",
+        ),
+        (
+            ServiceQuery {
+                filter: Some("ServiceMeta.env == prod".into()),
+                ..Default::default()
+            },
+            "filter=ServiceMeta.env%20%3D%3D%20prod",
+        ),
+        (
+            ServiceQuery {
+                dc: Some("dc2".into()),
+                tags: vec!["v1".into()],
+                node_meta: vec![],
+                near: Some("_agent".into()),
+                filter: Some("Node == a".into()),
+            },
+            "dc=dc2&tag=v1&near=_agent&filter=Node%20%3D%3D%20a",
+        ),
+    ];
+
+    let mut rt = Runtime::new().unwrap();
+
+    for (query, expected) in queries {
+        let sent = rt
+            .block_on(future::lazy(move || {
+                let (mut client, rx) = capture_query();
+                client
+                    .service_nodes_query("tower-consul", query)
+                    .map(move |_| rx.recv().unwrap())
+            }))
+            .unwrap();
+
+        assert_eq!(sent, expected);
+    }
+}
+
+#[test]
+fn raft_remove_peer_encoding() {
+    let mut rt = Runtime::new().unwrap();
+
+    let sent = rt
+        .block_on(future::lazy(|| {
+            let (mut client, rx) = capture_query();
+            client
+                .raft_remove_peer_by_id("a&b=c")
+                .map(move |_| rx.recv().unwrap())
+        }))
+        .unwrap();
+
+    assert_eq!(sent, "id=a%26b%3Dc");
+}
+
+#[test]
+fn health_passing_and_filter() {
+    let mut rt = Runtime::new().unwrap();
+
+    let (health, discover) = rt
+        .block_on(future::lazy(|| {
+            let (mut client, rx) = capture_query();
+            let query = HealthQuery {
+                tags: vec!["v1".into()],
+                passing: true,
+                filter: Some("Service.Meta.version == 2".into()),
+                ..Default::default()
+            };
+            let filter = HealthQuery {
+                filter: Some("Service.Meta.version == 2".into()),
+                ..Default::default()
+            };
+
+            client
+                .health_service("web", query)
+                .and_then(move |_| client.discover_query("web", filter))
+                .map(move |_| (rx.recv().unwrap(), rx.recv().unwrap()))
+        }))
+        .unwrap();
+
+    let expected = "tag=v1&passing&filter=Service.Meta.version%20%3D%3D%202";
+    assert_eq!(health, expected);
+    assert_eq!(discover, "passing&filter=Service.Meta.version%20%3D%3D%202");
+}
+
+#[test]
+fn health_service_tag_url() {
+    let mut rt = Runtime::new().unwrap();
+    let (tx, rx) = mpsc::channel();
+
+    rt.block_on(future::lazy(move || {
+        let mut client = mock(move |req: Request<Bytes>| {
+            tx.send(req.uri().path_and_query().unwrap().to_string())
+                .unwrap();
+            future::ok(Response::new(Bytes::from("[]")))
+        });
+
+        let single = HealthQuery {
+            tags: vec!["v1".into()],
+            ..Default::default()
+        };
+        let multiple = HealthQuery {
+            tags: vec!["v1".into(), "primary".into()],
+            ..Default::default()
+        };
+
+        client
+            .health_service("web", single)
+            .and_then(move |_| client.health_service("web", multiple))
+    }))
+    .unwrap();
+
+    assert_eq!(rx.recv().unwrap(), "/v1/health/service/web?tag=v1");
+    assert_eq!(
+        rx.recv().unwrap(),
+        "/v1/health/service/web?tag=v1&tag=primary"
+    );
+}
+
+#[test]
+fn get_with_opts_encoding() {
+    let opts = vec![
+        (KvReadOptions::default(), ""),
+        (
+            KvReadOptions {
+                recurse: true,
+                ..Default::default()
+            },
+            "recurse",
+        ),
+        (
+            KvReadOptions {
+                keys: true,
+                separator: Some("/".into()),
+                ..Default::default()
+            },
+            "keys&separator=%2F",
+        ),
+        (
+            KvReadOptions {
+                raw: true,
+                ..Default::default()
+            },
+            "raw",
+        ),
+        (
+            KvReadOptions {
+                dc: Some("dc2".into()),
+                ..Default::default()
+            },
+            "dc=dc2",
+        ),
+        (
+            KvReadOptions {
+                consistency: Consistency::Consistent,
+                ..Default::default()
+            },
+            "consistent",
+        ),
+        (
+            KvReadOptions {
+                consistency: Consistency::Stale,
+                ..Default::default()
+            },
+            "stale",
+        ),
+        (
+            KvReadOptions {
+                index: Some(12),
+                wait: Some(Duration::from_secs(5)),
+                ..Default::default()
+            },
+            "index=12&wait=5000ms",
+        ),
+    ];
+
+    let mut rt = Runtime::new().unwrap();
+
+    for (opts, expected) in opts {
+        let sent = rt
+            .block_on(future::lazy(move || {
+                let (mut client, rx) = capture_query();
+                client
+                    .get_with_opts("tower-consul", opts)
+                    .map(move |_| rx.recv().unwrap())
+            }))
+            .unwrap();
+
+        assert_eq!(sent, expected);
+    }
+}
+
+#[test]
+fn get_with_opts_result() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let (mut client, rx) = capture_query();
+        let mut client2 = client.clone();
+
+        let keys = KvReadOptions {
+            keys: true,
+            ..Default::default()
+        };
+        let raw = KvReadOptions {
+            raw: true,
+            ..Default::default()
+        };
+
+        client
+            .get_with_opts("tower-consul", keys)
+            .join(client2.get_with_opts("tower-consul", raw))
+            .map(move |res| {
+                drop(rx);
+                res
+            })
+    }));
+
+    match response.unwrap() {
+        (KvRead::Keys(keys), KvRead::Raw(raw)) => {
+            assert!(keys.is_empty());
+            assert_eq!(raw, Bytes::from("[]"));
+        }
+        res => panic!("unexpected results {:?}", res),
+    }
+}
+
+#[test]
+fn token_self() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|req: Request<Bytes>| {
+            let response = match req.headers().get("X-Consul-Token") {
+                Some(token) if token == "secret" => Response::new(Bytes::from(
+                    r#"{
+                        "AccessorID": "6a1253d2-1785-24fd-91c2-f8e78c745511",
+                        "SecretID": "secret",
+                        "Description": "Agent token",
+                        "Policies": [{"ID": "165d4317", "Name": "node-read"}],
+                        "Local": false,
+                        "CreateIndex": 59,
+                        "ModifyIndex": 59
+                    }"#,
+                )),
+                _ => Response::builder()
+                    .status(StatusCode::FORBIDDEN)
+                    .body(Bytes::from("ACL not found"))
+                    .unwrap(),
+            };
+
+            future::ok(response)
+        })
+        .with_token("secret");
+
+        client.token_self()
+    }));
+
+    let token = response.unwrap();
+    assert_eq!(token.secret_id, "secret");
+    assert_eq!(token.policies[0].name, "node-read");
+}
+
+#[test]
+fn register_warnings() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|_| {
+            let response = Response::builder()
+                .header(WARNING, "299 - \"Deprecated field Port\"")
+                .body(Bytes::new())
+                .unwrap();
+
+            future::ok(response)
+        });
+
+        client.register_with_warnings(&b"{}"[..])
+    }));
+
+    assert_eq!(response.unwrap(), vec!["299 - \"Deprecated field Port\""]);
+}
+
+const HEALTH_SERVICE: &str = r#"[
+    {
+        "Node": {"ID": "a", "Node": "node-a", "Address": "10.0.0.1", "Datacenter": "dc1"},
+        "Service": {"ID": "web-1", "Service": "web", "Tags": null, "Address": "", "Port": 8080},
+        "Checks": []
+    },
+    {
+        "Node": {"ID": "b", "Node": "node-b", "Address": "10.0.0.2", "Datacenter": "dc1"},
+        "Service": {"ID": "web-2", "Service": "web", "Tags": [], "Address": "172.16.0.2", "Port": 8081},
+        "Checks": []
+    },
+    {
+        "Node": {"ID": "c", "Node": "node-c", "Address": "10.0.0.3", "Datacenter": "dc1"},
+        "Service": {"ID": "web-3", "Service": "web", "Tags": [], "Address": "web.internal", "Port": 8082},
+        "Checks": []
+    }
+]"#;
+
+#[test]
+fn discover_addresses() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|req: Request<Bytes>| {
+            let response = if req.uri().query() == Some("passing") {
+                Response::new(Bytes::from(HEALTH_SERVICE))
+            } else {
+                Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Bytes::new())
+                    .unwrap()
+            };
+
+            future::ok(response)
+        });
+
+        let skipped = client.clone();
+        client
+            .discover("web")
+            .map(move |addrs| (addrs, skipped.skipped_hostnames()))
+    }));
+
+    let (addrs, skipped) = response.unwrap();
+    assert_eq!(
+        addrs,
+        vec![
+            "10.0.0.1:8080".parse::<SocketAddr>().unwrap(),
+            "172.16.0.2:8081".parse::<SocketAddr>().unwrap(),
+        ]
+    );
+    assert_eq!(skipped, 1);
+}
+
+#[test]
+fn discover_hosts() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client =
+            mock(|_: Request<Bytes>| future::ok(Response::new(Bytes::from(HEALTH_SERVICE))));
+        client.discover_hosts("web")
+    }));
+
+    assert_eq!(
+        response.unwrap(),
+        vec![
+            ("10.0.0.1".to_string(), 8080),
+            ("172.16.0.2".to_string(), 8081),
+            ("web.internal".to_string(), 8082),
+        ]
+    );
+}
+
+#[test]
+fn discover_tagged_address() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let body = r#"[{
+            "Node": {"Node": "node-a", "Address": "10.0.0.1",
+                     "TaggedAddresses": {"wan": "198.51.100.1"}},
+            "Service": {"ID": "web-1", "Service": "web", "Address": "web.internal",
+                        "Port": 8080,
+                        "TaggedAddresses": {"wan": {"Address": "203.0.113.5", "Port": 9090}}},
+            "Checks": []
+        }]"#;
+        let mut client =
+            mock(move |_: Request<Bytes>| future::ok(Response::new(Bytes::from(body))))
+                .with_tagged_address(TaggedAddress::Wan);
+        client.discover("web")
+    }));
+
+    let addrs = response.unwrap();
+    assert_eq!(
+        addrs,
+        vec!["203.0.113.5:9090".parse::<SocketAddr>().unwrap()]
+    );
+}
+
+#[test]
+fn discover_one_round_robin() {
+    let mut rt = Runtime::new().unwrap();
+
+    let picked = rt.block_on(future::lazy(|| {
+        let client =
+            mock(|_: Request<Bytes>| future::ok(Response::new(Bytes::from(HEALTH_SERVICE))))
+                .with_load_balance(LoadBalance::RoundRobin);
+
+        let picks = (0..3)
+            .map(|_| client.clone().discover_one("web"))
+            .collect::<Vec<_>>();
+        future::join_all(picks)
+    }));
+
+    let a = "10.0.0.1:8080".parse::<SocketAddr>().unwrap();
+    let b = "172.16.0.2:8081".parse::<SocketAddr>().unwrap();
+    assert_eq!(picked.unwrap(), vec![a, b, a]);
+}
+
+#[test]
+fn discover_one_no_instances() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|_: Request<Bytes>| future::ok(Response::new(Bytes::from("[]"))));
+        client.discover_one("web")
+    }));
+
+    match response {
+        Err(Error::NotFound) => {}
+        res => panic!("expected NotFound, got {:?}", res),
+    }
+}
+
+#[test]
+fn destroy_expired_sessions() {
+    let mut rt = Runtime::new().unwrap();
+    let destroyed = Arc::new(Mutex::new(Vec::new()));
+    let seen = destroyed.clone();
+
+    let response = rt.block_on(future::lazy(move || {
+        let mut client = mock(move |req: Request<Bytes>| {
+            let path = req.uri().path();
+            let body = if path == "/v1/session/list" {
+                r#"[
+                    {"ID": "old", "Node": "a", "CreateIndex": 1, "ModifyIndex": 1,
+                     "CreateTime": "2001-01-01T00:00:00Z"},
+                    {"ID": "locked", "Node": "a", "CreateIndex": 2, "ModifyIndex": 2,
+                     "CreateTime": "2001-01-01T00:00:00.5+02:00"},
+                    {"ID": "unknown", "Node": "a", "CreateIndex": 3, "ModifyIndex": 3},
+                    {"ID": "new", "Node": "a", "CreateIndex": 4, "ModifyIndex": 4,
+                     "CreateTime": "2999-01-01T00:00:00Z"}
+                ]"#
+            } else if path == "/v1/kv/locks/" && req.uri().query() == Some("recurse") {
+                r#"[{"CreateIndex": 5, "ModifyIndex": 5, "LockIndex": 1, "Key": "locks/a",
+                     "Flags": 0, "Value": null, "Session": "locked"}]"#
+            } else if let Some(id) = path.strip_prefix("/v1/session/destroy/") {
+                destroyed.lock().unwrap().push(id.to_string());
+                "true"
+            } else {
+                panic!("unexpected request {}", req.uri());
+            };
+
+            future::ok(Response::new(Bytes::from(body)))
+        });
+
+        client.destroy_expired_sessions(Duration::from_secs(60), "locks/")
+    }));
+
+    assert_eq!(response.unwrap(), 1);
+    assert_eq!(*seen.lock().unwrap(), vec!["old".to_string()]);
+}
+
+#[test]
+fn destroy_expired_sessions_kv_prefix() {
+    let mut rt = Runtime::new().unwrap();
+    let destroyed = Arc::new(Mutex::new(Vec::new()));
+    let seen = destroyed.clone();
+
+    let response = rt.block_on(future::lazy(move || {
+        let client = mock(move |req: Request<Bytes>| {
+            let path = req.uri().path();
+            let body = if path == "/v1/session/list" {
+                r#"[
+                    {"ID": "old", "Node": "a", "CreateIndex": 1, "ModifyIndex": 1,
+                     "CreateTime": "2001-01-01T00:00:00Z"},
+                    {"ID": "locked", "Node": "a", "CreateIndex": 2, "ModifyIndex": 2,
+                     "CreateTime": "2001-01-01T00:00:00Z"}
+                ]"#
+            } else if path == "/v1/kv/apps/web/locks/" && req.uri().query() == Some("recurse") {
+                r#"[{"CreateIndex": 5, "ModifyIndex": 5, "LockIndex": 1,
+                     "Key": "apps/web/locks/a", "Flags": 0, "Value": null,
+                     "Session": "locked"}]"#
+            } else if let Some(id) = path.strip_prefix("/v1/session/destroy/") {
+                destroyed.lock().unwrap().push(id.to_string());
+                "true"
+            } else {
+                panic!("unexpected request {}", req.uri());
+            };
+
+            future::ok(Response::new(Bytes::from(body)))
+        });
+        let mut client = client.with_kv_prefix("apps/web/");
+
+        client.destroy_expired_sessions(Duration::from_secs(60), "locks/")
+    }));
+
+    assert_eq!(response.unwrap(), 1);
+    assert_eq!(*seen.lock().unwrap(), vec!["old".to_string()]);
+}
+
+#[test]
+fn destroy_expired_sessions_created_locally() {
+    let mut rt = Runtime::new().unwrap();
+    let destroyed = Arc::new(Mutex::new(Vec::new()));
+    let seen = destroyed.clone();
+
+    let response = rt.block_on(future::lazy(move || {
+        let client = mock(move |req: Request<Bytes>| {
+            let path = req.uri().path();
+            // Consul does not return a CreateTime.
+            let body = if path == "/v1/session/create" {
+                r#"{"ID": "ours"}"#
+            } else if path == "/v1/session/list" {
+                r#"[
+                    {"ID": "ours", "Node": "a", "CreateIndex": 1, "ModifyIndex": 1},
+                    {"ID": "theirs", "Node": "a", "CreateIndex": 2, "ModifyIndex": 2}
+                ]"#
+            } else if path == "/v1/kv/locks/" {
+                "[]"
+            } else if let Some(id) = path.strip_prefix("/v1/session/destroy/") {
+                destroyed.lock().unwrap().push(id.to_string());
+                "true"
+            } else {
+                panic!("unexpected request {}", req.uri());
+            };
+
+            future::ok(Response::new(Bytes::from(body)))
+        });
+
+        let created = std::time::UNIX_EPOCH + Duration::from_secs(1_554_913_800);
+        let mut creator = client.clone().with_clock(FixedClock(created));
+        let mut client = client.with_clock(FixedClock(created + Duration::from_secs(120)));
+
+        creator
+            .create_session(&SessionRequest::default())
+            .and_then(move |_| client.destroy_expired_sessions(Duration::from_secs(60), "locks/"))
+    }));
+
+    assert_eq!(response.unwrap(), 1);
+    assert_eq!(*seen.lock().unwrap(), vec!["ours".to_string()]);
+}
+
+#[test]
+fn body_size_warning() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|req: Request<Bytes>| {
+            let body = if req.uri().path() == "/v1/kv/big" {
+                "[\"tower-consul/a\", \"tower-consul/b\"]"
+            } else {
+                "[]"
+            };
+
+            future::ok(Response::new(Bytes::from(body)))
+        })
+        .with_body_size_warning(10);
+
+        let mut clone = client.clone();
+        client
+            .get_keys("small")
+            .join(clone.get_keys("big"))
+            .join(clone.get_keys("big"))
+            .map(move |_| client)
+    }));
+
+    let client = response.unwrap();
+    assert_eq!(client.largest_body_size(), 36);
+    assert_eq!(client.oversized_bodies(), 2);
+}
+
+#[test]
+fn rebuild_after_failures() {
+    let mut rt = Runtime::new().unwrap();
+    let built = Arc::new(AtomicUsize::new(0));
+    let count = built.clone();
+
+    let response = rt.block_on(future::lazy(move || {
+        let factory = move || {
+            let generation = count.fetch_add(1, Ordering::SeqCst);
+            service_fn(move |_: Request<Bytes>| -> MockFuture {
+                if generation == 0 {
+                    future::err("connection pool gave up".into())
+                } else {
+                    future::ok(Response::new(Bytes::from("[]")))
+                }
+            })
+        };
+
+        let mut client =
+            Consul::from_factory(factory, 100, "http".into(), "127.0.0.1:8500".into(), 2).unwrap();
+        let mut retry = client.clone();
+
+        client
+            .get_keys("tower-consul/a")
+            .then(move |first| {
+                client
+                    .get_keys("tower-consul/a")
+                    .then(|second| Ok((first, second)))
+            })
+            .and_then(move |(first, second)| {
+                retry
+                    .get_keys("tower-consul/a")
+                    .map(move |third| (first, second, third))
+            })
+    }));
+
+    let (first, second, third) = response.unwrap();
+    assert!(matches!(first, Err(Error::Inner(_))));
+    assert!(matches!(second, Err(Error::Inner(_))));
+    assert!(third.is_empty());
+    assert_eq!(built.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn all_dc_leaders() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|req: Request<Bytes>| {
+            let response = match req.uri().query() {
+                _ if req.uri().path() == "/v1/catalog/datacenters" => {
+                    Response::new(Bytes::from(r#"["dc1", "dc2", "dc3"]"#))
+                }
+                Some("dc=dc1") => Response::new(Bytes::from(r#""10.0.0.1:8300""#)),
+                Some("dc=dc2") => Response::new(Bytes::from(r#""""#)),
+                _ => Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Bytes::from("No path to datacenter"))
+                    .unwrap(),
+            };
+
+            future::ok(response)
+        });
+
+        client.all_dc_leaders()
+    }));
+
+    let leaders = response.unwrap();
+    assert_eq!(leaders.len(), 3);
+    assert_eq!(leaders["dc1"], "10.0.0.1:8300");
+    assert_eq!(leaders["dc2"], "");
+    assert_eq!(leaders["dc3"], "");
+}
+
+#[test]
+fn service_node_addresses() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|_: Request<Bytes>| {
+            let body = r#"[
+                {"Node": "a", "Address": "10.0.0.1", "ServiceAddress": "", "ServicePort": 8080,
+                 "ServiceTags": ["v1"], "ServiceMeta": {}},
+                {"Node": "b", "Address": "10.0.0.2", "ServiceAddress": "172.16.0.2",
+                 "ServicePort": 8081}
+            ]"#;
+
+            future::ok(Response::new(Bytes::from(body)))
+        });
+
+        client.service_node_addresses("web")
+    }));
+
+    assert_eq!(
+        response.unwrap(),
+        vec![
+            ("10.0.0.1".to_string(), 8080),
+            ("172.16.0.2".to_string(), 8081)
+        ]
+    );
+}
+
+#[test]
+fn service_nodes_required() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|_: Request<Bytes>| future::ok(Response::new(Bytes::from("[]"))));
+
+        client
+            .service_nodes("unknown")
+            .join(client.service_nodes_required("unknown").then(Ok))
+    }));
+
+    let (nodes, required) = response.unwrap();
+    assert!(nodes.is_empty());
+    match required {
+        Err(Error::NotFound) => {}
+        res => panic!("expected NotFound, got {:?}", res),
+    }
+}
+
+#[test]
+fn service_nodes_sorted() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|_: Request<Bytes>| {
+            let instance = |node: &str, address: &str| {
+                format!(
+                    r#"{{"ServiceKind": "", "ID": "", "ServiceID": "web", "ServiceName": "web",
+                         "ServiceTags": [], "ServiceMeta": {{}}, "Node": "{}",
+                         "Address": "{}", "Datacenter": "dc1"}}"#,
+                    node, address
+                )
+            };
+            let body = format!(
+                "[{}, {}, {}]",
+                instance("node-b", "10.0.0.2"),
+                instance("node-a", "10.0.0.9"),
+                instance("node-a", "10.0.0.1")
+            );
+
+            future::ok(Response::new(Bytes::from(body)))
+        });
+
+        client.service_nodes_sorted("web")
+    }));
+
+    let nodes = response
+        .unwrap()
+        .into_iter()
+        .map(|node| (node.node, node.address))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        nodes,
+        vec![
+            ("node-a".to_string(), "10.0.0.1".to_string()),
+            ("node-a".to_string(), "10.0.0.9".to_string()),
+            ("node-b".to_string(), "10.0.0.2".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn request_id_header() {
+    let mut rt = Runtime::new().unwrap();
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+
+    let response = rt.block_on(future::lazy(move || {
+        let client = mock(move |req: Request<Bytes>| {
+            let ids = ["x-request-id", "x-trace"]
+                .iter()
+                .map(|name| {
+                    req.headers()
+                        .get(*name)
+                        .map(|id| id.to_str().unwrap().to_string())
+                })
+                .collect::<Vec<_>>();
+            tx.lock().unwrap().send(ids).unwrap();
+
+            future::ok(Response::new(Bytes::from("[]")))
+        });
+        let traced = client
+            .clone()
+            .with_request_id_header(HeaderName::from_static("x-trace"));
+
+        client
+            .with_request_id("abc")
+            .get_keys("a")
+            .join(client.clone().get_keys("b"))
+            .join(traced.with_request_id("def").get_keys("c"))
+    }));
+
+    assert!(response.is_ok());
+    let ids = rx.iter().take(3).collect::<Vec<_>>();
+    assert_eq!(ids[0], vec![Some("abc".to_string()), None]);
+    assert_eq!(ids[1], vec![None, None]);
+    assert_eq!(ids[2], vec![None, Some("def".to_string())]);
+}
+
+#[test]
+fn node_name_cached() {
+    let mut rt = Runtime::new().unwrap();
+    let requests = Arc::new(AtomicUsize::new(0));
+    let count = requests.clone();
+
+    let response = rt.block_on(future::lazy(move || {
+        let mut client = mock(move |req: Request<Bytes>| {
+            assert_eq!(req.uri().path(), "/v1/agent/self");
+            count.fetch_add(1, Ordering::SeqCst);
+
+            let body = r#"{
+                "Config": {"Datacenter": "dc1", "NodeName": "node-a", "NodeID": "1234"},
+                "Member": {"Name": "node-a"}
+            }"#;
+            future::ok(Response::new(Bytes::from(body)))
+        });
+        let mut clone = client.clone();
+
+        client
+            .node_name()
+            .and_then(move |first| clone.node_name().map(|second| (first, second)))
+    }));
+
+    assert_eq!(
+        response.unwrap(),
+        ("node-a".to_string(), "node-a".to_string())
+    );
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn assume_raw_kv_values() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let body = r#"[{"CreateIndex": 1, "ModifyIndex": 1, "LockIndex": 0, "Key": "a",
+                        "Flags": 0, "Value": "not base64!"}]"#;
+        let mut client =
+            mock(move |_: Request<Bytes>| future::ok(Response::new(Bytes::from(body))))
+                .assume_raw_kv_values(true);
+        let mut standard = client.clone().assume_raw_kv_values(false);
+
+        client.get_value("a").join(standard.get_value("a").then(Ok))
+    }));
+
+    let (raw, standard) = response.unwrap();
+    assert_eq!(raw, Bytes::from("not base64!"));
+    match standard {
+        Err(Error::Base64 { key, .. }) => assert_eq!(key, "a"),
+        res => panic!("expected Base64, got {:?}", res),
+    }
+}
+
+#[test]
+fn pretty_reads() {
+    let mut rt = Runtime::new().unwrap();
+
+    let sent = rt
+        .block_on(future::lazy(|| {
+            let (client, rx) = capture_query();
+            let mut client = client.with_pretty(true);
+            let mut client2 = client.clone();
+
+            client
+                .get_keys("tower-consul")
+                .join(client2.list_keys("tower-consul/", Some("/")))
+                // The mock does not respond with a bool, only the query matters
+                .join(client.delete("tower-consul").then(|_| Ok(())))
+                .map(move |_| rx.iter().take(3).collect::<Vec<_>>())
+        }))
+        .unwrap();
+
+    assert_eq!(sent, vec!["keys&pretty", "keys&separator=%2F&pretty", ""]);
+}
+
+#[test]
+fn validate_datacenter() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let client = mock(|req: Request<Bytes>| {
+            assert_eq!(req.uri().path(), "/v1/catalog/datacenters");
+            future::ok(Response::new(Bytes::from(r#"["dc1", "dc2"]"#)))
+        });
+        let mut unset = client.clone();
+        let mut known = client.clone().with_datacenter("dc2");
+        let mut typo = client.with_datacenter("dc-2");
+
+        unset
+            .validate_datacenter()
+            .join(known.validate_datacenter())
+            .join(typo.validate_datacenter().then(Ok))
+    }));
+
+    let (_, typo) = response.unwrap();
+    match typo {
+        Err(Error::InvalidArgument(msg)) => assert!(msg.contains("dc-2")),
+        res => panic!("expected InvalidArgument, got {:?}", res),
+    }
+}
+
+#[test]
+fn datacenter_param() {
+    let mut rt = Runtime::new().unwrap();
+
+    let sent = rt
+        .block_on(future::lazy(|| {
+            let (client, rx) = capture_query();
+            let mut client = client.with_datacenter("dc2");
+            let mut client2 = client.clone();
+
+            client
+                .get_keys("tower-consul")
+                .join(client2.service_nodes_query(
+                    "web",
+                    ServiceQuery {
+                        dc: Some("dc3".into()),
+                        ..Default::default()
+                    },
+                ))
+                .join(client.node_name().then(|_| Ok(())))
+                .map(move |_| rx.iter().take(3).collect::<Vec<_>>())
+        }))
+        .unwrap();
+
+    assert_eq!(sent, vec!["keys&dc=dc2", "dc=dc3", ""]);
+}
+
+#[test]
+fn get_latest() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|req: Request<Bytes>| {
+            let response = if req.uri().path() == "/v1/kv/config/" {
+                Response::new(Bytes::from(
+                    r#"[
+                        {"CreateIndex": 1, "ModifyIndex": 7, "LockIndex": 0,
+                         "Key": "config/v1", "Flags": 0, "Value": null},
+                        {"CreateIndex": 2, "ModifyIndex": 9, "LockIndex": 0,
+                         "Key": "config/v2", "Flags": 0, "Value": null},
+                        {"CreateIndex": 3, "ModifyIndex": 4, "LockIndex": 0,
+                         "Key": "config/v3", "Flags": 0, "Value": null}
+                    ]"#,
+                ))
+            } else {
+                Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(Bytes::new())
+                    .unwrap()
+            };
+
+            future::ok(response)
+        });
+        let mut client2 = client.clone();
+
+        client
+            .get_latest("config/")
+            .join(client2.get_latest("missing/"))
+    }));
+
+    let (latest, missing) = response.unwrap();
+    assert_eq!(latest.unwrap().key, "config/v2");
+    assert!(missing.is_none());
+}
+
+#[test]
+fn set_in_place() {
+    let mut rt = Runtime::new().unwrap();
+
+    let sent = rt
+        .block_on(future::lazy(|| {
+            let (mut client, rx) = capture_query();
+            client.set_datacenter(Some("dc2".into())).set_pretty(true);
+            let before = client.get_keys("a");
+
+            client.set_datacenter(None);
+            let after = client.get_keys("a");
+
+            before
+                .join(after)
+                .map(move |_| rx.iter().take(2).collect::<Vec<_>>())
+        }))
+        .unwrap();
+
+    assert_eq!(sent, vec!["keys&dc=dc2&pretty", "keys&pretty"]);
+}
+
+#[test]
+fn get_with_meta() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|_: Request<Bytes>| {
+            let response = Response::builder()
+                .header("X-Consul-Index", "42")
+                .header("X-Consul-KnownLeader", "true")
+                .header("X-Consul-LastContact", "1500")
+                .header("X-Consul-Effective-Consistency", "stale")
+                .body(Bytes::from("[]"))
+                .unwrap();
+
+            future::ok(response)
+        });
+
+        let mut capturing = client.clone().with_raw_body_capture(true);
+
+        client
+            .get_with_meta("tower-consul/a")
+            .join(capturing.get_with_meta("tower-consul/a"))
+    }));
+
+    let ((values, meta), (_, captured)) = response.unwrap();
+    assert!(values.is_empty());
+    assert_eq!(meta.index, Some(42));
+    assert_eq!(meta.known_leader, Some(true));
+    assert_eq!(meta.last_contact, Some(Duration::from_millis(1500)));
+    assert_eq!(meta.effective_consistency.as_deref(), Some("stale"));
+    assert_eq!(meta.raw_body, None);
+    assert_eq!(captured.raw_body, Some(Bytes::from("[]")));
+}
+
+#[test]
+fn compare_and_swap() {
+    let mut rt = Runtime::new().unwrap();
+    let writes = Arc::new(AtomicUsize::new(0));
+    let count = writes.clone();
+
+    let response = rt.block_on(future::lazy(move || {
+        let mut client = mock(move |req: Request<Bytes>| {
+            if req.method() == http::Method::PUT {
+                // Another writer sets the same value between the first read
+                // and write, so only the second write succeeds.
+                let attempt = count.fetch_add(1, Ordering::SeqCst);
+                assert_eq!(req.uri().query(), Some(&*format!("cas={}", 10 + attempt)));
+                assert_eq!(req.body(), &Bytes::from("new"));
+                let body = if attempt == 0 { "false" } else { "true" };
+                return future::ok(Response::new(Bytes::from(body)));
+            }
+
+            let body = match req.uri().path() {
+                "/v1/kv/tower-consul/swap" => format!(
+                    r#"[{{"CreateIndex": 1, "ModifyIndex": {}, "LockIndex": 0,
+                          "Key": "tower-consul/swap", "Flags": 0, "Value": "b2xk"}}]"#,
+                    10 + count.load(Ordering::SeqCst)
+                ),
+                _ => {
+                    return future::ok(Response::builder().status(404).body(Bytes::new()).unwrap())
+                }
+            };
+
+            future::ok(Response::new(Bytes::from(body)))
+        });
+        let mut client2 = client.clone();
+        let mut client3 = client.clone();
+
+        client
+            .compare_and_swap("tower-consul/swap", b"old", "new")
+            .join(client2.compare_and_swap("tower-consul/swap", b"other", "new"))
+            .join(client3.compare_and_swap("tower-consul/missing", b"old", "new"))
+    }));
+
+    let ((swapped, mismatch), missing) = response.unwrap();
+    assert!(swapped);
+    assert!(!mismatch);
+    assert!(!missing);
+    assert_eq!(writes.load(Ordering::SeqCst), 2);
+}
+
+fn warning_instances(req: Request<Bytes>) -> MockFuture {
+    let instance = |address: &str, status: &str| {
+        format!(
+            r#"{{
+                "Node": {{"Node": "node-a", "Address": "{}"}},
+                "Service": {{"ID": "web", "Service": "web", "Port": 80}},
+                "Checks": [
+                    {{"Node": "node-a", "CheckID": "serfHealth", "Name": "Serf", "Status": "passing"}},
+                    {{"Node": "node-a", "CheckID": "web", "Name": "web", "Status": "{}"}}
+                ]
+            }}"#,
+            address, status
+        )
+    };
+
+    // Consul drops the instance with a warning when `passing` is set.
+    let body = if req.uri().query().unwrap_or("").contains("passing") {
+        format!("[{}]", instance("10.0.0.1", "passing"))
+    } else {
+        format!(
+            "[{}, {}, {}]",
+            instance("10.0.0.1", "passing"),
+            instance("10.0.0.2", "warning"),
+            instance("10.0.0.3", "critical")
+        )
+    };
+
+    future::ok(Response::new(Bytes::from(body)))
+}
+
+#[test]
+fn warnings_are_healthy() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut strict = mock(warning_instances);
+        let mut lenient = mock(warning_instances).warnings_are_healthy(true);
+
+        let strict_addrs = strict.discover("web");
+        let strict_health = strict.service_health("web");
+        let lenient_addrs = lenient.discover("web");
+        let lenient_health = lenient.service_health("web");
+
+        strict_addrs
+            .join(strict_health)
+            .join(lenient_addrs.join(lenient_health))
+    }));
+
+    let ((strict_addrs, strict_health), (lenient_addrs, lenient_health)) = response.unwrap();
+
+    let addr = |s: &str| s.parse::<SocketAddr>().unwrap();
+    assert_eq!(strict_addrs, vec![addr("10.0.0.1:80")]);
+    assert_eq!(strict_health, HealthStatus::Critical);
+    assert_eq!(
+        lenient_addrs,
+        vec![addr("10.0.0.1:80"), addr("10.0.0.2:80")]
+    );
+    assert_eq!(lenient_health, HealthStatus::Critical);
+}
+
+#[test]
+fn warning_service_health() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let warning = |_: Request<Bytes>| -> MockFuture {
+            let body = r#"[{
+                "Node": {"Node": "node-a", "Address": "10.0.0.1"},
+                "Service": {"ID": "web", "Service": "web", "Port": 80},
+                "Checks": [{"Node": "node-a", "CheckID": "web", "Name": "web", "Status": "warning"}]
+            }]"#;
+            future::ok(Response::new(Bytes::from(body)))
+        };
+
+        let strict = mock(warning).service_health("web");
+        let lenient = mock(warning)
+            .warnings_are_healthy(true)
+            .service_health("web");
+        strict.join(lenient)
+    }));
+
+    assert_eq!(
+        response.unwrap(),
+        (HealthStatus::Warning, HealthStatus::Passing)
+    );
+}
+
+#[test]
+fn services_health() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|req: Request<Bytes>| {
+            let instance = |status: &str| {
+                format!(
+                    r#"{{
+                        "Node": {{"Node": "node-a", "Address": "10.0.0.1"}},
+                        "Service": {{"ID": "a", "Service": "a", "Port": 80}},
+                        "Checks": [
+                            {{"Node": "node-a", "CheckID": "serfHealth", "Name": "Serf", "Status": "passing"}},
+                            {{"Node": "node-a", "CheckID": "a", "Name": "a", "Status": "{}"}}
+                        ]
+                    }}"#,
+                    status
+                )
+            };
+
+            let body = match req.uri().path() {
+                "/v1/health/service/web" => format!("[{}]", instance("passing")),
+                "/v1/health/service/db" => {
+                    format!("[{}, {}]", instance("passing"), instance("warning"))
+                }
+                "/v1/health/service/cache" => {
+                    format!("[{}, {}]", instance("critical"), instance("warning"))
+                }
+                "/v1/health/service/none" => "[]".to_string(),
+                _ => {
+                    let response = Response::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body(Bytes::new())
+                        .unwrap();
+                    return future::ok(response);
+                }
+            };
+
+            future::ok(Response::new(Bytes::from(body)))
+        });
+
+        client.services_health(&["web", "db", "cache", "none", "broken"])
+    }));
+
+    let health = response.unwrap();
+    assert_eq!(health["web"], HealthStatus::Passing);
+    assert_eq!(health["db"], HealthStatus::Warning);
+    assert_eq!(health["cache"], HealthStatus::Critical);
+    assert_eq!(health["none"], HealthStatus::Unknown);
+    assert_eq!(health["broken"], HealthStatus::Unknown);
+}
+
+#[test]
+fn watch_seeds_index() {
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(move || {
+        let mut client = mock(move |req: Request<Bytes>| {
+            let query = req.uri().query().unwrap_or("").to_string();
+
+            // The initial read reports an index of zero, the next query
+            // must still block rather than repeat the initial read.
+            let index = match query.as_str() {
+                "" => "0",
+                "index=1&wait=1000ms" => "2",
+                _ => panic!("unexpected query {}", query),
+            };
+            tx.lock().unwrap().send(query).unwrap();
+
+            let response = Response::builder()
+                .header("X-Consul-Index", index)
+                .body(Bytes::from("[]"))
+                .unwrap();
+
+            future::ok(response)
+        });
+
+        client
+            .watch("tower-consul/a", Duration::from_secs(1))
+            .take(2)
+            .collect()
+    }));
+
+    assert_eq!(response.unwrap().len(), 2);
+    let queries = rx.iter().take(2).collect::<Vec<_>>();
+    assert_eq!(queries, vec!["", "index=1&wait=1000ms"]);
+}
+
+#[test]
+fn watch_max_index() {
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+    let calls = Arc::new(AtomicUsize::new(0));
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(move || {
+        let mut client = mock(move |req: Request<Bytes>| {
+            let query = req.uri().query().unwrap_or("").to_string();
+            tx.lock().unwrap().send(query).unwrap();
+
+            // An index past `i64::MAX` saturates and is treated as
+            // unchanged, then the index goes backwards and the watch
+            // starts over.
+            let index = match calls.fetch_add(1, Ordering::SeqCst) {
+                0 => "9223372036854775807",
+                1 => "18446744073709551615",
+                2 => "1",
+                _ => "5",
+            };
+
+            let response = Response::builder()
+                .header("X-Consul-Index", index)
+                .body(Bytes::from("[]"))
+                .unwrap();
+
+            future::ok(response)
+        });
+
+        client
+            .watch("tower-consul/a", Duration::from_secs(1))
+            .take(2)
+            .collect()
+    }));
+
+    assert_eq!(response.unwrap().len(), 2);
+    let queries = rx.iter().take(4).collect::<Vec<_>>();
+    assert_eq!(
+        queries,
+        vec![
+            "",
+            "index=9223372036854775807&wait=1000ms",
+            "index=9223372036854775807&wait=1000ms",
+            "",
+        ]
+    );
+}
+
+#[test]
+fn execute_custom_request() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|req: Request<Bytes>| {
+            // Nothing but the scheme and authority is added.
+            assert_eq!(req.method(), "PURGE");
+            assert_eq!(req.uri(), "http://127.0.0.1:8500/custom/path?x=1");
+            assert!(req.headers().get("X-Consul-Token").is_none());
+            assert_eq!(req.headers()["x-custom"], "yes");
+
+            let response = Response::builder()
+                .status(StatusCode::IM_A_TEAPOT)
+                .body(req.into_body())
+                .unwrap();
+
+            future::ok(response)
+        })
+        .with_token("secret");
+
+        let request = Request::builder()
+            .method("PURGE")
+            .uri("/custom/path?x=1")
+            .header("x-custom", "yes")
+            .body(Bytes::from("body"))
+            .unwrap();
+
+        client.execute(request)
+    }));
+
+    let response = response.unwrap();
+    assert_eq!(response.status(), StatusCode::IM_A_TEAPOT);
+    assert_eq!(response.body(), "body");
+}
+
+#[test]
+fn metrics_by_operation() {
+    let mut rt = Runtime::new().unwrap();
+
+    let metrics = rt.block_on(future::lazy(|| {
+        let client = mock(|req: Request<Bytes>| {
+            let status = if req.uri().path().starts_with("/v1/health/") {
+                StatusCode::INTERNAL_SERVER_ERROR
+            } else {
+                StatusCode::OK
+            };
+
+            let response = Response::builder()
+                .status(status)
+                .body(Bytes::from("[]"))
+                .unwrap();
+
+            future::ok(response)
+        });
+
+        // Only the counters matter, the failed health read included.
+        fn done<T>(_: Result<T, Error>) -> Result<(), ()> {
+            Ok(())
+        }
+
+        let kv_a = client.clone().get("tower-consul/a").then(done);
+        let kv_b = client.clone().get("tower-consul/b").then(done);
+        let catalog = client.clone().service_nodes("web").then(done);
+        let health = client
+            .clone()
+            .health_service("web", HealthQuery::default())
+            .then(done);
+
+        kv_a.join4(kv_b, catalog, health)
+            .map(move |_| client.metrics())
+    }));
+
+    let metrics = metrics.unwrap();
+    assert_eq!(metrics.requests(Operation::Kv), 2);
+    assert_eq!(metrics.errors(Operation::Kv), 0);
+    assert_eq!(metrics.requests(Operation::Catalog), 1);
+    assert_eq!(metrics.requests(Operation::Health), 1);
+    assert_eq!(metrics.errors(Operation::Health), 1);
+    assert_eq!(metrics.requests(Operation::Agent), 0);
+    assert_eq!(metrics.total_requests(), 4);
+}
+
+#[test]
+fn plan_and_apply() {
+    let mut rt = Runtime::new().unwrap();
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+
+    let plan = rt.block_on(future::lazy(move || {
+        let mut client = mock(move |req: Request<Bytes>| {
+            if req.method() == http::Method::PUT {
+                tx.lock().unwrap().send(req.into_body()).unwrap();
+                return future::ok(Response::new(Bytes::from(r#"{"Results": []}"#)));
+            }
+
+            // a = "1", b = "2" and c = "3"
+            let body = r#"[
+                {"CreateIndex": 1, "ModifyIndex": 5, "LockIndex": 0,
+                 "Key": "app/a", "Flags": 0, "Value": "MQ=="},
+                {"CreateIndex": 2, "ModifyIndex": 6, "LockIndex": 0,
+                 "Key": "app/b", "Flags": 7, "Value": "Mg=="},
+                {"CreateIndex": 3, "ModifyIndex": 7, "LockIndex": 0,
+                 "Key": "app/c", "Flags": 0, "Value": "Mw=="}
+            ]"#;
+            future::ok(Response::new(Bytes::from(body)))
+        });
+
+        let mut desired = HashMap::new();
+        desired.insert("app/a".to_string(), Bytes::from("1"));
+        desired.insert("app/b".to_string(), Bytes::from("two"));
+        desired.insert("app/d".to_string(), Bytes::from("4"));
+
+        client.plan("app/", desired).and_then(move |plan| {
+            let applied = plan.clone();
+            client
+                .apply(applied)
+                .map(move |committed| (plan, committed))
+        })
+    }));
+
+    let (plan, committed) = plan.unwrap();
+    assert!(committed);
+
+    let keys = |changes: &[KvChange]| changes.iter().map(|c| c.key.clone()).collect::<Vec<_>>();
+    assert_eq!(keys(&plan.create), vec!["app/d"]);
+    assert_eq!(keys(&plan.update), vec!["app/b"]);
+    assert_eq!(keys(&plan.delete), vec!["app/c"]);
+
+    let ops: serde_json::Value = serde_json::from_slice(&rx.recv().unwrap()).unwrap();
+    assert_eq!(
+        ops,
+        serde_json::json!([
+            {"KV": {"Verb": "cas", "Key": "app/d", "Value": "NA==", "Flags": 0, "Index": 0}},
+            {"KV": {"Verb": "cas", "Key": "app/b", "Value": "dHdv", "Flags": 7, "Index": 6}},
+            {"KV": {"Verb": "delete-cas", "Key": "app/c", "Index": 7}},
+        ])
+    );
+}
+
+#[test]
+fn plan_outside_prefix() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|_: Request<Bytes>| -> MockFuture { panic!("no request is sent") });
+
+        let mut desired = HashMap::new();
+        desired.insert("other/a".to_string(), Bytes::from("1"));
+        client.plan("app/", desired)
+    }));
+
+    match response {
+        Err(Error::InvalidArgument(_)) => {}
+        res => panic!("expected InvalidArgument, got {:?}", res),
+    }
+}
+
+/// Counts the requests passed to the inner service.
+struct CountingLayer(Arc<AtomicUsize>);
+
+struct Counting<S> {
+    inner: S,
+    count: Arc<AtomicUsize>,
+}
+
+impl<S> Layer<S> for CountingLayer {
+    type Service = Counting<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Counting {
+            inner,
+            count: self.0.clone(),
+        }
+    }
+}
+
+impl<S> Service<Request<Bytes>> for Counting<S>
+where
+    S: Service<Request<Bytes>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Request<Bytes>) -> Self::Future {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        self.inner.call(req)
+    }
+}
+
+#[test]
+fn layered_client() {
+    let mut rt = Runtime::new().unwrap();
+    let count = Arc::new(AtomicUsize::new(0));
+    let layer = CountingLayer(count.clone());
+
+    rt.block_on(future::lazy(move || {
+        let inner = service_fn(|_: Request<Bytes>| -> MockFuture {
+            future::ok(Response::new(Bytes::from(r#"["tower-consul/a"]"#)))
+        });
+        let mut client =
+            match Consul::layered(inner, 100, "http".into(), "127.0.0.1:8500".into(), layer) {
+                Ok(c) => c,
+                Err(_) => panic!("Unable to spawn!"),
+            };
+
+        client
+            .get_keys("tower-consul/")
+            .join(client.clone().get_keys("tower-consul/"))
+    }))
+    .unwrap();
+
+    assert_eq!(count.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn rename_key() {
+    let mut rt = Runtime::new().unwrap();
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+
+    let response = rt.block_on(future::lazy(move || {
+        let mut client = mock(move |req: Request<Bytes>| {
+            if req.method() == http::Method::PUT {
+                tx.lock().unwrap().send(req.into_body()).unwrap();
+                return future::ok(Response::new(Bytes::from(r#"{"Results": []}"#)));
+            }
+
+            if req.uri().path() != "/v1/kv/app/old" {
+                return future::ok(Response::builder().status(404).body(Bytes::new()).unwrap());
+            }
+
+            let body = r#"[{"CreateIndex": 1, "ModifyIndex": 9, "LockIndex": 0,
+                            "Key": "app/old", "Flags": 3, "Value": "dmFsdWU="}]"#;
+            future::ok(Response::new(Bytes::from(body)))
+        });
+
+        let renamed = client.rename("app/old", "app/new");
+        let missing = client.rename("app/missing", "app/other");
+        renamed.join(missing)
+    }));
+
+    assert_eq!(response.unwrap(), (true, false));
+
+    let ops: serde_json::Value = serde_json::from_slice(&rx.recv().unwrap()).unwrap();
+    assert_eq!(
+        ops,
+        serde_json::json!([
+            {"KV": {"Verb": "cas", "Key": "app/new", "Value": "dmFsdWU=", "Flags": 3, "Index": 0}},
+            {"KV": {"Verb": "delete-cas", "Key": "app/old", "Index": 9}},
+        ])
+    );
+    assert!(rx.try_recv().is_err());
+}
+
+#[test]
+fn kv_prefix() {
+    let mut rt = Runtime::new().unwrap();
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+
+    let response = rt.block_on(future::lazy(move || {
+        let client = mock(move |req: Request<Bytes>| {
+            let path = req.uri().path_and_query().unwrap().to_string();
+            tx.lock().unwrap().send(path.clone()).unwrap();
+
+            let body = match path.as_str() {
+                "/v1/kv/apps/web/?keys" => r#"["apps/web/a", "apps/web/b/c"]"#,
+                "/v1/kv/apps/web/a" => {
+                    r#"[{"CreateIndex": 1, "ModifyIndex": 1, "LockIndex": 0,
+                        "Key": "apps/web/a", "Flags": 0, "Value": "MQ=="}]"#
+                }
+                _ => "true",
+            };
+            future::ok(Response::new(Bytes::from(body)))
+        });
+        let mut client = client.with_kv_prefix("apps/web/");
+
+        let keys = client.get_keys("");
+        let values = client.get("a");
+        let set = client.set("b/c", "2");
+        keys.join3(values, set)
+    }));
+
+    let (keys, values, _) = response.unwrap();
+    assert_eq!(keys, vec!["a", "b/c"]);
+    assert_eq!(values[0].key, "a");
+
+    let mut paths = rx.iter().take(3).collect::<Vec<_>>();
+    paths.sort();
+    assert_eq!(
+        paths,
+        vec![
+            "/v1/kv/apps/web/?keys",
+            "/v1/kv/apps/web/a",
+            "/v1/kv/apps/web/b/c"
+        ]
+    );
+}
+
+#[test]
+fn update_read_modify_write() {
+    let mut rt = Runtime::new().unwrap();
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+
+    let response = rt.block_on(future::lazy(move || {
+        let mut client = mock(move |req: Request<Bytes>| {
+            let path = req.uri().path().to_string();
+
+            if req.method() != http::Method::GET {
+                let query = req.uri().query().unwrap_or("").to_string();
+                let write = format!("{} {} {}", req.method(), path, query);
+                tx.lock().unwrap().send(write).unwrap();
+
+                // The stuck key is modified concurrently on every write.
+                let body = if path == "/v1/kv/tower-consul/stuck" {
+                    "false"
+                } else {
+                    "true"
+                };
+                return future::ok(Response::new(Bytes::from(body)));
+            }
+
+            let body = match path.as_str() {
+                "/v1/kv/tower-consul/counter" => {
+                    r#"[{"CreateIndex": 1, "ModifyIndex": 2, "LockIndex": 0,
+                        "Key": "tower-consul/counter", "Flags": 0, "Value": "Mg=="}]"#
+                }
+                "/v1/kv/tower-consul/gone" | "/v1/kv/tower-consul/stuck" => {
+                    r#"[{"CreateIndex": 1, "ModifyIndex": 4, "LockIndex": 0,
+                        "Key": "tower-consul/gone", "Flags": 0, "Value": "eA=="}]"#
+                }
+                _ => {
+                    return future::ok(Response::builder().status(404).body(Bytes::new()).unwrap())
+                }
+            };
+
+            future::ok(Response::new(Bytes::from(body)))
+        });
+
+        let increment = |value: Option<Bytes>| {
+            let n = value.map_or(0, |value| value[0] - b'0');
+            Some(Bytes::from(vec![b'0' + n + 1]))
+        };
+
+        let counter = client.update("tower-consul/counter", increment);
+        let created = client.update("tower-consul/new", increment);
+        let deleted = client.update("tower-consul/gone", |_| None);
+        let untouched = client.update("tower-consul/missing", |_| None);
+        let stuck = client.update("tower-consul/stuck", increment).then(Ok);
+
+        counter.join5(created, deleted, untouched, stuck)
+    }));
+
+    let (_, _, _, _, stuck) = response.unwrap();
+    match stuck {
+        Err(Error::CasConflict(key)) => assert_eq!(key, "tower-consul/stuck"),
+        res => panic!("expected a conflict, got {:?}", res),
+    }
+
+    let mut writes = rx.iter().take(8).collect::<Vec<_>>();
+    writes.sort();
+    assert_eq!(
+        &writes[..3],
+        &[
+            "DELETE /v1/kv/tower-consul/gone cas=4",
+            "PUT /v1/kv/tower-consul/counter cas=2",
+            "PUT /v1/kv/tower-consul/new cas=0",
+        ]
+    );
+    assert!(writes[3..]
+        .iter()
+        .all(|write| write == "PUT /v1/kv/tower-consul/stuck cas=4"));
+}
+
+#[test]
+fn session_datacenter() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|req: Request<Bytes>| {
+            // Sessions are scoped to the datacenter of the client.
+            assert_eq!(req.uri().query(), Some("dc=dc2"));
+
+            let body = match req.uri().path() {
+                "/v1/session/renew/a" | "/v1/session/info/a" => {
+                    r#"[{"ID": "a", "Node": "node-a", "TTL": "15s",
+                         "CreateIndex": 1, "ModifyIndex": 2}]"#
+                }
+                "/v1/session/renew/b" => {
+                    return future::ok(Response::builder().status(404).body(Bytes::new()).unwrap())
+                }
+                _ => "null",
+            };
+
+            future::ok(Response::new(Bytes::from(body)))
+        })
+        .with_datacenter("dc2");
+
+        client
+            .renew_session("a")
+            .join(client.renew_session("b").then(Ok))
+            .join(client.validate_session("a"))
+            .join(client.validate_session("b").then(Ok))
+    }));
+
+    let (((renewed, missing), _), invalid) = response.unwrap();
+    assert_eq!(renewed.id, "a");
+    assert_eq!(renewed.ttl, Some(Duration::from_secs(15).into()));
+    match missing {
+        Err(Error::NotFound) => {}
+        res => panic!("expected not found, got {:?}", res),
+    }
+    match invalid {
+        Err(Error::InvalidArgument(msg)) => {
+            assert_eq!(msg, "session b does not exist in datacenter dc2")
+        }
+        res => panic!("expected an invalid argument, got {:?}", res),
+    }
+}
+
+#[test]
+fn auto_renew_token() {
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+    let mut rt = Runtime::new().unwrap();
+
+    let client = rt.block_on(future::lazy(move || {
+        let client = mock(move |req: Request<Bytes>| {
+            let token = req.headers()["X-Consul-Token"]
+                .to_str()
+                .unwrap()
+                .to_string();
+            let path = req.uri().path().to_string();
+            tx.lock().unwrap().send((path, token.clone())).unwrap();
+
+            // The old token has already expired, the renewed one does not
+            // expire which stops the renewal task.
+            let expiration = match token.as_str() {
+                "old" => r#""ExpirationTime": "2019-04-10T16:30:59Z","#,
+                _ => "",
+            };
+            let body = format!(
+                r#"{{"AccessorID": "a", "SecretID": "{}", {} "Policies": null}}"#,
+                token, expiration
+            );
+
+            future::ok(Response::new(Bytes::from(body)))
+        })
+        .with_token("old");
+
+        client.with_auto_renew_token(|token| {
+            assert_eq!(token.secret_id, "old");
+            assert!(token.expires_at().is_some());
+            Ok("new".to_string())
+        })
+    }));
+
+    let mut client = client.unwrap();
+    let reads = rx.iter().take(2).collect::<Vec<_>>();
+    assert_eq!(
+        reads,
+        vec![
+            ("/v1/acl/token/self".to_string(), "old".to_string()),
+            ("/v1/acl/token/self".to_string(), "new".to_string()),
+        ]
+    );
+
+    let token = rt.block_on(future::lazy(move || client.token_self()));
+    assert_eq!(token.unwrap().secret_id, "new");
+}
+
+#[test]
+fn agent_service_checks() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|req: Request<Bytes>| {
+            assert_eq!(req.uri().path(), "/v1/agent/checks");
+
+            let body = r#"{
+                "serfHealth": {"Node": "a", "CheckID": "serfHealth", "Name": "Serf", "Status": "passing"},
+                "web-2": {"Node": "a", "CheckID": "web-2", "Name": "web", "Status": "critical",
+                          "ServiceID": "web", "ServiceName": "web", "ServiceTags": null, "Type": "http"},
+                "web-1": {"Node": "a", "CheckID": "web-1", "Name": "web", "Status": "passing",
+                          "ServiceID": "web", "ServiceName": "web", "Type": "ttl"},
+                "db": {"Node": "a", "CheckID": "db", "Name": "db", "Status": "passing",
+                       "ServiceID": "db", "ServiceName": "db"}
+            }"#;
+
+            future::ok(Response::new(Bytes::from(body)))
+        });
+
+        client.agent_service_checks("web")
+    }));
+
+    let checks = response.unwrap();
+    let ids = checks
+        .iter()
+        .map(|check| check.check_id.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(ids, vec!["web-1", "web-2"]);
+    assert_eq!(checks[1].status, "critical");
+    assert_eq!(checks[1].r#type, "http");
+}
+
+#[test]
+fn force_leave_if_failed() {
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(move || {
+        let mut client = mock(move |req: Request<Bytes>| {
+            let path = req.uri().path().to_string();
+            let serf = |status: &str| {
+                format!(
+                    r#"[{{"Node": "n", "CheckID": "serfHealth", "Name": "Serf", "Status": "{}"}},
+                        {{"Node": "n", "CheckID": "web", "Name": "web", "Status": "critical"}}]"#,
+                    status
+                )
+            };
+
+            let body = match path.as_str() {
+                "/v1/health/node/failed" => serf("critical"),
+                "/v1/health/node/slow" => serf("passing"),
+                "/v1/health/node/unknown" => "[]".to_string(),
+                _ => {
+                    tx.lock().unwrap().send(path).unwrap();
+                    String::new()
+                }
+            };
+
+            future::ok(Response::new(Bytes::from(body)))
+        });
+
+        client.force_leave_if_failed("failed").join3(
+            client.force_leave_if_failed("slow"),
+            client.force_leave_if_failed("unknown"),
+        )
+    }));
+
+    assert_eq!(response.unwrap(), (true, false, false));
+    let left = rx.iter().take(1).collect::<Vec<_>>();
+    assert_eq!(left, vec!["/v1/agent/force-leave/failed"]);
+}
+
+#[test]
+fn get_entry() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|req: Request<Bytes>| {
+            if req.uri().path() != "/v1/kv/tower-consul/a" {
+                return future::ok(Response::builder().status(404).body(Bytes::new()).unwrap());
+            }
+
+            let body = r#"[{"CreateIndex": 3, "ModifyIndex": 7, "LockIndex": 1,
+                "Key": "tower-consul/a", "Flags": 42, "Value": "dmFsdWU=", "Session": "s1"}]"#;
+            future::ok(Response::new(Bytes::from(body)))
+        });
+
+        client
+            .get_entry("tower-consul/a")
+            .join(client.get_entry("tower-consul/missing"))
+    }));
+
+    let (entry, missing) = response.unwrap();
+    assert_eq!(
+        entry,
+        Some(KvEntry {
+            key: "tower-consul/a".into(),
+            value: Bytes::from("value"),
+            flags: 42,
+            create_index: 3,
+            modify_index: 7,
+            session: Some("s1".into()),
+        })
+    );
+    assert_eq!(missing, None);
+}
+
+#[test]
+fn create_session_without_checks() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|_: Request<Bytes>| -> MockFuture {
+            panic!("an invalid session must not be sent")
+        });
+
+        let session = SessionRequest {
+            node: Some("external-db".into()),
+            behavior: Some("delete".into()),
+            node_checks: Some(Vec::new()),
+            ..SessionRequest::default()
+        };
+
+        client.create_session(&session)
+    }));
+
+    match response {
+        Err(Error::InvalidArgument(_)) => {}
+        res => panic!("expected an invalid argument, got {:?}", res),
+    }
+}
+
+#[test]
+fn deregister_detached() {
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(move || {
+        let mut client = mock(move |req: Request<Bytes>| {
+            tx.lock()
+                .unwrap()
+                .send(req.uri().path().to_string())
+                .unwrap();
+            future::ok(Response::new(Bytes::new()))
+        });
+
+        future::result(client.deregister_detached("web-1"))
+    }));
+
+    assert!(response.is_ok());
+    let paths = rx.iter().take(1).collect::<Vec<_>>();
+    assert_eq!(paths, vec!["/v1/agent/service/deregister/web-1"]);
+}
+
+struct FixedClock(std::time::SystemTime);
+
+impl Clock for FixedClock {
+    fn now(&self) -> std::time::SystemTime {
+        self.0
+    }
+}
+
+#[test]
+fn destroy_expired_sessions_clock() {
+    let mut rt = Runtime::new().unwrap();
+    let destroyed = Arc::new(Mutex::new(Vec::new()));
+    let seen = destroyed.clone();
+
+    let response = rt.block_on(future::lazy(move || {
+        let client = mock(move |req: Request<Bytes>| {
+            let path = req.uri().path();
+            let body = if path == "/v1/session/list" {
+                r#"[
+                    {"ID": "minute", "Node": "a", "CreateIndex": 1, "ModifyIndex": 1,
+                     "CreateTime": "2019-04-10T16:30:00Z"},
+                    {"ID": "second", "Node": "a", "CreateIndex": 2, "ModifyIndex": 2,
+                     "CreateTime": "2019-04-10T16:30:59Z"}
+                ]"#
+            } else if path == "/v1/kv/locks/" {
+                "[]"
+            } else if let Some(id) = path.strip_prefix("/v1/session/destroy/") {
+                destroyed.lock().unwrap().push(id.to_string());
+                "true"
+            } else {
+                panic!("unexpected request {}", req.uri());
+            };
+
+            future::ok(Response::new(Bytes::from(body)))
+        });
+
+        // 2019-04-10T16:31:00Z
+        let now = std::time::UNIX_EPOCH + Duration::from_secs(1_554_913_860);
+        let mut client = client.with_clock(FixedClock(now));
+
+        client.destroy_expired_sessions(Duration::from_secs(30), "locks/")
+    }));
+
+    assert_eq!(response.unwrap(), 1);
+    assert_eq!(*seen.lock().unwrap(), vec!["minute".to_string()]);
+}
+
+#[test]
+fn acl_default_policy() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let bodies = [
+            r#"{"Config": {"NodeName": "a"},
+                "DebugConfig": {"ACLResolverSettings": {"ACLDefaultPolicy": "deny"}}}"#,
+            r#"{"Config": {"NodeName": "a"}, "DebugConfig": {"ACLDefaultPolicy": "allow"}}"#,
+            r#"{"Config": {"NodeName": "a", "ACLDefaultPolicy": "deny"}}"#,
+            r#"{"Config": {"NodeName": "a"}}"#,
+        ];
+        let calls = AtomicUsize::new(0);
+
+        let mut client = mock(move |_: Request<Bytes>| {
+            let body = bodies[calls.fetch_add(1, Ordering::SeqCst)];
+            future::ok(Response::new(Bytes::from(body)))
+        });
+
+        // The reads are sent one after another to match the bodies.
+        future::loop_fn(Vec::new(), move |mut policies| {
+            client.acl_default_policy().then(move |res| {
+                policies.push(res.ok());
+                if policies.len() == 4 {
+                    Ok(Loop::Break(policies))
+                } else {
+                    Ok::<_, Error>(Loop::Continue(policies))
+                }
+            })
+        })
+    }));
+
+    assert_eq!(
+        response.unwrap(),
+        vec![
+            Some("deny".to_string()),
+            Some("allow".to_string()),
+            Some("deny".to_string()),
+            None,
+        ]
+    );
+}
+
+#[test]
+fn get_recurse_bounded() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        // Two values of 5 bytes each, "value".
+        let body = r#"[
+            {"CreateIndex": 1, "ModifyIndex": 1, "LockIndex": 0, "Key": "a/1", "Flags": 0,
+             "Value": "dmFsdWU="},
+            {"CreateIndex": 2, "ModifyIndex": 2, "LockIndex": 0, "Key": "a/2", "Flags": 0,
+             "Value": "dmFsdWU="}
+        ]"#;
+        let mut client =
+            mock(move |_: Request<Bytes>| future::ok(Response::new(Bytes::from(body))));
+
+        client
+            .get_recurse_bounded("a", 10)
+            .join(client.get_recurse_bounded("a", 9).then(Ok))
+    }));
+
+    let (values, over) = response.unwrap();
+    assert_eq!(values.len(), 2);
+    match over {
+        Err(Error::BodyTooLarge(9)) => {}
+        res => panic!("expected BodyTooLarge, got {:?}", res),
+    }
+}
+
+#[test]
+fn get_recurse_bounded_stops_reading() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        // The body is cut off after the value that exceeds the budget, it
+        // is never parsed that far.
+        let body = r#"[
+            {"CreateIndex": 1, "ModifyIndex": 1, "LockIndex": 0, "Key": "a/1", "Flags": 0,
+             "Value": "dmFsdWU="},
+            {"CreateIndex": 2, "ModifyIndex": 2, "LockIndex": 0, "Key": "a/2", "Flags": 0,
+             "Value": "dmFsdWU="},
+            {"CreateIndex": 3, "Mod"#;
+        let mut client =
+            mock(move |_: Request<Bytes>| future::ok(Response::new(Bytes::from(body))));
+
+        client.get_recurse_bounded("a", 9)
+    }));
+
+    match response {
+        Err(Error::BodyTooLarge(9)) => {}
+        res => panic!("expected BodyTooLarge, got {:?}", res),
+    }
+}
+
+#[test]
+fn all_service_instances() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|req: Request<Bytes>| {
+            let body = match req.uri().path() {
+                "/v1/catalog/services" => r#"{"web": ["v1"], "db": [], "broken": []}"#.to_string(),
+                "/v1/catalog/service/broken" => {
+                    let response = Response::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body(Bytes::from("boom"))
+                        .unwrap();
+                    return future::ok(response);
+                }
+                path => format!(
+                    r#"[{{"ServiceKind": "", "ID": "", "ServiceID": "{0}", "ServiceName": "{0}",
+                          "ServiceTags": [], "ServiceMeta": {{}}, "Node": "node-a",
+                          "Address": "10.0.0.1", "Datacenter": "dc1"}}]"#,
+                    path.trim_start_matches("/v1/catalog/service/")
+                ),
+            };
+
+            future::ok(Response::new(Bytes::from(body)))
+        });
+
+        client.all_service_instances()
+    }));
+
+    let inventory = response.unwrap();
+    let mut names = inventory.instances.keys().cloned().collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, vec!["db", "web"]);
+    assert_eq!(inventory.instances["web"][0].service_name, "web");
+    assert_eq!(inventory.errors.len(), 1);
+    match &inventory.errors[0] {
+        (name, Error::ConsulServer(body)) => {
+            assert_eq!(name, "broken");
+            assert_eq!(body, "boom");
+        }
+        res => panic!("expected a server error, got {:?}", res),
+    }
+}
+
+#[test]
+fn monitor_worker_panic() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let inner = service_fn(|_: Request<Bytes>| -> MockFuture { panic!("inner service bug") });
+        let (mut client, monitor) =
+            Consul::new_monitored(inner, 10, "http".into(), "127.0.0.1:8500".into()).unwrap();
+
+        client
+            .get_keys("tower-consul/test-monitor")
+            .then(move |res| monitor.map(move |exit| (res.is_err(), exit)))
+    }));
+
+    assert_eq!(
+        response,
+        Ok((
+            true,
+            WorkerExit::Panicked(Some("inner service bug".to_string()))
+        ))
+    );
+}
+
+#[test]
+fn discover_cached_hit() {
+    let mut rt = Runtime::new().unwrap();
+
+    let (addrs, meta) = rt
+        .block_on(future::lazy(|| {
+            let mut client = mock(|req: Request<Bytes>| {
+                assert_eq!(req.uri().query(), Some("passing&cached"));
+                assert_eq!(req.headers()["Cache-Control"], "max-age=30");
+
+                let body = r#"[{"Node": {"Node": "node-a", "Address": "10.0.0.1"},
+                               "Service": {"ID": "web-1", "Service": "web", "Port": 8080},
+                               "Checks": []}]"#;
+                let response = Response::builder()
+                    .header("X-Cache", "HIT")
+                    .header("Age", "12")
+                    .body(Bytes::from(body))
+                    .unwrap();
+
+                future::ok(response)
+            });
+
+            client.discover_cached("web", Duration::from_millis(30_500))
+        }))
+        .unwrap();
+
+    assert_eq!(addrs, vec!["10.0.0.1:8080".parse::<SocketAddr>().unwrap()]);
+    assert_eq!(meta.cache_hit, Some(true));
+    assert_eq!(meta.cache_age, Some(Duration::from_secs(12)));
+}
+
+#[test]
+fn session_ttl_remaining() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let client = mock(|_: Request<Bytes>| {
+            let body = r#"[{"ID": "s1", "Node": "node-a", "TTL": "30s",
+                           "CreateTime": "2019-04-10T16:30:00Z",
+                           "CreateIndex": 1, "ModifyIndex": 1}]"#;
+            future::ok(Response::new(Bytes::from(body)))
+        });
+
+        // 2019-04-10T16:30:10Z
+        let now = std::time::UNIX_EPOCH + Duration::from_secs(1_554_913_810);
+        let mut client = client.with_clock(FixedClock(now));
+        let mut renewer = client.clone();
+        let mut after = client.clone();
+
+        client.session_ttl_remaining("s1").and_then(move |before| {
+            renewer
+                .renew_session("s1")
+                .and_then(move |_| after.session_ttl_remaining("s1"))
+                .map(move |after| (before, after))
+        })
+    }));
+
+    let (before, after) = response.unwrap();
+    assert_eq!(before, Some(Duration::from_secs(20)));
+    assert_eq!(after, Some(Duration::from_secs(30)));
+}
+
+#[test]
+fn session_ttl_remaining_after_create() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let client = mock(|req: Request<Bytes>| {
+            let body = match req.uri().path() {
+                "/v1/session/create" => r#"{"ID": "s1"}"#,
+                // Consul does not return a CreateTime.
+                _ => {
+                    r#"[{"ID": "s1", "Node": "node-a", "TTL": "30s",
+                         "CreateIndex": 1, "ModifyIndex": 1}]"#
+                }
+            };
+            future::ok(Response::new(Bytes::from(body)))
+        });
+
+        let now = std::time::UNIX_EPOCH + Duration::from_secs(1_554_913_810);
+        let mut client = client.with_clock(FixedClock(now));
+        let mut reader = client.clone();
+
+        let session = SessionRequest {
+            ttl: Some(Duration::from_secs(30).into()),
+            ..Default::default()
+        };
+        client
+            .create_session(&session)
+            .and_then(move |id| reader.session_ttl_remaining(&id))
+    }));
+
+    assert_eq!(response.unwrap(), Some(Duration::from_secs(30)));
+}
+
+#[test]
+fn holds_lock() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let client = mock(|req: Request<Bytes>| {
+            let body = match req.uri().path() {
+                "/v1/kv/service/leader" => {
+                    r#"[{"CreateIndex": 1, "ModifyIndex": 2, "LockIndex": 1,
+                         "Key": "service/leader", "Flags": 0, "Value": null,
+                         "Session": "s1"}]"#
+                }
+                _ => {
+                    let response = Response::builder()
+                        .status(StatusCode::NOT_FOUND)
+                        .body(Bytes::new())
+                        .unwrap();
+                    return future::ok(response);
+                }
+            };
+
+            future::ok(Response::new(Bytes::from(body)))
+        });
+
+        let mut ours = client.clone();
+        let mut theirs = client.clone();
+        let mut missing = client.clone();
+
+        ours.holds_lock("service/leader", "s1").join3(
+            theirs.holds_lock("service/leader", "s2"),
+            missing.holds_lock("service/other", "s1"),
+        )
+    }));
+
+    assert_eq!(response.unwrap(), (true, false, false));
+}
+
+#[test]
+fn abandon_inflight() {
+    let mut rt = Runtime::new().unwrap();
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+
+    let response = rt.block_on(future::lazy(move || {
+        let mut client = mock(move |req: Request<Bytes>| {
+            tx.lock()
+                .unwrap()
+                .send(req.uri().path().to_string())
+                .unwrap();
+            future::ok(Response::new(Bytes::from("[]")))
+        });
+
+        // The buffer worker has not run yet, so the first request is still
+        // queued when it is abandoned.
+        let queued = client.get_keys("tower-consul/old");
+        client.abandon_inflight();
+        let after = client.get_keys("tower-consul/new");
+
+        queued.then(|res| after.map(move |_| res.map(|_| ())))
+    }));
+
+    match response {
+        Ok(Err(Error::Abandoned)) => {}
+        res => panic!("expected Abandoned, got {:?}", res),
+    }
+    let paths = rx.try_iter().collect::<Vec<_>>();
+    assert_eq!(paths, vec!["/v1/kv/tower-consul/new"]);
+}
+
+#[test]
+fn prefix_index() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|req: Request<Bytes>| {
+            assert_eq!(req.uri().path(), "/v1/kv/tower-consul/");
+            assert_eq!(req.uri().query(), Some("keys"));
+
+            let response = Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .header("X-Consul-Index", "42")
+                .body(Bytes::new())
+                .unwrap();
+            future::ok(response)
+        });
+
+        client.prefix_index("tower-consul/")
+    }));
+
+    assert_eq!(response.unwrap(), 42);
+}
+
+#[test]
+fn discover_by_dns_name() {
+    let mut rt = Runtime::new().unwrap();
+
+    let names = vec![
+        ("web.service.consul", "passing"),
+        ("v1.web.service.dc2.consul.", "dc=dc2&tag=v1&passing"),
+        ("_web._tcp.service.consul", "passing"),
+        ("_web._v1.service.dc2.example", "dc=dc2&tag=v1&passing"),
+    ];
+
+    for (name, expected) in names {
+        let query = rt
+            .block_on(future::lazy(move || {
+                let (mut client, rx) = capture_query();
+                client
+                    .discover_by_dns_name(name)
+                    .map(move |_| rx.recv().unwrap())
+            }))
+            .unwrap();
+
+        assert_eq!(query, expected, "{}", name);
+    }
+
+    for name in &["web.node.consul", "web.service", "a.b.web.service.consul"] {
+        let response = rt.block_on(future::lazy(move || {
+            let (mut client, _rx) = capture_query();
+            client.discover_by_dns_name(name)
+        }));
+
+        match response {
+            Err(Error::InvalidArgument(msg)) => assert!(msg.contains(name), "{}", msg),
+            res => panic!("expected InvalidArgument for {}, got {:?}", name, res),
+        }
+    }
+}
+
+#[test]
+fn no_cluster_leader() {
+    let mut rt = Runtime::new().unwrap();
+
+    let response = rt.block_on(future::lazy(|| {
+        let mut client = mock(|_: Request<Bytes>| {
+            let response = Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .body(Bytes::from("No cluster leader"))
+                .unwrap();
+            future::ok(response)
+        });
+
+        client.get_keys("tower-consul/test-leader")
+    }));
+
+    match response {
+        Err(Error::NoClusterLeader) => {}
+        res => panic!("expected NoClusterLeader, got {:?}", res),
+    }
+}
+
+#[test]
+fn warm_up_without_leader() {
+    let mut rt = Runtime::new().unwrap();
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+
+    let response = rt.block_on(future::lazy(move || {
+        let mut client = mock(move |req: Request<Bytes>| {
+            tx.lock()
+                .unwrap()
+                .send(req.uri().path().to_string())
+                .unwrap();
+            let response = Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Bytes::from("No cluster leader"))
+                .unwrap();
+            future::ok(response)
+        });
+
+        client.warm_up()
+    }));
+
+    response.unwrap();
+    assert_eq!(rx.recv().unwrap(), "/v1/status/leader");
+}
+
+#[test]
+fn register_default_tags() {
+    let mut rt = Runtime::new().unwrap();
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+
+    rt.block_on(future::lazy(move || {
+        let client = mock(move |req: Request<Bytes>| {
+            tx.lock().unwrap().send(req.into_body()).unwrap();
+            future::ok(Response::new(Bytes::new()))
+        });
+        let mut client = client.with_default_tags(vec!["env:prod".into(), "region:us-east".into()]);
+
+        let registration = AgentServiceRegistration {
+            name: "web".into(),
+            tags: vec!["v1".into(), "env:prod".into()],
+            ..Default::default()
+        };
+
+        client.register_service(&registration)
+    }))
+    .unwrap();
+
+    let body = rx.recv().unwrap();
+    let registration: serde_json::Value = serde_json::from_slice(&body[..]).unwrap();
+    assert_eq!(
+        registration["Tags"],
+        serde_json::json!(["v1", "env:prod", "region:us-east"])
+    );
+}
@@ -0,0 +1,712 @@
+use bytes::Bytes;
+use futures::{stream, Future, Stream};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::time::Duration;
+use tower_consul::{
+    log_entries, service_id, AclToken, AclTokenPolicy, AgentCheck, AgentConfig, AgentSelf,
+    AgentServiceCheck, AgentServiceRegistration, AutopilotConfig, CatalogVerb, CheckTxnOp,
+    ConsulDuration, ConsulService, Error, HealthCheck, HealthNode, HealthService,
+    HealthServiceInstance, KVValue, LogEntry, LogLevel, Member, MemberStatus, NodeTxnOp,
+    RaftConfiguration, RaftServer, ServiceKind, ServiceTxnOp, SessionEntry, SessionRequest,
+    TaggedAddress, TxnCheck, TxnCheckDefinition, TxnError, TxnNode, TxnOp, TxnResponse, TxnResult,
+    TxnService, Weights,
+};
+
+#[test]
+fn service_id_sanitized() {
+    assert_eq!(service_id("web", "10.0.0.1", 8080), "web-10-0-0-1-8080");
+    assert_eq!(service_id("web api", "::1", 80), "web-api---1-80");
+}
+
+#[test]
+fn health_service_weight() {
+    let body = r#"{
+        "Node": {"Node": "node-a", "Address": "10.0.0.1"},
+        "Service": {
+            "ID": "web-1",
+            "Service": "web",
+            "Port": 8080,
+            "Weights": {"Passing": 10, "Warning": 3}
+        },
+        "Checks": [
+            {"Node": "node-a", "CheckID": "serfHealth", "Name": "Serf", "Status": "passing"},
+            {"Node": "node-a", "CheckID": "service:web-1", "Name": "web", "Status": "warning"}
+        ]
+    }"#;
+
+    let mut instance: HealthService = serde_json::from_str(body).unwrap();
+    assert_eq!(instance.weight(), 3);
+
+    instance.checks[1].status = "passing".into();
+    assert_eq!(instance.weight(), 10);
+
+    instance.checks[0].status = "critical".into();
+    assert_eq!(instance.weight(), 0);
+}
+
+#[test]
+fn health_service_node_check_critical() {
+    let body = r#"{
+        "Node": {"Node": "node-a", "Address": "10.0.0.1"},
+        "Service": {"ID": "web-1", "Service": "web", "Port": 8080},
+        "Checks": [
+            {"Node": "node-a", "CheckID": "serfHealth", "Name": "Serf Health Status",
+             "Status": "critical", "ServiceID": "", "ServiceName": ""},
+            {"Node": "node-a", "CheckID": "service:web-1", "Name": "web",
+             "Status": "passing", "ServiceID": "web-1", "ServiceName": "web"}
+        ]
+    }"#;
+
+    let mut instance: HealthService = serde_json::from_str(body).unwrap();
+    assert_eq!(instance.checks.len(), 2);
+    assert!(!instance.is_healthy());
+
+    instance.checks[0].status = "passing".into();
+    assert!(instance.is_healthy());
+}
+
+#[test]
+fn consul_service_weight() {
+    let body = r#"{
+        "ServiceKind": "",
+        "ID": "a",
+        "ServiceID": "web-1",
+        "ServiceName": "web",
+        "ServiceTags": [],
+        "ServiceMeta": {},
+        "ServiceWeights": {"Passing": 5, "Warning": 1},
+        "Node": "node-a",
+        "Address": "10.0.0.1",
+        "Datacenter": "dc1"
+    }"#;
+
+    let service: ConsulService = serde_json::from_str(body).unwrap();
+    assert_eq!(service.weight(), 5);
+    assert_eq!(service.service_kind(), ServiceKind::Typical);
+
+    let body = serde_json::to_value(&service).unwrap();
+    assert_eq!(body["ServiceKind"], "");
+}
+
+#[test]
+fn connect_proxy_service() {
+    let body = r#"[{
+        "ID": "40e4a748-2192-161a-0510-9bf59fe950b5",
+        "Node": "node-a",
+        "Address": "10.0.0.1",
+        "Datacenter": "dc1",
+        "ServiceKind": "connect-proxy",
+        "ServiceID": "web-sidecar-proxy",
+        "ServiceName": "web-sidecar-proxy",
+        "ServiceTags": [],
+        "ServiceAddress": "10.0.0.5",
+        "ServicePort": 21000,
+        "ServiceMeta": {},
+        "ServiceWeights": {"Passing": 1, "Warning": 1},
+        "ServiceProxy": {
+            "DestinationServiceName": "web",
+            "DestinationServiceID": "web",
+            "LocalServiceAddress": "127.0.0.1",
+            "LocalServicePort": 8080,
+            "Upstreams": []
+        },
+        "ServiceConnect": {}
+    }]"#;
+
+    let services: Vec<ConsulService> = serde_json::from_str(body).unwrap();
+    let proxy = &services[0];
+    assert_eq!(proxy.kind.as_deref(), Some("connect-proxy"));
+    assert_eq!(proxy.service_kind(), ServiceKind::ConnectProxy);
+    assert_eq!(proxy.service_address, "10.0.0.5");
+    assert_eq!(proxy.service_port, 21000);
+
+    let dest = proxy.proxy.as_ref().unwrap();
+    assert_eq!(dest.destination_service_name, "web");
+    assert_eq!(dest.local_service_port, 8080);
+}
+
+#[test]
+fn session_lock_delay() {
+    let mut session = SessionRequest {
+        name: Some("leader".into()),
+        lock_delay: Some(Duration::from_secs(15).into()),
+        ..SessionRequest::default()
+    };
+
+    let body = serde_json::to_value(&session).unwrap();
+    assert_eq!(body["LockDelay"], "15s");
+
+    session.lock_delay = Some(Duration::from_millis(1500).into());
+    let body = serde_json::to_value(&session).unwrap();
+    assert_eq!(body["LockDelay"], "1.5s");
+
+    session.lock_delay = None;
+    let body = serde_json::to_value(&session).unwrap();
+    assert!(body.get("LockDelay").is_none());
+}
+
+#[test]
+fn session_explicit_node() {
+    let mut session = SessionRequest {
+        node: Some("external-db".into()),
+        behavior: Some("delete".into()),
+        ttl: Some(Duration::from_secs(30).into()),
+        node_checks: Some(Vec::new()),
+        ..SessionRequest::default()
+    };
+
+    let body = serde_json::to_value(&session).unwrap();
+    assert_eq!(body["Node"], "external-db");
+    assert_eq!(body["Behavior"], "delete");
+    assert_eq!(body["TTL"], "30s");
+    assert_eq!(body["NodeChecks"], serde_json::json!([]));
+    assert!(body.get("Checks").is_none());
+
+    session.node = None;
+    session.node_checks = None;
+    let body = serde_json::to_value(&session).unwrap();
+    assert!(body.get("Node").is_none());
+    assert!(body.get("NodeChecks").is_none());
+}
+
+#[test]
+fn consul_duration_round_trip() {
+    let cases = [
+        ("0s", Duration::from_secs(0)),
+        ("30s", Duration::from_secs(30)),
+        ("1m30s", Duration::from_secs(90)),
+        ("1h0m0s", Duration::from_secs(3600)),
+        ("2h5m7.25s", Duration::from_millis(7_507_250)),
+        ("250ms", Duration::from_millis(250)),
+        ("15us", Duration::from_micros(15)),
+        ("7ns", Duration::from_nanos(7)),
+    ];
+
+    for (text, duration) in cases.iter() {
+        let parsed: ConsulDuration = serde_json::from_value(text.to_string().into()).unwrap();
+        assert_eq!(Duration::from(parsed), *duration, "{}", text);
+
+        let body = serde_json::to_value(ConsulDuration::from(*duration)).unwrap();
+        assert_eq!(body, *text);
+    }
+
+    let parsed: ConsulDuration = "1.5h".parse().unwrap();
+    assert_eq!(parsed.0, Duration::from_secs(5400));
+    let parsed: ConsulDuration = "0".parse().unwrap();
+    assert_eq!(parsed.0, Duration::from_secs(0));
+
+    for invalid in &["", "30", "s", "1.2.3s", "-1s", "10d"] {
+        assert!(invalid.parse::<ConsulDuration>().is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn txn_catalog_ops() {
+    let ops = vec![
+        TxnOp::Node(NodeTxnOp {
+            verb: CatalogVerb::Set,
+            node: TxnNode {
+                node: "external".into(),
+                address: "10.0.0.9".into(),
+                ..TxnNode::default()
+            },
+        }),
+        TxnOp::Service(ServiceTxnOp {
+            verb: CatalogVerb::Set,
+            node: "external".into(),
+            service: TxnService {
+                id: "db-1".into(),
+                service: "db".into(),
+                port: 5432,
+                ..TxnService::default()
+            },
+        }),
+        TxnOp::Check(CheckTxnOp {
+            verb: CatalogVerb::DeleteCas,
+            check: TxnCheck {
+                node: "external".into(),
+                check_id: "db-1-tcp".into(),
+                service_id: "db-1".into(),
+                definition: TxnCheckDefinition {
+                    tcp: Some("10.0.0.9:5432".into()),
+                    interval: Some(Duration::from_secs(10).into()),
+                    ..TxnCheckDefinition::default()
+                },
+                modify_index: 12,
+                ..TxnCheck::default()
+            },
+        }),
+    ];
+
+    let body = serde_json::to_value(&ops).unwrap();
+    assert_eq!(body[0]["Node"]["Verb"], "set");
+    assert_eq!(body[0]["Node"]["Node"]["Node"], "external");
+    assert!(body[0]["Node"]["Node"].get("ID").is_none());
+    assert_eq!(body[1]["Service"]["Node"], "external");
+    assert_eq!(body[1]["Service"]["Service"]["ID"], "db-1");
+    assert_eq!(body[1]["Service"]["Service"]["Port"], 5432);
+    assert_eq!(body[2]["Check"]["Verb"], "delete-cas");
+    assert_eq!(body[2]["Check"]["Check"]["CheckID"], "db-1-tcp");
+    assert_eq!(body[2]["Check"]["Check"]["ModifyIndex"], 12);
+    assert_eq!(body[2]["Check"]["Check"]["Definition"]["Interval"], "10s");
+
+    let body = r#"{
+        "Results": [
+            {"Node": {"ID": "", "Node": "external", "Address": "10.0.0.9", "ModifyIndex": 20}},
+            {"Service": {"ID": "db-1", "Service": "db", "Tags": null, "Port": 5432}}
+        ],
+        "Errors": null
+    }"#;
+
+    let response: TxnResponse = serde_json::from_str(body).unwrap();
+    assert!(response.is_success());
+    match &response.results[0] {
+        TxnResult::Node(node) => assert_eq!(node.modify_index, 20),
+        res => panic!("expected a node, got {:?}", res),
+    }
+    match &response.results[1] {
+        TxnResult::Service(service) => assert_eq!(service.port, 5432),
+        res => panic!("expected a service, got {:?}", res),
+    }
+}
+
+#[test]
+fn service_kind() {
+    let kinds: Vec<ServiceKind> =
+        serde_json::from_str(r#"["", "connect-proxy", "mesh-gateway", "api-gateway", null]"#)
+            .unwrap();
+
+    assert_eq!(
+        kinds,
+        vec![
+            ServiceKind::Typical,
+            ServiceKind::ConnectProxy,
+            ServiceKind::MeshGateway,
+            ServiceKind::Unknown("api-gateway".into()),
+            ServiceKind::Typical,
+        ]
+    );
+
+    let body = serde_json::to_value(&kinds[3]).unwrap();
+    assert_eq!(body, "api-gateway");
+    let body = serde_json::to_value(&kinds[0]).unwrap();
+    assert_eq!(body, "");
+}
+
+#[test]
+fn log_entries_across_chunks() {
+    let chunks = vec![
+        Bytes::from(r#"{"@level":"info","@message":"agent started","@timestamp":"2019-04-"#),
+        Bytes::from("10T16:30:59.123Z\"}\n\n2019/04/10 16:31:00 [WARN] agent: plain"),
+        Bytes::from(" text\n{\"@level\":\"error\",\"@message\":\"no newline\"}"),
+    ];
+
+    let entries = log_entries(stream::iter_ok::<_, ()>(chunks))
+        .collect()
+        .wait()
+        .unwrap();
+
+    assert_eq!(
+        entries,
+        vec![
+            LogEntry {
+                level: Some(LogLevel::Info),
+                timestamp: Some("2019-04-10T16:30:59.123Z".into()),
+                message: "agent started".into(),
+            },
+            LogEntry {
+                level: None,
+                timestamp: None,
+                message: "2019/04/10 16:31:00 [WARN] agent: plain text".into(),
+            },
+            LogEntry {
+                level: Some(LogLevel::Error),
+                timestamp: None,
+                message: "no newline".into(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn invalid_base64_names_key() {
+    let body = r#"{"CreateIndex": 1, "ModifyIndex": 1, "LockIndex": 0,
+        "Key": "tower-consul/broken", "Flags": 0, "Value": "not base64!"}"#;
+    let value: KVValue = serde_json::from_str(body).unwrap();
+
+    let err = value.decoded_value().unwrap_err();
+    assert!(err.to_string().contains("tower-consul/broken"), "{}", err);
+    match err {
+        Error::Base64 { key, .. } => assert_eq!(key, "tower-consul/broken"),
+        err => panic!("expected Base64, got {:?}", err),
+    }
+}
+
+#[test]
+fn check_constructors() {
+    let body = serde_json::to_value(AgentServiceCheck::http(
+        "http://10.0.0.1:8080/health",
+        Duration::from_secs(10),
+    ))
+    .unwrap();
+    assert_eq!(
+        body,
+        serde_json::json!({"HTTP": "http://10.0.0.1:8080/health", "Interval": "10s"})
+    );
+
+    let body = serde_json::to_value(AgentServiceCheck::tcp(
+        "10.0.0.1:5432",
+        Duration::from_secs(5),
+    ))
+    .unwrap();
+    assert_eq!(
+        body,
+        serde_json::json!({"TCP": "10.0.0.1:5432", "Interval": "5s"})
+    );
+
+    let body = serde_json::to_value(AgentServiceCheck::grpc(
+        "10.0.0.1:9090",
+        Duration::from_secs(5),
+    ))
+    .unwrap();
+    assert_eq!(
+        body,
+        serde_json::json!({"GRPC": "10.0.0.1:9090", "Interval": "5s"})
+    );
+
+    let body = serde_json::to_value(AgentServiceCheck::ttl(Duration::from_secs(30))).unwrap();
+    assert_eq!(body, serde_json::json!({"TTL": "30s"}));
+}
+
+#[test]
+fn kv_value_unknown_fields() {
+    let body = r#"{"CreateIndex": 1, "ModifyIndex": 2, "LockIndex": 0,
+        "Key": "tower-consul/key", "Flags": 0, "Value": "aGVsbG8=",
+        "Session": null, "Namespace": "team-a", "Partition": "default"}"#;
+    let value: KVValue = serde_json::from_str(body).unwrap();
+
+    assert_eq!(value.decoded_value().unwrap(), Bytes::from("hello"));
+    assert_eq!(value.extra["Namespace"], "team-a");
+    assert_eq!(value.extra["Partition"], "default");
+    assert!(!value.extra.contains_key("Key"));
+}
+
+/// Deserialize `body` after adding fields a future Consul might return.
+fn with_unknown_fields<T: DeserializeOwned>(body: Value) -> T {
+    let mut body = body;
+    let fields = body.as_object_mut().expect("body is an object");
+    fields.insert("FutureField".into(), json!({"Nested": [1, "two"]}));
+    fields.insert("FutureFlag".into(), json!(true));
+
+    serde_json::from_value(body).unwrap()
+}
+
+#[test]
+fn acl_unknown_fields() {
+    let policy: AclTokenPolicy = with_unknown_fields(json!({"ID": "p1", "Name": "read"}));
+    assert_eq!(policy.name, "read");
+
+    let token: AclToken = with_unknown_fields(json!({
+        "AccessorID": "a1",
+        "SecretID": "s1",
+        "Policies": [{"ID": "p1", "Name": "read", "Unknown": 1}],
+        "Namespace": "default",
+    }));
+    assert_eq!(token.accessor_id, "a1");
+    assert_eq!(token.policies[0].id, "p1");
+}
+
+#[test]
+fn agent_unknown_fields() {
+    let config = json!({"Datacenter": "dc1", "NodeName": "node-a", "PrimaryDatacenter": "dc1"});
+    let agent: AgentConfig = with_unknown_fields(config.clone());
+    assert_eq!(agent.node_name, "node-a");
+
+    let agent: AgentSelf = with_unknown_fields(json!({"Config": config, "Member": {}}));
+    assert_eq!(agent.config.datacenter, "dc1");
+
+    let check: AgentCheck = with_unknown_fields(json!({
+        "Node": "node-a",
+        "CheckID": "service:web",
+        "Name": "web",
+        "Status": "passing",
+        "Interval": "10s",
+    }));
+    assert_eq!(check.check_id, "service:web");
+}
+
+#[test]
+fn catalog_unknown_fields() {
+    let weights: Weights = with_unknown_fields(json!({"Passing": 3, "Warning": 1}));
+    assert_eq!(weights.passing, 3);
+
+    let service: ConsulService = with_unknown_fields(json!({
+        "ServiceKind": "",
+        "ID": "node-id",
+        "ServiceID": "web-1",
+        "ServiceName": "web",
+        "ServiceTags": [],
+        "ServiceMeta": {},
+        "Node": "node-a",
+        "Address": "10.0.0.1",
+        "Datacenter": "dc1",
+        "ServiceWeights": {"Passing": 2, "Warning": 1, "Unknown": 0},
+        "ServiceProxy": {"Mode": ""},
+    }));
+    assert_eq!(service.service_id, "web-1");
+    assert_eq!(service.weight(), 2);
+}
+
+#[test]
+fn health_unknown_fields() {
+    let node = json!({"Node": "node-a", "Address": "10.0.0.1", "Partition": "default"});
+    let instance = json!({"ID": "web-1", "Service": "web", "Port": 8080, "Proxy": {}});
+    let check = json!({
+        "Node": "node-a",
+        "CheckID": "serfHealth",
+        "Name": "Serf Health Status",
+        "Status": "passing",
+        "Type": "",
+    });
+
+    let parsed: HealthNode = with_unknown_fields(node.clone());
+    assert_eq!(parsed.node, "node-a");
+    let parsed: HealthServiceInstance = with_unknown_fields(instance.clone());
+    assert_eq!(parsed.port, 8080);
+    let parsed: HealthCheck = with_unknown_fields(check.clone());
+    assert_eq!(parsed.check_id, "serfHealth");
+
+    let service: HealthService = with_unknown_fields(json!({
+        "Node": node,
+        "Service": instance,
+        "Checks": [check],
+    }));
+    assert_eq!(
+        service.socket_addr(),
+        Some("10.0.0.1:8080".parse().unwrap())
+    );
+}
+
+#[test]
+fn health_service_tagged_address() {
+    let service: HealthService = serde_json::from_value(json!({
+        "Node": {
+            "Node": "node-a",
+            "Address": "10.0.0.1",
+            "TaggedAddresses": {"lan": "10.0.0.1", "wan": "198.51.100.1"},
+        },
+        "Service": {
+            "ID": "web-1",
+            "Service": "web",
+            "Address": "172.16.0.2",
+            "Port": 8080,
+            "TaggedAddresses": {"wan": {"Address": "203.0.113.5", "Port": 9090}},
+        },
+        "Checks": [],
+    }))
+    .unwrap();
+
+    let addr = |tagged| service.socket_addr_tagged(tagged).unwrap().to_string();
+    assert_eq!(addr(Some(TaggedAddress::Wan)), "203.0.113.5:9090");
+    assert_eq!(addr(Some(TaggedAddress::Lan)), "172.16.0.2:8080");
+    assert_eq!(addr(None), "172.16.0.2:8080");
+
+    // Without a service address the tagged address of the node is used.
+    let mut service = service;
+    service.service.address.clear();
+    service.service.tagged_addresses.clear();
+    let addr = |tagged| service.socket_addr_tagged(tagged).unwrap().to_string();
+    assert_eq!(addr(Some(TaggedAddress::Wan)), "198.51.100.1:8080");
+    assert_eq!(addr(None), "10.0.0.1:8080");
+}
+
+#[test]
+fn health_service_hostname_address() {
+    let service: HealthService = serde_json::from_value(json!({
+        "Node": {"Node": "node-a", "Address": "10.0.0.1"},
+        "Service": {"ID": "web-1", "Service": "web", "Address": "web.internal", "Port": 8080},
+        "Checks": [],
+    }))
+    .unwrap();
+
+    assert_eq!(service.socket_addr(), None);
+    assert_eq!(service.address(None), ("web.internal", 8080));
+}
+
+#[test]
+fn operator_unknown_fields() {
+    let server = json!({
+        "ID": "s1",
+        "Node": "node-a",
+        "Address": "10.0.0.1:8300",
+        "Leader": true,
+        "Voter": true,
+        "ProtocolVersion": "3",
+    });
+
+    let parsed: RaftServer = with_unknown_fields(server.clone());
+    assert!(parsed.leader);
+
+    let config: RaftConfiguration = with_unknown_fields(json!({"Servers": [server], "Index": 42}));
+    assert_eq!(config.index, 42);
+    assert_eq!(config.servers.len(), 1);
+}
+
+#[test]
+fn session_unknown_fields() {
+    let session: SessionEntry = with_unknown_fields(json!({
+        "ID": "s1",
+        "Node": "node-a",
+        "NodeChecks": ["serfHealth"],
+        "ServiceChecks": null,
+        "CreateIndex": 1,
+        "ModifyIndex": 2,
+    }));
+    assert_eq!(session.id, "s1");
+}
+
+#[test]
+fn txn_unknown_fields() {
+    let node: TxnNode = with_unknown_fields(json!({"Node": "node-a"}));
+    assert_eq!(node.node, "node-a");
+    let service: TxnService = with_unknown_fields(json!({"ID": "web-1"}));
+    assert_eq!(service.id, "web-1");
+    let check: TxnCheck = with_unknown_fields(json!({"Node": "node-a", "CheckID": "c1"}));
+    assert_eq!(check.check_id, "c1");
+    let error: TxnError = with_unknown_fields(json!({"OpIndex": 1, "What": "failed"}));
+    assert_eq!(error.op_index, 1);
+
+    let response: TxnResponse = with_unknown_fields(json!({
+        "Results": [{"Node": {"Node": "node-a", "Partition": "default"}}],
+        "Errors": null,
+    }));
+    assert!(response.is_success());
+    match &response.results[0] {
+        TxnResult::Node(node) => assert_eq!(node.node, "node-a"),
+        res => panic!("expected a node result, got {:?}", res),
+    }
+}
+
+#[test]
+fn autopilot_config_round_trip() {
+    let body = r#"{"CleanupDeadServers": true, "LastContactThreshold": "200ms",
+        "MaxTrailingLogs": 250, "MinQuorum": 0, "ServerStabilizationTime": "10s",
+        "RedundancyZoneTag": "", "DisableUpgradeMigration": false,
+        "UpgradeVersionTag": "", "CreateIndex": 4, "ModifyIndex": 4}"#;
+    let config: AutopilotConfig = serde_json::from_str(body).unwrap();
+
+    assert!(config.cleanup_dead_servers);
+    assert_eq!(
+        Duration::from(config.last_contact_threshold),
+        Duration::from_millis(200)
+    );
+    assert_eq!(config.max_trailing_logs, 250);
+    assert_eq!(
+        Duration::from(config.server_stabilization_time),
+        Duration::from_secs(10)
+    );
+
+    let json = serde_json::to_value(&config).unwrap();
+    assert_eq!(json["LastContactThreshold"], "200ms");
+    assert_eq!(json["ServerStabilizationTime"], "10s");
+    assert!(json.get("ModifyIndex").is_none());
+}
+
+#[test]
+fn error_to_json() {
+    let body = "x".repeat(1000);
+    let json = serde_json::to_value(Error::ConsulServer(body)).unwrap();
+    assert_eq!(json["kind"], "consul_server");
+    assert_eq!(json["body"].as_str().unwrap().len(), 256 + "...".len());
+    assert!(json["message"].as_str().unwrap().len() <= 256 + "...".len());
+
+    let failed = vec![("web-1".to_string(), Error::NotFound)];
+    let json = serde_json::to_value(Error::Registration(failed)).unwrap();
+    assert_eq!(
+        json,
+        json!({
+            "kind": "registration",
+            "message": "failed to register services: web-1",
+            "failed": [{
+                "id": "web-1",
+                "error": {"kind": "not_found", "message": "resource not found"},
+            }],
+        })
+    );
+}
+
+#[test]
+fn registration_weights() {
+    let registration = AgentServiceRegistration {
+        name: "web".into(),
+        weights: Some(Weights {
+            passing: 10,
+            warning: 1,
+        }),
+        ..Default::default()
+    };
+
+    let json = serde_json::to_value(&registration).unwrap();
+    assert_eq!(json["Weights"], json!({"Passing": 10, "Warning": 1}));
+
+    let unweighted = AgentServiceRegistration {
+        name: "web".into(),
+        ..Default::default()
+    };
+    let json = serde_json::to_value(&unweighted).unwrap();
+    assert!(json.get("Weights").is_none());
+}
+
+#[test]
+fn check_output_max_size() {
+    let check =
+        AgentServiceCheck::http("http://localhost:8080/diagnostics", Duration::from_secs(10))
+            .with_output_max_size(64 * 1024);
+
+    let json = serde_json::to_value(&check).unwrap();
+    assert_eq!(json["OutputMaxSize"], 65536);
+
+    let json = serde_json::to_value(AgentServiceCheck::ttl(Duration::from_secs(30))).unwrap();
+    assert!(json.get("OutputMaxSize").is_none());
+}
+
+#[test]
+fn check_deregister_critical_after() {
+    let check = AgentServiceCheck::tcp("10.0.0.1:8080", Duration::from_secs(10))
+        .with_deregister_critical_after(Duration::from_secs(90));
+
+    let json = serde_json::to_value(&check).unwrap();
+    assert_eq!(json["DeregisterCriticalServiceAfter"], "1m30s");
+
+    let check: AgentServiceCheck = serde_json::from_value(json).unwrap();
+    assert_eq!(
+        check.deregister_critical_service_after,
+        Some(Duration::from_secs(90).into())
+    );
+}
+
+#[test]
+fn member_status() {
+    let body = r#"[
+        {"Name": "node-a", "Addr": "10.0.0.1", "Port": 8301, "Tags": {"role": "consul"},
+         "Status": 1, "ProtocolMin": 1, "ProtocolMax": 5, "ProtocolCur": 2},
+        {"Name": "node-b", "Addr": "10.0.0.2", "Port": 8301, "Tags": null, "Status": 4},
+        {"Name": "node-c", "Addr": "10.0.0.3", "Port": 8301, "Status": 9}
+    ]"#;
+    let members: Vec<Member> = serde_json::from_str(body).unwrap();
+
+    let statuses = members.iter().map(|m| m.status).collect::<Vec<_>>();
+    assert_eq!(
+        statuses,
+        vec![
+            MemberStatus::Alive,
+            MemberStatus::Failed,
+            MemberStatus::Unknown(9)
+        ]
+    );
+    assert_eq!(members[0].protocol_cur, 2);
+    assert_eq!(members[0].tags["role"], "consul");
+    assert!(members[1].tags.is_empty());
+    assert_eq!(serde_json::to_value(members[2].status).unwrap(), 9);
+}